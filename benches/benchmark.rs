@@ -9,26 +9,25 @@ use rmesg::{
 use std::time::Duration;
 
 fn random_entry() -> Entry {
-    Entry {
-        timestamp_from_system_start: match rand::thread_rng().gen_bool(0.5) {
-            true => Some(Duration::from_secs_f64(rand::thread_rng().gen::<f64>())),
-            false => None,
-        },
-        facility: match rand::thread_rng().gen_bool(0.5) {
-            true => Some(LogFacility::Kern),
-            false => None,
-        },
-        level: match rand::thread_rng().gen_bool(0.5) {
-            true => Some(LogLevel::Info),
-            false => None,
-        },
-        sequence_num: match rand::thread_rng().gen_bool(0.5) {
-            true => Some(rand::thread_rng().gen::<usize>()),
-            false => None,
-        },
-        message: "Some very long string with no purpose. Lorem. Ipsum. Something Something."
-            .to_owned(),
-    }
+    let mut entry =
+        Entry::new("Some very long string with no purpose. Lorem. Ipsum. Something Something.");
+    entry.timestamp_from_system_start = match rand::thread_rng().gen_bool(0.5) {
+        true => Some(Duration::from_secs_f64(rand::thread_rng().gen::<f64>())),
+        false => None,
+    };
+    entry.facility = match rand::thread_rng().gen_bool(0.5) {
+        true => Some(LogFacility::Kern),
+        false => None,
+    };
+    entry.level = match rand::thread_rng().gen_bool(0.5) {
+        true => Some(LogLevel::Info),
+        false => None,
+    };
+    entry.sequence_num = match rand::thread_rng().gen_bool(0.5) {
+        true => Some(rand::thread_rng().gen::<usize>()),
+        false => None,
+    };
+    entry
 }
 
 fn display_entry() {
@@ -0,0 +1,455 @@
+use crate::entry::Entry;
+/// This module turns a sequence of entries into structured reports (kernel crashes,
+/// warnings, faults, etc.) through `Analyzer`s.
+///
+/// An analyzer is fed one entry at a time, and hands back the reports those entries
+/// complete. Many reports span several entries, so an analyzer may hold on to a report
+/// until an entry that doesn't belong to it comes along (or until `finish`).
+///
+/// Analyzers work on any sequence of entries: a vector read single-shot (`analyze`),
+/// or any iterator or stream of them (`AnalyzerIter` and `AnalyzerStream`), including
+/// those returned by `logs_iter`/`logs_stream`.
+///
+#[cfg(any(feature = "sync", feature = "async"))]
+use crate::error::RMesgError;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+#[cfg(feature = "async")]
+use core::pin::Pin;
+#[cfg(feature = "async")]
+use futures::stream::Stream;
+#[cfg(feature = "async")]
+use futures::task::{Context, Poll};
+#[cfg(feature = "async")]
+use pin_project::pin_project;
+#[cfg(any(feature = "sync", feature = "async"))]
+use std::collections::VecDeque;
+
+lazy_static! {
+    static ref RE_FRAME: Regex = Regex::new(
+        r"(?x)^
+        (?:\[<[[:xdigit:]]+>\][[:space:]]*)?
+        (?P<unreliable>\?[[:space:]]+)?
+        (?P<function>[^[:space:]+]+)\+0x(?P<offset>[[:xdigit:]]+)/0x(?P<size>[[:xdigit:]]+)
+        (?:[[:space:]]+\[(?P<module>[^\]]+)\])?
+        $"
+    )
+    .unwrap();
+    static ref RE_TASK: Regex = Regex::new(
+        r"(?x)^
+        CPU:[[:space:]]+(?P<cpu>[[:digit:]]+)
+        (?:[[:space:]]+UID:[[:space:]]+[[:digit:]]+)?
+        [[:space:]]+PID:[[:space:]]+(?P<pid>[[:digit:]]+)
+        [[:space:]]+Comm:[[:space:]]+(?P<comm>.+?)
+        [[:space:]]+(?:Not[[:space:]]tainted|Tainted:[[:space:]]+(?P<taint>[A-Z]+(?:[[:space:]]+[A-Z]+)*))
+        [[:space:]]+(?P<release>[^[:space:]]+)"
+    )
+    .unwrap();
+    static ref RE_FAULTING_FRAME: Regex =
+        Regex::new(r"^(?:RIP: [[:xdigit:]]{4}:|EIP: |pc : |PC is at )(?P<frame>.+)$").unwrap();
+    static ref RE_PANIC: Regex = Regex::new(r"^Kernel panic - not syncing: (?P<reason>.*)$").unwrap();
+    // registers, and the other hex-valued fields printed among the frames (e.g. "RIP: 0033:...", "x29: ...")
+    static ref RE_FIELD: Regex = Regex::new(r"^[[:alnum:]]{1,6}[[:space:]]*:[[:space:]]+[[:xdigit:]]").unwrap();
+    // the trap that led to a report, e.g. "invalid opcode: 0000 [#1] SMP" after "kernel BUG at"
+    static ref RE_TRAP: Regex =
+        Regex::new(r"^[[:alpha:][:space:]]+: [[:xdigit:]]{4} \[#[[:digit:]]+\]").unwrap();
+    // arm64's decoded syndrome fields, e.g. "ESR = 0x96000006", "EC = 0x25: DABT (current EL), IL = 32 bits"
+    static ref RE_SYNDROME_FIELD: Regex = Regex::new(r"^[[:alnum:]]{1,6} = 0x[[:xdigit:]]").unwrap();
+    // the context markers interleaved with frames, e.g. "<TASK>", "</IRQ>"
+    static ref RE_TRACE_MARKER: Regex = Regex::new(r"^</?[A-Z]+>$").unwrap();
+}
+
+/// What's printed between a report's header and its call trace, besides frames, registers
+/// and the task, e.g. the page fault details of an oops, or the hint of a hung task
+const REPORT_LINE_PREFIXES: [&str; 24] = [
+    "Call Trace:",
+    "Call trace:",
+    "Hardware name:",
+    "Modules linked in:",
+    "Workqueue:",
+    "Code:",
+    "Stack:",
+    "Kernel Offset:",
+    "---[ end ",
+    "#PF:",
+    "PGD ",
+    "Mem abort info:",
+    "Data abort info:",
+    "user pgtable:",
+    "Internal error:",
+    "Not tainted",
+    "Tainted:",
+    "task:",
+    "\"echo 0 > /proc/sys/kernel/hung_task_timeout_secs\"",
+    "rcu:",
+    "(t=",
+    "(detected by",
+    "Sending NMI from CPU",
+    "NMI backtrace for cpu",
+];
+
+/// No report is that long; a report is closed after so many entries regardless,
+/// so that a missed end doesn't swallow the rest of the log.
+const MAX_REPORT_ENTRIES: usize = 500;
+
+/// Recognizes reports within a sequence of entries.
+pub trait Analyzer {
+    type Report;
+
+    /// Feeds the next entry, returning the reports it completed (most often none).
+    fn push(&mut self, entry: &Entry) -> Vec<Self::Report>;
+
+    /// Ends the sequence, returning the reports still incomplete.
+    fn finish(&mut self) -> Vec<Self::Report>;
+}
+
+/// Runs an analyzer over a vector of entries (e.g. read through `log_entries`).
+pub fn analyze<A: Analyzer>(mut analyzer: A, entries: &[Entry]) -> Vec<A::Report> {
+    let mut reports: Vec<A::Report> = entries
+        .iter()
+        .flat_map(|entry| analyzer.push(entry))
+        .collect();
+    reports.append(&mut analyzer.finish());
+    reports
+}
+
+/// A function within a call trace (or the faulting instruction), as the kernel
+/// symbolizes it: `function+0x12/0x40 [module]`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Frame {
+    pub function: String,
+    /// The offset of the instruction within the function
+    pub offset: u64,
+    /// The size of the function
+    pub size: u64,
+    /// The module the function belongs to (`None` when built into the kernel)
+    pub module: Option<String>,
+    /// Whether the unwinder is sure this frame is part of the trace (it prints "?" when not)
+    pub reliable: bool,
+}
+
+impl Frame {
+    /// Parses a frame, ignoring surrounding whitespace and the raw address older
+    /// kernels print before it (`[<ffffffff81234567>] `).
+    pub fn parse(text: &str) -> Option<Frame> {
+        let parts = RE_FRAME.captures(text.trim())?;
+        Some(Frame {
+            function: parts["function"].to_owned(),
+            offset: u64::from_str_radix(&parts["offset"], 16).ok()?,
+            size: u64::from_str_radix(&parts["size"], 16).ok()?,
+            module: parts.name("module").map(|m| m.as_str().to_owned()),
+            reliable: parts.name("unreliable").is_none(),
+        })
+    }
+}
+
+impl Display for Frame {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        if !self.reliable {
+            write!(f, "? ")?;
+        }
+        write!(f, "{}+0x{:x}/0x{:x}", self.function, self.offset, self.size)?;
+        if let Some(module) = &self.module {
+            write!(f, " [{}]", module)?;
+        }
+        Ok(())
+    }
+}
+
+/// The task a report was printed for:
+/// "CPU: 2 PID: 1234 Comm: insmod Tainted: G           O      6.1.0-13-amd64 #1"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskInfo {
+    pub cpu: u32,
+    pub pid: u32,
+    /// The command (task name)
+    pub comm: String,
+    /// The taint flags without padding (e.g. "GO" when printed "G           O"), `None` when not tainted
+    pub taint: Option<String>,
+    pub kernel_release: String,
+}
+
+impl TaskInfo {
+    pub fn parse(line: &str) -> Option<TaskInfo> {
+        let parts = RE_TASK.captures(line.trim())?;
+        Some(TaskInfo {
+            cpu: parts["cpu"].parse().ok()?,
+            pid: parts["pid"].parse().ok()?,
+            comm: parts["comm"].to_owned(),
+            taint: parts
+                .name("taint")
+                .map(|taint| taint.as_str().split_whitespace().collect()),
+            kernel_release: parts["release"].to_owned(),
+        })
+    }
+}
+
+/// Whether a line is the "Kernel panic - not syncing: ..." line, and the reason given
+pub(crate) fn panic_reason(line: &str) -> Option<&str> {
+    RE_PANIC
+        .captures(line)
+        .and_then(|parts| parts.name("reason"))
+        .map(|reason| reason.as_str())
+}
+
+/// The body kernel reports (crashes, warnings, stalls) share: the task they were printed
+/// for, the faulting frame, the modules loaded, the call trace and the end marker.
+///
+/// Lines are added one at a time (already trimmed) once an analyzer recognized the start
+/// of a report. A report continues until its end marker, or until a line that can't be part
+/// of it comes along (before the call trace, only the lines reports are made of can be).
+/// Since a "Kernel panic" line may follow the end marker, that's left for the analyzer to
+/// decide (see `has_ended`).
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct TraceReport {
+    pub entries: Vec<Entry>,
+    pub task: Option<TaskInfo>,
+    pub hardware: Option<String>,
+    pub faulting_frame: Option<Frame>,
+    pub modules: Vec<String>,
+    pub call_trace: Vec<Frame>,
+    pub panic: Option<String>,
+    pub complete: bool,
+    in_call_trace: bool,
+    ended: bool,
+}
+
+impl TraceReport {
+    /// A report whose first entries came before its header (e.g. "cut here")
+    pub fn with_entries(entries: Vec<Entry>) -> Self {
+        Self {
+            entries,
+            ..Self::default()
+        }
+    }
+
+    /// Whether the end marker was seen (and no panic followed it)
+    pub fn has_ended(&self) -> bool {
+        self.ended
+    }
+
+    /// Whether anything past the report's header was seen
+    pub fn has_body(&self) -> bool {
+        self.task.is_some() || self.in_call_trace || self.panic.is_some()
+    }
+
+    /// Whether a line that starts no other report belongs to this one.
+    /// Only report-shaped lines do, so that a header no call trace follows (e.g.
+    /// "BUG: workqueue lockup") ends with the first line that isn't.
+    pub fn continues_with(&self, line: &str) -> bool {
+        if self.ended {
+            false
+        } else if self.in_call_trace {
+            Frame::parse(line).is_some()
+                || RE_TRACE_MARKER.is_match(line)
+                || RE_FIELD.is_match(line)
+                || line.starts_with("Modules linked in:")
+                || line.starts_with("Kernel Offset:")
+                || line.starts_with("---[ end ")
+        } else {
+            Frame::parse(line).is_some()
+                || RE_TRACE_MARKER.is_match(line)
+                || RE_FIELD.is_match(line)
+                || RE_SYNDROME_FIELD.is_match(line)
+                || RE_TRAP.is_match(line)
+                || RE_FAULTING_FRAME.is_match(line)
+                || TaskInfo::parse(line).is_some()
+                || panic_reason(line).is_some()
+                || REPORT_LINE_PREFIXES
+                    .iter()
+                    .any(|prefix| line.starts_with(prefix))
+        }
+    }
+
+    /// Adds a line, and returns whether the report is over.
+    pub fn add(&mut self, line: &str, entry: &Entry) -> bool {
+        self.entries.push(entry.clone());
+
+        if let Some(reason) = panic_reason(line) {
+            self.panic = Some(reason.to_owned());
+            self.ended = false;
+        } else if line.starts_with("---[ end Kernel panic") {
+            // nothing gets logged after a panic
+            self.complete = true;
+            return true;
+        } else if line.starts_with("---[ end trace") {
+            self.complete = true;
+            self.ended = true;
+        } else if line.starts_with("Call Trace:") || line.starts_with("Call trace:") {
+            self.in_call_trace = true;
+        } else if let Some(task) = TaskInfo::parse(line) {
+            self.task = Some(task);
+        } else if let Some(hardware) = line.strip_prefix("Hardware name: ") {
+            self.hardware = Some(hardware.to_owned());
+        } else if let Some(modules) = line.strip_prefix("Modules linked in:") {
+            self.modules = modules
+                .split_whitespace()
+                // e.g. "[last unloaded: foo]"
+                .take_while(|module| !module.starts_with('['))
+                .map(|module| module.split('(').next().unwrap_or(module).to_owned())
+                .collect();
+        } else if let Some(frame) = self.in_call_trace.then(|| Frame::parse(line)).flatten() {
+            self.call_trace.push(frame);
+        } else if let Some(parts) = RE_FAULTING_FRAME.captures(line) {
+            if self.faulting_frame.is_none() {
+                self.faulting_frame = Frame::parse(&parts["frame"]);
+            }
+        }
+
+        self.entries.len() >= MAX_REPORT_ENTRIES
+    }
+}
+
+/// Wraps an iterator of entries into an iterator of the reports an analyzer finds
+/// in them. Errors are passed through.
+#[cfg(feature = "sync")]
+pub struct AnalyzerIter<I, A: Analyzer> {
+    inner: I,
+    analyzer: A,
+    ready: VecDeque<A::Report>,
+    finished: bool,
+}
+
+#[cfg(feature = "sync")]
+impl<I, A: Analyzer> AnalyzerIter<I, A> {
+    pub fn new(inner: I, analyzer: A) -> Self {
+        Self {
+            inner,
+            analyzer,
+            ready: VecDeque::new(),
+            finished: false,
+        }
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<I, A> Iterator for AnalyzerIter<I, A>
+where
+    I: Iterator<Item = Result<Entry, RMesgError>>,
+    A: Analyzer,
+{
+    type Item = Result<A::Report, RMesgError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(report) = self.ready.pop_front() {
+                return Some(Ok(report));
+            }
+            if self.finished {
+                return None;
+            }
+
+            match self.inner.next() {
+                Some(Ok(entry)) => self.ready.extend(self.analyzer.push(&entry)),
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    self.finished = true;
+                    self.ready.extend(self.analyzer.finish());
+                }
+            }
+        }
+    }
+}
+
+/// Wraps a stream of entries into a stream of the reports an analyzer finds
+/// in them. Errors are passed through.
+#[cfg(feature = "async")]
+#[pin_project]
+pub struct AnalyzerStream<S, A: Analyzer> {
+    #[pin]
+    inner: S,
+    analyzer: A,
+    ready: VecDeque<A::Report>,
+    finished: bool,
+}
+
+#[cfg(feature = "async")]
+impl<S, A: Analyzer> AnalyzerStream<S, A> {
+    pub fn new(inner: S, analyzer: A) -> Self {
+        Self {
+            inner,
+            analyzer,
+            ready: VecDeque::new(),
+            finished: false,
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<S, A> Stream for AnalyzerStream<S, A>
+where
+    S: Stream<Item = Result<Entry, RMesgError>>,
+    A: Analyzer,
+{
+    type Item = Result<A::Report, RMesgError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            if let Some(report) = this.ready.pop_front() {
+                return Poll::Ready(Some(Ok(report)));
+            }
+            if *this.finished {
+                return Poll::Ready(None);
+            }
+
+            match this.inner.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(entry))) => this.ready.extend(this.analyzer.push(&entry)),
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) => {
+                    *this.finished = true;
+                    this.ready.extend(this.analyzer.finish());
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/**********************************************************************************/
+// Tests! Tests! Tests!
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_frame() {
+        assert_eq!(
+            Frame::parse(" ? do_one_initcall+0x41/0x200 [crash]"),
+            Some(Frame {
+                function: "do_one_initcall".to_owned(),
+                offset: 0x41,
+                size: 0x200,
+                module: Some("crash".to_owned()),
+                reliable: false,
+            })
+        );
+
+        let frame = Frame::parse("[<ffffffff8100a1b2>] ret_from_fork+0x1f/0x30").unwrap();
+        assert!(frame.reliable && frame.module.is_none());
+        assert_eq!(frame.to_string(), "ret_from_fork+0x1f/0x30");
+
+        assert_eq!(Frame::parse("<TASK>"), None);
+        assert_eq!(Frame::parse("Call Trace:"), None);
+    }
+
+    #[test]
+    fn test_parse_task() {
+        let task = TaskInfo::parse("CPU: 0 PID: 1 Comm: swapper/0 Not tainted 5.15.0 #1").unwrap();
+        assert_eq!(
+            (task.cpu, task.pid, task.comm.as_str()),
+            (0, 1, "swapper/0")
+        );
+        assert_eq!(task.taint, None);
+
+        let task =
+            TaskInfo::parse("CPU: 3 UID: 0 PID: 78 Comm: bash Tainted: G      D W 6.12.0 #2")
+                .unwrap();
+        assert_eq!(task.taint.as_deref(), Some("GDW"));
+        assert_eq!(task.kernel_release, "6.12.0");
+    }
+}
@@ -0,0 +1,105 @@
+use crate::klogctl;
+use crate::kmsgfile;
+/// This module gathers the state of the kernel log buffer, and of the settings that
+/// govern it, into a single `BufferInfo` (e.g. to expose as node health metrics).
+///
+/// Every field is gathered independently, and is `None` when it can't be obtained
+/// (most often because the process isn't permitted to), so that one restricted source
+/// doesn't hide all the others.
+///
+use crate::paths::SystemPaths;
+use crate::permissions;
+use crate::printk::{self, ConsoleLogLevels, DevKMsgAccess};
+
+/// The state of the kernel log buffer and its settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct BufferInfo {
+    /// The size of the kernel log buffer in bytes (`SyslogActionSizeBuffer`)
+    pub buffer_size: Option<usize>,
+    /// The bytes not yet consumed through /proc/kmsg (`SyslogActionSizeUnread`, requires CAP_SYSLOG)
+    pub unread_size: Option<usize>,
+    /// The sequence number of the oldest record still in the buffer (from /dev/kmsg)
+    pub first_sequence_num: Option<u64>,
+    /// The sequence number of the newest record in the buffer (from /dev/kmsg)
+    pub last_sequence_num: Option<u64>,
+    /// The console log levels (/proc/sys/kernel/printk)
+    pub console_log_levels: Option<ConsoleLogLevels>,
+    /// Whether entries are timestamped (printk.time)
+    pub timestamps_enabled: Option<bool>,
+    /// Whether userspace may write to /dev/kmsg (printk.devkmsg)
+    pub devkmsg: Option<DevKMsgAccess>,
+    /// Whether reading the log requires CAP_SYSLOG (kernel.dmesg_restrict)
+    pub dmesg_restrict: Option<bool>,
+}
+
+impl BufferInfo {
+    /// The number of records in the buffer, when the sequence numbers are known
+    pub fn records(&self) -> Option<u64> {
+        match (self.first_sequence_num, self.last_sequence_num) {
+            (Some(first), Some(last)) if last >= first => Some(last - first + 1),
+            _ => None,
+        }
+    }
+}
+
+/// Gathers the current `BufferInfo`.
+///
+/// Note that finding the last sequence number requires reading every record from /dev/kmsg.
+pub fn buffer_info() -> BufferInfo {
+    buffer_info_in(&SystemPaths::default())
+}
+
+/// Same as `buffer_info`, with /dev/kmsg and the settings found through `paths`.
+/// The buffer sizes always come from the running kernel (through klogctl).
+pub fn buffer_info_in(paths: &SystemPaths) -> BufferInfo {
+    let (first_sequence_num, last_sequence_num) =
+        match kmsgfile::kmsg_raw(paths.dev_kmsg_override()) {
+            Ok(records) => sequence_num_range(&records),
+            Err(_) => (None, None),
+        };
+
+    BufferInfo {
+        buffer_size: klogctl::klog_buffer_size().ok(),
+        unread_size: klogctl::klog_unread_size().ok(),
+        first_sequence_num,
+        last_sequence_num,
+        console_log_levels: printk::console_log_levels_in(paths).ok(),
+        timestamps_enabled: klogctl::klog_timestamps_enabled_in(paths).ok(),
+        devkmsg: printk::printk_devkmsg_in(paths).ok(),
+        dmesg_restrict: permissions::dmesg_restrict_in(paths).ok(),
+    }
+}
+
+fn sequence_num_range(records: &str) -> (Option<u64>, Option<u64>) {
+    let mut sequence_nums = records.lines().filter_map(kmsgfile::sequence_num_of);
+    let first = sequence_nums.next();
+    let last = sequence_nums.next_back().or(first);
+    (first, last)
+}
+
+/**********************************************************************************/
+// Tests! Tests! Tests!
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sequence_num_range() {
+        let records = "6,12,100,-;first\n LINE2=continued\n6,13,200,-;second\n6,14,300,-;third\n";
+        assert_eq!(sequence_num_range(records), (Some(12), Some(14)));
+        assert_eq!(sequence_num_range("6,7,100,-;only\n"), (Some(7), Some(7)));
+        assert_eq!(sequence_num_range(""), (None, None));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_buffer_info() {
+        let info = buffer_info();
+        assert!(info.buffer_size.unwrap_or_default() > 0);
+        assert!(info.console_log_levels.is_some());
+        if let Some(records) = info.records() {
+            assert!(records > 0);
+        }
+    }
+}
@@ -0,0 +1,514 @@
+use crate::common;
+use crate::entry::{Entry, EntryParsingError, LogFacility, LogLevel};
+/// This module records streams of entries to a capture file, along with the time
+/// each entry was captured, and replays them later with their original timing
+/// (or faster). This makes it possible to reproduce a production incident against
+/// log processing in CI.
+///
+/// A capture file is line-oriented text:
+///   #rmesg-capture v2 started=1791973321000000
+///   1791973321000123 e 0 6 1 1.000000000 - first message
+///   1791973321250000 e - - - - 1791973321.250000000 a message with a wall-clock time only
+///
+/// Each record holds the capture time (microseconds since the UNIX epoch), the `e` marker,
+/// and every field of the entry: the facility and level (as numbers), the sequence number,
+/// the time since boot and the wall-clock time (as seconds.nanoseconds, since the UNIX
+/// epoch for the latter), with `-` for those it doesn't have, and finally the message
+/// (backslashes and newlines escaped). So a replayed entry is identical to the recorded one.
+///
+/// Reading a capture fails unless it starts with the header, of the same version.
+///
+/// Plain dumps of /dev/kmsg (without capture times) can still be replayed through
+/// `KMsgEntriesStream::with_options` and its `file_override` parameter.
+///
+use crate::error::RMesgError;
+use crate::mock::ScriptedSource;
+
+use num::FromPrimitive;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const CAPTURE_HEADER: &str = "#rmesg-capture v2";
+
+/// Written for a field an entry doesn't have
+const ABSENT: &str = "-";
+
+/// How fast a capture is replayed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pacing {
+    /// Entries are spaced exactly as they were captured
+    RealTime,
+
+    /// Entries are spaced as they were captured, divided by this factor (i.e. 10.0 is 10x faster)
+    Accelerated(f64),
+
+    /// Entries are emitted without any delay
+    AsFastAsPossible,
+}
+
+/// An entry, along with the time it was captured
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapturedEntry {
+    pub captured_at: SystemTime,
+    pub entry: Entry,
+}
+
+/// Writes entries to a capture file (or any other `Write`).
+pub struct CaptureWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// Creates a new writer, and writes the capture header immediately.
+    pub fn new(mut writer: W) -> Result<Self, RMesgError> {
+        writeln!(
+            writer,
+            "{} started={}",
+            CAPTURE_HEADER,
+            micros_since_epoch(SystemTime::now())?
+        )?;
+        Ok(Self { writer })
+    }
+
+    /// Records an entry as captured right now.
+    pub fn record(&mut self, entry: &Entry) -> Result<(), RMesgError> {
+        self.record_at(entry, SystemTime::now())
+    }
+
+    /// Records an entry as captured at a specific time.
+    pub fn record_at(&mut self, entry: &Entry, captured_at: SystemTime) -> Result<(), RMesgError> {
+        let wallclock_time = match entry.wallclock_time {
+            Some(time) => Some(match time.duration_since(UNIX_EPOCH) {
+                Ok(d) => d,
+                Err(e) => return Err(RMesgError::UnableToObtainElapsedTime(e)),
+            }),
+            None => None,
+        };
+
+        writeln!(
+            self.writer,
+            "{} e {} {} {} {} {} {}",
+            micros_since_epoch(captured_at)?,
+            field(entry.facility.map(|facility| facility as u8)),
+            field(entry.level.map(|level| level as u8)),
+            field(entry.sequence_num),
+            duration_field(entry.timestamp_from_system_start),
+            duration_field(wallclock_time),
+            escape(&entry.message)
+        )?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), RMesgError> {
+        Ok(self.writer.flush()?)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Creates (or truncates) a capture file at `path` and returns a writer to it.
+pub fn create_capture_file(path: &str) -> Result<CaptureWriter<fs::File>, RMesgError> {
+    CaptureWriter::new(fs::File::create(path)?)
+}
+
+/// Reads every record from a capture.
+pub fn read_capture<R: BufRead>(reader: R) -> Result<Vec<CapturedEntry>, RMesgError> {
+    let mut lines = reader.lines();
+    check_header(&lines.next().transpose()?.unwrap_or_default())?;
+
+    let mut captured = Vec::new();
+    // the header is line 1
+    for (index, line) in lines.enumerate().map(|(index, line)| (index + 1, line)) {
+        let line = line?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        captured.push(captured_entry_from_line(&line).map_err(|e| match e {
+            RMesgError::EntryParsingError { source, .. } => RMesgError::EntryParsingError {
+                line_number: Some(index + 1),
+                source,
+            },
+            e => e,
+        })?);
+    }
+
+    Ok(captured)
+}
+
+/// Fails unless `line` is the capture header (of this version)
+fn check_header(line: &str) -> Result<(), RMesgError> {
+    if line
+        .split_whitespace()
+        .take(2)
+        .eq(CAPTURE_HEADER.split_whitespace())
+    {
+        return Ok(());
+    }
+
+    let reason = match line.split_whitespace().next() {
+        Some(marker) if CAPTURE_HEADER.starts_with(marker) && marker.len() > 1 => {
+            format!(
+                "Unsupported capture version (expected '{}')",
+                CAPTURE_HEADER
+            )
+        }
+        _ => format!("Not a capture: '{}' header missing", CAPTURE_HEADER),
+    };
+    Err(RMesgError::EntryParsingError {
+        line_number: Some(1),
+        source: EntryParsingError::InvalidFragment {
+            fragment: line.to_owned(),
+            expected: CAPTURE_HEADER.to_owned(),
+            reason,
+            line: line.to_owned(),
+        },
+    })
+}
+
+/// Reads every record from the capture file at `path`.
+pub fn read_capture_file(path: &str) -> Result<Vec<CapturedEntry>, RMesgError> {
+    read_capture(BufReader::new(fs::File::open(path)?))
+}
+
+/// Builds a log source that emits the captured entries with the requested pacing.
+///
+/// The returned `ScriptedSource` can be iterated/streamed directly, or wrapped into the
+/// `EntriesIterator`/`EntriesStream` returned for the built-in backends.
+pub fn replay(captured: Vec<CapturedEntry>, pacing: Pacing) -> ScriptedSource {
+    let mut source = ScriptedSource::new();
+    let mut previous: Option<SystemTime> = None;
+
+    for record in captured {
+        if let Some(previous) = previous {
+            // captures are appended in order, but clocks may step backwards
+            let gap = record
+                .captured_at
+                .duration_since(previous)
+                .unwrap_or_default();
+            if let Some(delay) = paced(gap, pacing) {
+                source = source.delay(delay);
+            }
+        }
+
+        previous = Some(record.captured_at);
+        source = source.entry(record.entry);
+    }
+
+    source
+}
+
+/// Reads the capture file at `path` and builds a log source that replays it.
+pub fn replay_file(path: &str, pacing: Pacing) -> Result<ScriptedSource, RMesgError> {
+    Ok(replay(read_capture_file(path)?, pacing))
+}
+
+fn paced(gap: Duration, pacing: Pacing) -> Option<Duration> {
+    let delay = match pacing {
+        Pacing::RealTime => gap,
+        // a tiny factor makes for a delay too long to hold; such a replay never ends anyway
+        Pacing::Accelerated(factor) if factor > 0.0 => {
+            Duration::try_from_secs_f64(gap.as_secs_f64() / factor).unwrap_or(Duration::MAX)
+        }
+        Pacing::Accelerated(_) | Pacing::AsFastAsPossible => Duration::ZERO,
+    };
+
+    match delay.is_zero() {
+        true => None,
+        false => Some(delay),
+    }
+}
+
+fn captured_entry_from_line(line: &str) -> Result<CapturedEntry, RMesgError> {
+    let mut parts = line.splitn(3, ' ');
+    let (micros, marker, serialized) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(micros), Some(marker), Some(serialized)) => (micros, marker, serialized),
+        (Some(micros), Some(marker), None) => (micros, marker, ""),
+        _ => {
+            return Err(RMesgError::InternalError(format!(
+                "Malformed capture record: {}",
+                line
+            )))
+        }
+    };
+
+    let captured_at = UNIX_EPOCH + Duration::from_micros(common::parse_fragment(micros, line)?);
+
+    let entry = match marker {
+        "e" => entry_from_fields(serialized, line)?,
+        _ => {
+            return Err(RMesgError::InternalError(format!(
+                "Unknown capture record marker '{}': {}",
+                marker, line
+            )))
+        }
+    };
+
+    Ok(CapturedEntry { captured_at, entry })
+}
+
+/// Parses the fields of an `e` record, after the marker
+fn entry_from_fields(serialized: &str, line: &str) -> Result<Entry, RMesgError> {
+    let mut fields = serialized.splitn(6, ' ');
+    let mut next = || match fields.next() {
+        Some(field) => Ok(field),
+        None => Err(RMesgError::InternalError(format!(
+            "Malformed capture record: {}",
+            line
+        ))),
+    };
+    let (facility, level, sequence_num, timestamp, wallclock, message) =
+        (next()?, next()?, next()?, next()?, next()?, next()?);
+
+    let facility = match parse_field::<u32>(facility, line)? {
+        Some(facility) => Some(LogFacility::from_u32(facility).ok_or_else(|| {
+            EntryParsingError::InvalidFacilityLevel {
+                faclev: facility << 3,
+                line: line.to_owned(),
+            }
+        })?),
+        None => None,
+    };
+    let level = match parse_field::<u32>(level, line)? {
+        Some(level) => Some(LogLevel::from_u32(level).ok_or_else(|| {
+            EntryParsingError::InvalidFacilityLevel {
+                faclev: level,
+                line: line.to_owned(),
+            }
+        })?),
+        None => None,
+    };
+    let wallclock_time = match parse_duration_field(wallclock, line)? {
+        Some(since_epoch) => Some(UNIX_EPOCH.checked_add(since_epoch).ok_or_else(|| {
+            EntryParsingError::InvalidTimestamp {
+                reason: "wall-clock time out of range".to_owned(),
+                line: line.to_owned(),
+            }
+        })?),
+        None => None,
+    };
+
+    Ok(Entry {
+        facility,
+        level,
+        sequence_num: parse_field(sequence_num, line)?,
+        timestamp_from_system_start: parse_duration_field(timestamp, line)?,
+        wallclock_time,
+        message: unescape(message),
+    })
+}
+
+fn field<T: ToString>(value: Option<T>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => ABSENT.to_owned(),
+    }
+}
+
+fn duration_field(value: Option<Duration>) -> String {
+    match value {
+        Some(d) => format!("{}.{:09}", d.as_secs(), d.subsec_nanos()),
+        None => ABSENT.to_owned(),
+    }
+}
+
+fn parse_field<N>(field: &str, line: &str) -> Result<Option<N>, EntryParsingError>
+where
+    N: std::str::FromStr,
+    N::Err: std::fmt::Display,
+{
+    match field {
+        ABSENT => Ok(None),
+        field => common::parse_fragment(field, line).map(Some),
+    }
+}
+
+fn parse_duration_field(field: &str, line: &str) -> Result<Option<Duration>, EntryParsingError> {
+    match field.split_once('.') {
+        Some((secs, nanos)) => Ok(Some(Duration::new(
+            common::parse_fragment(secs, line)?,
+            common::parse_fragment(nanos, line)?,
+        ))),
+        None => parse_field(field, line).map(|secs| secs.map(Duration::from_secs)),
+    }
+}
+
+/// Keeps a message on its line
+fn escape(message: &str) -> String {
+    message
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn unescape(message: &str) -> String {
+    let mut unescaped = String::with_capacity(message.len());
+    let mut chars = message.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => unescaped.push('\n'),
+                Some('r') => unescaped.push('\r'),
+                Some(escaped) => unescaped.push(escaped),
+                None => unescaped.push('\\'),
+            },
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+fn micros_since_epoch(time: SystemTime) -> Result<u128, RMesgError> {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => Ok(d.as_micros()),
+        Err(e) => Err(RMesgError::UnableToObtainElapsedTime(e)),
+    }
+}
+
+/**********************************************************************************/
+// Tests! Tests! Tests!
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::kmsgfile;
+    #[cfg(feature = "async")]
+    use tokio_stream::StreamExt;
+
+    fn recorded() -> Vec<u8> {
+        let start = UNIX_EPOCH + Duration::from_secs(1_791_973_321);
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        writer
+            .record_at(
+                &kmsgfile::entry_from_line("6,1,1000000,-;first").unwrap(),
+                start,
+            )
+            .unwrap();
+        writer
+            .record_at(
+                &kmsgfile::entry_from_line("bare continuation").unwrap(),
+                start + Duration::from_millis(400),
+            )
+            .unwrap();
+        writer.into_inner()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let captured = read_capture(recorded().as_slice()).unwrap();
+        assert_eq!(captured.len(), 2);
+        assert_eq!(captured[0].entry.message, "first");
+        assert_eq!(captured[0].entry.sequence_num, Some(1));
+        assert_eq!(captured[1].entry.message, "bare continuation");
+        assert_eq!(captured[1].entry.facility, None);
+        assert_eq!(
+            captured[1]
+                .captured_at
+                .duration_since(captured[0].captured_at)
+                .unwrap(),
+            Duration::from_millis(400)
+        );
+    }
+
+    #[test]
+    fn test_lossless_round_trip() {
+        let mut entries = Vec::new();
+        for with_fields in [false, true] {
+            entries.push(Entry {
+                facility: with_fields.then_some(LogFacility::FTP),
+                level: with_fields.then_some(LogLevel::Debug),
+                sequence_num: with_fields.then_some(0),
+                timestamp_from_system_start: with_fields.then_some(Duration::new(12, 345_678_901)),
+                wallclock_time: with_fields.then_some(UNIX_EPOCH + Duration::new(1_791_973_321, 7)),
+                message: "a message ; with \\ backslash,\nnewline and\r".to_owned(),
+            });
+        }
+        // each field on its own
+        entries.push(Entry {
+            level: Some(LogLevel::Emergency),
+            ..entries[0].clone()
+        });
+        entries.push(Entry {
+            facility: Some(LogFacility::Kern),
+            message: String::new(),
+            ..entries[0].clone()
+        });
+        entries.push(Entry {
+            timestamp_from_system_start: Some(Duration::ZERO),
+            ..entries[0].clone()
+        });
+
+        let start = UNIX_EPOCH + Duration::from_secs(1_791_973_321);
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        for entry in &entries {
+            writer.record_at(entry, start).unwrap();
+        }
+
+        let replayed: Vec<Entry> = read_capture(writer.into_inner().as_slice())
+            .unwrap()
+            .into_iter()
+            .map(|captured| captured.entry)
+            .collect();
+        assert_eq!(replayed, entries);
+    }
+
+    #[test]
+    fn test_header() {
+        let record = "1791973321000123 e 0 6 1 1.000000000 - first";
+        let read = |capture: String| read_capture(capture.as_bytes());
+
+        assert_eq!(
+            read(format!("#rmesg-capture v2 started=1\n{}", record)).unwrap()[0]
+                .entry
+                .message,
+            "first"
+        );
+        assert_eq!(read("#rmesg-capture v2".to_owned()).unwrap(), vec![]);
+
+        for capture in [
+            format!("#rmesg-capture v3 started=1\n{}", record),
+            format!("#rmesg-capture\n{}", record),
+            record.to_owned(),
+            "6,1,1000000,-;a plain kmsg dump".to_owned(),
+            String::new(),
+        ] {
+            let err = read(capture.clone()).unwrap_err();
+            assert_eq!(err.kind(), crate::error::ErrorKind::Parse, "{:?}", capture);
+        }
+        assert!(format!(
+            "{}",
+            read(format!("#rmesg-capture v1\n{}", record)).unwrap_err()
+        )
+        .contains("Unsupported capture version"));
+        assert!(format!("{}", read(record.to_owned()).unwrap_err()).contains("header missing"));
+    }
+
+    #[test]
+    fn test_pacing() {
+        let gap = Duration::from_millis(400);
+        assert_eq!(paced(gap, Pacing::RealTime), Some(gap));
+        assert_eq!(
+            paced(gap, Pacing::Accelerated(4.0)),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(paced(gap, Pacing::AsFastAsPossible), None);
+        assert_eq!(paced(gap, Pacing::Accelerated(0.0)), None);
+        assert_eq!(paced(gap, Pacing::Accelerated(1e-300)), Some(Duration::MAX));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_replay() {
+        let captured = read_capture(recorded().as_slice()).unwrap();
+        let mut stream = replay(captured, Pacing::Accelerated(100.0)).into_stream_entries();
+
+        assert_eq!(stream.next().await.unwrap().unwrap().message, "first");
+        assert_eq!(
+            stream.next().await.unwrap().unwrap().message,
+            "bare continuation"
+        );
+        assert!(stream.next().await.is_none());
+    }
+}
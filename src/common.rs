@@ -1,9 +1,10 @@
-use crate::entry::{EntryParsingError, LogFacility, LogLevel};
+use crate::entry::{Entry, EntryParsingError, LogFacility, LogLevel};
+use crate::error::RMesgError;
 use num::FromPrimitive;
 use std::any::type_name;
 use std::fmt::Display;
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 const LEVEL_MASK: u32 = (1 << 3) - 1;
 
@@ -18,10 +19,10 @@ pub fn parse_favlecstr(
         LogLevel::from_u32(faclev & LEVEL_MASK),
     ) {
         (Some(facility), Some(level)) => Ok((Some(facility), Some(level))),
-        _ => Err(EntryParsingError::Generic(format!(
-            "Unable to parse {} into log facility and level. Line: {}",
-            faclev, line
-        ))),
+        _ => Err(EntryParsingError::InvalidFacilityLevel {
+            faclev,
+            line: line.to_owned(),
+        }),
     }
 }
 
@@ -51,12 +52,113 @@ where
 {
     match frag.trim().parse() {
         Ok(f) => Ok(f),
-        Err(e) => Err(EntryParsingError::Generic(format!(
-            "Unable to parse {} into a {} due to error: {}\nLine: {}",
-            frag,
-            type_name::<N>(),
-            e,
-            line,
-        ))),
+        Err(e) => Err(EntryParsingError::InvalidFragment {
+            fragment: frag.to_owned(),
+            expected: type_name::<N>().to_owned(),
+            reason: e.to_string(),
+            line: line.to_owned(),
+        }),
     }
 }
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+pub fn parse_month_abbrev(monthstr: &str, line: &str) -> Result<u32, EntryParsingError> {
+    match MONTHS.iter().position(|m| m.eq_ignore_ascii_case(monthstr)) {
+        Some(i) => Ok(i as u32 + 1),
+        None => Err(EntryParsingError::InvalidFragment {
+            fragment: monthstr.to_owned(),
+            expected: "month name".to_owned(),
+            reason: "not a three-letter month abbreviation".to_owned(),
+            line: line.to_owned(),
+        }),
+    }
+}
+
+/// Number of days since 1970-01-01 for a proleptic Gregorian calendar date.
+/// http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let mp = (i64::from(month) + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Converts a broken-down civil time (already shifted to UTC) into a SystemTime.
+/// `date` is (year, month, day) and `time` is (hour, minute, second, nanoseconds).
+pub fn system_time_from_civil(
+    date: (i64, u32, u32),
+    time: (u32, u32, u32, u32),
+    line: &str,
+) -> Result<SystemTime, EntryParsingError> {
+    let (year, month, day) = date;
+    let (hour, minute, second, nanos) = time;
+
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return Err(EntryParsingError::InvalidTimestamp {
+            reason: format!(
+                "Date/time {}-{}-{} {}:{}:{} is out of range.",
+                year, month, day, hour, minute, second
+            ),
+            line: line.to_owned(),
+        });
+    }
+
+    let secs = days_from_civil(year, month, day) * 86400
+        + i64::from(hour) * 3600
+        + i64::from(minute) * 60
+        + i64::from(second);
+
+    let since_epoch = Duration::new(secs.unsigned_abs(), 0);
+    let time = match secs >= 0 {
+        true => UNIX_EPOCH.checked_add(since_epoch),
+        false => UNIX_EPOCH.checked_sub(since_epoch),
+    };
+
+    match time.and_then(|t| t.checked_add(Duration::from_nanos(u64::from(nanos)))) {
+        Some(t) => Ok(t),
+        None => Err(EntryParsingError::InvalidTimestamp {
+            reason: format!(
+                "Date/time {}-{}-{} {}:{}:{} is not representable as a SystemTime.",
+                year, month, day, hour, minute, second
+            ),
+            line: line.to_owned(),
+        }),
+    }
+}
+
+/// Shifts a time that was expressed in a zone `utc_offset_secs` east of UTC, back to UTC.
+pub fn shift_to_utc(time: SystemTime, utc_offset_secs: i32) -> Option<SystemTime> {
+    let offset = Duration::from_secs(u64::from(utc_offset_secs.unsigned_abs()));
+    match utc_offset_secs >= 0 {
+        true => time.checked_sub(offset),
+        false => time.checked_add(offset),
+    }
+}
+
+/// Parses every line with `parse`, attaching the (1-based) line number to any parsing error.
+pub fn entries_from_numbered_lines<F>(all_lines: &str, parse: F) -> Result<Vec<Entry>, RMesgError>
+where
+    F: Fn(&str) -> Result<Entry, EntryParsingError>,
+{
+    all_lines
+        .lines()
+        .enumerate()
+        .map(|(index, line)| {
+            parse(line).map_err(|source| RMesgError::EntryParsingError {
+                line_number: Some(index + 1),
+                source,
+            })
+        })
+        .collect()
+}
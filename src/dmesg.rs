@@ -0,0 +1,353 @@
+use crate::common;
+use crate::entry::{Entry, EntryParsingError, LogFacility, LogLevel};
+/// This module parses the human-readable output of the util-linux `dmesg` utility
+/// back into structured entries. This is what usually gets pasted into tickets,
+/// so offline tooling can ingest it without access to the original host.
+///
+/// Supported variants (and their combinations):
+///   dmesg                     [    1.234567] message
+///   dmesg -T                  [Tue Oct 14 10:22:01 2026] message
+///   dmesg --time-format iso   2026-10-14T10:22:01,123456+02:00 message
+///   dmesg -x                  kern  :info  : [    1.234567] message
+///   dmesg -r                  <6>[    1.234567] message
+///
+use crate::klogctl;
+
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+lazy_static! {
+    static ref RE_DMESG_ENTRY: Regex = Regex::new(
+        r"(?x)^
+        # dmesg -x decodes facility and level like so: 'kern  :info  : '
+        (?:
+            (?P<facility>kern|user|mail|daemon|auth|syslog|lpr|news|uucp|cron|authpriv|ftp|local[0-7])[[:space:]]*:
+            (?P<level>emerg|alert|crit|err|warn|notice|info|debug)[[:space:]]*:[[:space:]]
+        )?
+        (?:(?:
+            # default: [    1.234567]
+            [\[][[:space:]]*(?P<timestampstr>[[:digit:]]+\.[[:digit:]]+)[\]]
+            |
+            # dmesg -T: [Tue Oct 14 10:22:01 2026]
+            [\[](?:[[:alpha:]]{3})[[:space:]]+(?P<ctime_month>[[:alpha:]]{3})[[:space:]]+(?P<ctime_day>[[:digit:]]{1,2})
+                [[:space:]]+(?P<ctime_hour>[[:digit:]]{2}):(?P<ctime_minute>[[:digit:]]{2}):(?P<ctime_second>[[:digit:]]{2})
+                [[:space:]]+(?P<ctime_year>[[:digit:]]{4})[\]]
+            |
+            # dmesg --time-format iso: 2026-10-14T10:22:01,123456+02:00
+            (?P<iso_year>[[:digit:]]{4})-(?P<iso_month>[[:digit:]]{2})-(?P<iso_day>[[:digit:]]{2})
+                T(?P<iso_hour>[[:digit:]]{2}):(?P<iso_minute>[[:digit:]]{2}):(?P<iso_second>[[:digit:]]{2})
+                (?:[,.](?P<iso_fraction>[[:digit:]]{1,9}))?
+                (?:(?P<iso_utc>Z)|(?P<iso_offset_sign>[+-])(?P<iso_offset_hours>[[:digit:]]{2}):?(?P<iso_offset_minutes>[[:digit:]]{2}))
+        )
+        # dmesg separates the timestamp from the message with a single space
+        [[:space:]]?)?
+        (?P<message>.*)
+        $"
+    )
+    .unwrap();
+}
+
+/// Parses a single line of dmesg output. Wall-clock times printed by `dmesg -T`
+/// carry no timezone, and are interpreted as UTC. Use `entry_from_line_with_utc_offset`
+/// when the timezone of the originating host is known.
+pub fn entry_from_line(line: &str) -> Result<Entry, EntryParsingError> {
+    entry_from_line_with_utc_offset(line, 0)
+}
+
+/// Parses a single line of dmesg output. Wall-clock times printed by `dmesg -T` are
+/// interpreted as being `utc_offset_secs` seconds east of UTC (e.g. 7200 for UTC+02:00).
+/// ISO timestamps carry their own offset, and are unaffected by this parameter.
+pub fn entry_from_line_with_utc_offset(
+    line: &str,
+    utc_offset_secs: i32,
+) -> Result<Entry, EntryParsingError> {
+    // dmesg -r prints the raw kernel format, which is exactly what klogctl returns
+    if line.trim_start().starts_with('<') {
+        return klogctl::entry_from_line(line);
+    }
+
+    let parts = match RE_DMESG_ENTRY.captures(line) {
+        Some(parts) => parts,
+        None => {
+            return Ok(Entry {
+                facility: None,
+                level: None,
+                sequence_num: None,
+                timestamp_from_system_start: None,
+                wallclock_time: None,
+                message: line.to_owned(),
+            })
+        }
+    };
+
+    let facility = match parts.name("facility") {
+        Some(facilitystr) => Some(parse_named::<LogFacility>(facilitystr.as_str(), line)?),
+        None => None,
+    };
+
+    let level = match parts.name("level") {
+        Some(levelstr) => Some(parse_named::<LogLevel>(levelstr.as_str(), line)?),
+        None => None,
+    };
+
+    let timestamp_from_system_start = match parts.name("timestampstr") {
+        Some(timestampstr) => common::parse_timestamp_secs(timestampstr.as_str(), line)?,
+        None => None,
+    };
+
+    let wallclock_time = if parts.name("ctime_year").is_some() {
+        Some(parse_ctime(&parts, utc_offset_secs, line)?)
+    } else if parts.name("iso_year").is_some() {
+        Some(parse_iso(&parts, line)?)
+    } else {
+        None
+    };
+
+    let message = parts["message"].to_owned();
+
+    Ok(Entry {
+        facility,
+        level,
+        sequence_num: None,
+        timestamp_from_system_start,
+        wallclock_time,
+        message,
+    })
+}
+
+/// Parses multiple lines of dmesg output (`dmesg -T` times are interpreted as UTC).
+pub fn entries_from_lines(all_lines: &str) -> Result<Vec<Entry>, EntryParsingError> {
+    entries_from_lines_with_utc_offset(all_lines, 0)
+}
+
+/// Parses multiple lines of dmesg output, interpreting `dmesg -T` times as being
+/// `utc_offset_secs` seconds east of UTC.
+pub fn entries_from_lines_with_utc_offset(
+    all_lines: &str,
+    utc_offset_secs: i32,
+) -> Result<Vec<Entry>, EntryParsingError> {
+    all_lines
+        .lines()
+        .map(|line| entry_from_line_with_utc_offset(line, utc_offset_secs))
+        .collect()
+}
+
+fn parse_named<T: FromStr>(name: &str, line: &str) -> Result<T, EntryParsingError> {
+    match T::from_str(name) {
+        Ok(t) => Ok(t),
+        Err(_) => Err(EntryParsingError::InvalidFragment {
+            fragment: name.to_owned(),
+            expected: std::any::type_name::<T>().to_owned(),
+            reason: "unknown name".to_owned(),
+            line: line.to_owned(),
+        }),
+    }
+}
+
+fn parse_ctime(
+    parts: &Captures,
+    utc_offset_secs: i32,
+    line: &str,
+) -> Result<SystemTime, EntryParsingError> {
+    let local = common::system_time_from_civil(
+        (
+            common::parse_fragment::<i64>(&parts["ctime_year"], line)?,
+            common::parse_month_abbrev(&parts["ctime_month"], line)?,
+            common::parse_fragment::<u32>(&parts["ctime_day"], line)?,
+        ),
+        (
+            common::parse_fragment::<u32>(&parts["ctime_hour"], line)?,
+            common::parse_fragment::<u32>(&parts["ctime_minute"], line)?,
+            common::parse_fragment::<u32>(&parts["ctime_second"], line)?,
+            0,
+        ),
+        line,
+    )?;
+
+    to_utc(local, utc_offset_secs, line)
+}
+
+fn parse_iso(parts: &Captures, line: &str) -> Result<SystemTime, EntryParsingError> {
+    // fraction is a decimal fraction of a second of arbitrary precision
+    let nanos = match parts.name("iso_fraction") {
+        Some(fraction) => {
+            let digits = fraction.as_str();
+            common::parse_fragment::<u32>(digits, line)? * 10u32.pow(9 - digits.len() as u32)
+        }
+        None => 0,
+    };
+
+    let local = common::system_time_from_civil(
+        (
+            common::parse_fragment::<i64>(&parts["iso_year"], line)?,
+            common::parse_fragment::<u32>(&parts["iso_month"], line)?,
+            common::parse_fragment::<u32>(&parts["iso_day"], line)?,
+        ),
+        (
+            common::parse_fragment::<u32>(&parts["iso_hour"], line)?,
+            common::parse_fragment::<u32>(&parts["iso_minute"], line)?,
+            common::parse_fragment::<u32>(&parts["iso_second"], line)?,
+            nanos,
+        ),
+        line,
+    )?;
+
+    let utc_offset_secs = match parts.name("iso_offset_sign") {
+        Some(sign) => {
+            let hours = common::parse_fragment::<i32>(&parts["iso_offset_hours"], line)?;
+            let minutes = common::parse_fragment::<i32>(&parts["iso_offset_minutes"], line)?;
+            let offset = hours * 3600 + minutes * 60;
+            match sign.as_str() {
+                "-" => -offset,
+                _ => offset,
+            }
+        }
+        None => 0,
+    };
+
+    to_utc(local, utc_offset_secs, line)
+}
+
+fn to_utc(
+    local: SystemTime,
+    utc_offset_secs: i32,
+    line: &str,
+) -> Result<SystemTime, EntryParsingError> {
+    match common::shift_to_utc(local, utc_offset_secs) {
+        Some(t) => Ok(t),
+        None => Err(EntryParsingError::InvalidTimestamp {
+            reason: format!(
+                "Unable to apply a UTC offset of {} seconds.",
+                utc_offset_secs
+            ),
+            line: line.to_owned(),
+        }),
+    }
+}
+
+/**********************************************************************************/
+// Tests! Tests! Tests!
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    // 2026-10-14T10:22:01Z
+    const OCT_14_2026_102201_UTC: u64 = 1_791_973_321;
+
+    #[test]
+    fn test_parse_default() {
+        let entry = entry_from_line("[    1.234567] Linux version 5.15.0").unwrap();
+        assert_eq!(
+            entry.timestamp_from_system_start,
+            Some(Duration::from_secs_f64(1.234567))
+        );
+        assert_eq!(entry.wallclock_time, None);
+        assert_eq!(entry.facility, None);
+        assert_eq!(entry.message, "Linux version 5.15.0");
+    }
+
+    #[test]
+    fn test_parse_ctime() {
+        let entry = entry_from_line("[Wed Oct 14 10:22:01 2026] eth0: Link is Up").unwrap();
+        assert_eq!(
+            entry.wallclock_time,
+            Some(UNIX_EPOCH + Duration::from_secs(OCT_14_2026_102201_UTC))
+        );
+        assert_eq!(entry.timestamp_from_system_start, None);
+        assert_eq!(entry.message, "eth0: Link is Up");
+
+        let entry =
+            entry_from_line_with_utc_offset("[Wed Oct 14 12:22:01 2026] eth0: Link is Up", 7200)
+                .unwrap();
+        assert_eq!(
+            entry.wallclock_time,
+            Some(UNIX_EPOCH + Duration::from_secs(OCT_14_2026_102201_UTC))
+        );
+
+        let entry = entry_from_line("[Sun Oct  4 00:00:00 2026] padded day").unwrap();
+        assert_eq!(
+            entry.wallclock_time,
+            Some(UNIX_EPOCH + Duration::from_secs(OCT_14_2026_102201_UTC - 10 * 86400 - 37321))
+        );
+    }
+
+    #[test]
+    fn test_parse_iso() {
+        let entry =
+            entry_from_line("2026-10-14T12:22:01,500000+02:00 usb 1-1: new device").unwrap();
+        assert_eq!(
+            entry.wallclock_time,
+            Some(UNIX_EPOCH + Duration::from_millis(OCT_14_2026_102201_UTC * 1000 + 500))
+        );
+        assert_eq!(entry.message, "usb 1-1: new device");
+
+        let entry = entry_from_line("2026-10-14T05:52:01,000000-0430 negative offset").unwrap();
+        assert_eq!(
+            entry.wallclock_time,
+            Some(UNIX_EPOCH + Duration::from_secs(OCT_14_2026_102201_UTC))
+        );
+    }
+
+    #[test]
+    fn test_parse_decoded_faclev() {
+        let entry = entry_from_line("kern  :info  : [    0.000000] Linux version").unwrap();
+        assert_eq!(entry.facility, Some(LogFacility::Kern));
+        assert_eq!(entry.level, Some(LogLevel::Info));
+        assert_eq!(
+            entry.timestamp_from_system_start,
+            Some(Duration::from_secs(0))
+        );
+        assert_eq!(entry.message, "Linux version");
+
+        let entry =
+            entry_from_line("daemon:warn  : [Wed Oct 14 10:22:01 2026] systemd[1]: hello").unwrap();
+        assert_eq!(entry.facility, Some(LogFacility::Daemon));
+        assert_eq!(entry.level, Some(LogLevel::Warning));
+        assert!(entry.wallclock_time.is_some());
+        assert_eq!(entry.message, "systemd[1]: hello");
+
+        let entry = entry_from_line("kern  :err   : no timestamp here").unwrap();
+        assert_eq!(entry.level, Some(LogLevel::Error));
+        assert_eq!(entry.message, "no timestamp here");
+
+        // messages written to /dev/kmsg from userspace may use the local facilities
+        let entry = entry_from_line("local7:notice: [   12.500000] myapp: started").unwrap();
+        assert_eq!(entry.facility, Some(LogFacility::Local7));
+        assert_eq!(entry.level, Some(LogLevel::Notice));
+        assert_eq!(entry.message, "myapp: started");
+        assert_eq!(entry.to_faclev(), Some(189));
+
+        let entry = entry_from_line("<189>[   12.500000] myapp: started").unwrap();
+        assert_eq!(entry.facility, Some(LogFacility::Local7));
+        assert_eq!(
+            entry_from_line("local0:info  : x").unwrap().facility,
+            Some(LogFacility::Local0)
+        );
+    }
+
+    #[test]
+    fn test_parse_raw_and_unknown() {
+        let entry = entry_from_line("<6>[    1.000000] raw line").unwrap();
+        assert_eq!(entry.level, Some(LogLevel::Info));
+        assert_eq!(
+            entry.timestamp_from_system_start,
+            Some(Duration::from_secs(1))
+        );
+
+        let entry = entry_from_line("  continuation of a previous line").unwrap();
+        assert_eq!(entry.message, "  continuation of a previous line");
+        assert_eq!(entry.timestamp_from_system_start, None);
+    }
+
+    #[test]
+    fn test_parse_multiline() {
+        let lines = "[    1.000000] first\n[    2.000000] second\n";
+        let entries = entries_from_lines(lines).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].message, "second");
+    }
+}
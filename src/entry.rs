@@ -1,9 +1,12 @@
 // Copyright (c) 2019 Polyverse Corporation
 
+// num-derive 0.3 implements FromPrimitive inside a const, which newer compilers lint
+#![allow(unknown_lints, non_local_definitions)]
+
 use num_derive::FromPrimitive;
 use std::error::Error;
 use std::fmt::{Display, Error as FmtError, Formatter, Result as FmtResult, Write};
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 use strum_macros::{Display, EnumString};
 
 #[cfg(feature = "extra-traits")]
@@ -24,11 +27,26 @@ pub struct Entry {
     // The amount of time since system bootstrapped
     pub timestamp_from_system_start: Option<Duration>,
 
+    // The wall-clock time of the entry (only known when the source carries it, e.g. dmesg -T)
+    pub wallclock_time: Option<SystemTime>,
+
     // Log message
     pub message: String,
 }
 
 impl Entry {
+    /// An entry with only a message, and none of the other fields known
+    pub fn new<S: Into<String>>(message: S) -> Self {
+        Self {
+            facility: None,
+            level: None,
+            sequence_num: None,
+            timestamp_from_system_start: None,
+            wallclock_time: None,
+            message: message.into(),
+        }
+    }
+
     pub fn to_faclev(&self) -> Option<u8> {
         match (self.facility, self.level) {
             (Some(facility), Some(level)) => Some(((facility as u8) << 3) + (level as u8)),
@@ -135,6 +153,31 @@ pub enum LogFacility {
 
     #[strum(serialize = "ftp")]
     FTP,
+
+    // 12 to 15 are reserved
+    #[strum(serialize = "local0")]
+    Local0 = 16,
+
+    #[strum(serialize = "local1")]
+    Local1,
+
+    #[strum(serialize = "local2")]
+    Local2,
+
+    #[strum(serialize = "local3")]
+    Local3,
+
+    #[strum(serialize = "local4")]
+    Local4,
+
+    #[strum(serialize = "local5")]
+    Local5,
+
+    #[strum(serialize = "local6")]
+    Local6,
+
+    #[strum(serialize = "local7")]
+    Local7,
 }
 
 /// Linux kmesg (kernel message buffer) Log Level.
@@ -171,6 +214,31 @@ pub enum EntryParsingError {
     Completed,
     EventTooOld,
     Generic(String),
+    InvalidFragment {
+        fragment: String,
+        expected: String,
+        reason: String,
+        line: String,
+    },
+    InvalidFacilityLevel {
+        faclev: u32,
+        line: String,
+    },
+    InvalidTimestamp {
+        reason: String,
+        line: String,
+    },
+}
+impl EntryParsingError {
+    /// The offending line, when the error is about a specific line.
+    pub fn line(&self) -> Option<&str> {
+        match self {
+            Self::InvalidFragment { line, .. }
+            | Self::InvalidFacilityLevel { line, .. }
+            | Self::InvalidTimestamp { line, .. } => Some(line),
+            _ => None,
+        }
+    }
 }
 impl Error for EntryParsingError {}
 impl Display for EntryParsingError {
@@ -179,10 +247,24 @@ impl Display for EntryParsingError {
             f,
             "KMsgParsingError:: {}",
             match self {
-                Self::Completed => "Completed Parsing",
+                Self::Completed => "Completed Parsing".to_owned(),
                 Self::EventTooOld =>
-                    "Event too old due to timestamp or sequence number (we've parsed newer messages than these)",
-                Self::Generic(s) => s,
+                    "Event too old due to timestamp or sequence number (we've parsed newer messages than these)".to_owned(),
+                Self::Generic(s) => s.to_owned(),
+                Self::InvalidFragment {
+                    fragment,
+                    expected,
+                    reason,
+                    line,
+                } => format!(
+                    "Unable to parse {} into a {} due to error: {}\nLine: {}",
+                    fragment, expected, reason, line
+                ),
+                Self::InvalidFacilityLevel { faclev, line } => format!(
+                    "Unable to parse {} into log facility and level. Line: {}",
+                    faclev, line
+                ),
+                Self::InvalidTimestamp { reason, line } => format!("{}\nLine: {}", reason, line),
             }
         )
     }
@@ -199,6 +281,7 @@ mod tests {
             facility: Some(LogFacility::Kern),
             level: Some(LogLevel::Info),
             sequence_num: Some(10),
+            wallclock_time: None,
             message: "Test message".to_owned(),
         };
         let expected_serialization = "<6>[    24241.325252]Test message";
@@ -219,6 +302,7 @@ mod tests {
             facility: Some(LogFacility::Kern),
             level: Some(LogLevel::Info),
             sequence_num: Some(23),
+            wallclock_time: None,
             message: "Test message".to_owned(),
         };
         let expected_serialization = "6,23,24241325252,-;Test message";
@@ -239,6 +323,7 @@ mod tests {
             facility: Some(LogFacility::Kern),
             level: Some(LogLevel::Info),
             sequence_num: Some(15),
+            wallclock_time: None,
             message: "Test message".to_owned(),
         };
         let expected_serialization = "[    24241.325252] Test message";
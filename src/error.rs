@@ -1,7 +1,11 @@
 use crate::entry;
+use crate::klogctl::KLogType;
+use crate::permissions::AccessDiagnosis;
+use errno::Errno;
 use std::convert::From;
 use std::error::Error;
 use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::string::FromUtf8Error;
 use std::time::SystemTimeError;
 
 #[derive(Debug)]
@@ -9,18 +13,124 @@ pub enum RMesgError {
     NotImplementedForThisPlatform,
     UnableToObtainSystemTime,
     UnableToAddDurationToSystemTime,
+    #[deprecated(
+        note = "never returned: following logs through klogctl no longer requires timestamps"
+    )]
     KLogTimestampsDisabled,
     IntegerOutOfBound(String),
-    Utf8StringConversionError(String),
-    IOError(String),
+    Utf8StringConversionError(FromUtf8Error),
+    IOError(std::io::Error),
     InternalError(String),
-    EntryParsingError(String),
+    EntryParsingError {
+        // 1-based line number within the buffer being parsed, when known
+        line_number: Option<usize>,
+        source: entry::EntryParsingError,
+    },
     UnableToObtainElapsedTime(SystemTimeError),
-    DevKMsgFileOpenError(String),
-    OperationNotPermitted(String),
+    DevKMsgFileOpenError {
+        path: String,
+        source: std::io::Error,
+    },
+    PermissionDenied {
+        operation: String,
+        diagnosis: Box<AccessDiagnosis>,
+        source: std::io::Error,
+    },
+    KLogCtlError {
+        request: KLogType,
+        errno: Errno,
+    },
+    BackendUnavailable(String),
+    UnexpectedSettingValue {
+        path: String,
+        value: String,
+    },
+    InvalidTimeBound {
+        value: String,
+        reason: String,
+    },
 }
-impl Error for RMesgError {}
+
+/// A stable classification of `RMesgError`, to branch on without matching messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// Not supported on this platform/kernel (includes ENOSYS)
+    Unsupported,
+    /// EPERM or EACCES (see `RMesgError::PermissionDenied` for a diagnosis)
+    PermissionDenied,
+    /// ENOENT
+    NotFound,
+    /// The log buffer contents could not be parsed or decoded
+    Parse,
+    /// Any other I/O or system call failure
+    Io,
+    /// System time could not be obtained or computed
+    Time,
+    /// A bug or an unexpected condition within this crate
+    Internal,
+}
+
+impl RMesgError {
+    /// Classifies this error into a stable `ErrorKind`.
+    #[allow(deprecated)]
+    pub fn kind(&self) -> ErrorKind {
+        if let Some(errno) = self.errno() {
+            match errno {
+                libc::EPERM | libc::EACCES => return ErrorKind::PermissionDenied,
+                libc::ENOENT => return ErrorKind::NotFound,
+                libc::ENOSYS => return ErrorKind::Unsupported,
+                _ => {}
+            }
+        }
+
+        match self {
+            Self::NotImplementedForThisPlatform
+            | Self::BackendUnavailable(_)
+            | Self::KLogTimestampsDisabled => ErrorKind::Unsupported,
+            Self::PermissionDenied { .. } => ErrorKind::PermissionDenied,
+            Self::Utf8StringConversionError(_)
+            | Self::EntryParsingError { .. }
+            | Self::UnexpectedSettingValue { .. }
+            | Self::InvalidTimeBound { .. } => ErrorKind::Parse,
+            Self::IOError(_) | Self::DevKMsgFileOpenError { .. } | Self::KLogCtlError { .. } => {
+                ErrorKind::Io
+            }
+            Self::UnableToObtainSystemTime
+            | Self::UnableToAddDurationToSystemTime
+            | Self::UnableToObtainElapsedTime(_) => ErrorKind::Time,
+            Self::IntegerOutOfBound(_) | Self::InternalError(_) => ErrorKind::Internal,
+        }
+    }
+
+    /// The OS error number behind this error, if there is one.
+    pub fn errno(&self) -> Option<i32> {
+        match self {
+            Self::IOError(e) | Self::DevKMsgFileOpenError { source: e, .. } => e.raw_os_error(),
+            Self::KLogCtlError { errno, .. } => Some(errno.0),
+            Self::PermissionDenied { diagnosis, .. } => Some(diagnosis.errno),
+            _ => None,
+        }
+    }
+}
+
+impl Error for RMesgError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Utf8StringConversionError(e) => Some(e),
+            Self::IOError(e) => Some(e),
+            Self::EntryParsingError { source, .. } => Some(source),
+            Self::UnableToObtainElapsedTime(e) => Some(e),
+            Self::DevKMsgFileOpenError { source, .. } => Some(source),
+            Self::PermissionDenied { source, .. } => Some(source),
+            Self::KLogCtlError { errno, .. } => Some(errno),
+            _ => None,
+        }
+    }
+}
+
 impl Display for RMesgError {
+    #[allow(deprecated)]
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         write!(
             f,
@@ -29,35 +139,128 @@ impl Display for RMesgError {
                 Self::NotImplementedForThisPlatform =>
                     "RMesg not implemented for this platform.".to_owned(),
                 Self::IntegerOutOfBound(s) => format!("IntegerOutOfBound: {}", s),
-                Self::Utf8StringConversionError(s) => format!("Utf8StringConversionError: {}", s),
-                Self::IOError(s) => format!("std::io::Error: {}", s),
+                Self::Utf8StringConversionError(e) => format!("Utf8StringConversionError: {}", e),
+                Self::IOError(e) => format!("std::io::Error: {}", e),
                 Self::InternalError(s) => format!("InternalError: {}", s),
-                Self::EntryParsingError(s) => format!("EntryParsingError: {}", s),
-                Self::UnableToObtainElapsedTime(s) => format!("UnableToObtainElapsedTime: {}", s),
+                Self::EntryParsingError {
+                    line_number: Some(n),
+                    source,
+                } => format!("EntryParsingError at line {}: {}", n, source),
+                Self::EntryParsingError {
+                    line_number: None,
+                    source,
+                } => format!("EntryParsingError: {}", source),
+                Self::UnableToObtainElapsedTime(e) => format!("UnableToObtainElapsedTime: {}", e),
                 Self::UnableToObtainSystemTime => "Failed to get SystemTime.".to_owned(),
                 Self::UnableToAddDurationToSystemTime =>
                     "Failed to add a Duration to SystemTime".to_owned(),
                 Self::KLogTimestampsDisabled => "Kernel Log timestamps are disabled".to_owned(),
-                Self::DevKMsgFileOpenError(s) => s.to_owned(),
-                Self::OperationNotPermitted(s) => format!("OperationNotPermitted: {}", s),
+                Self::DevKMsgFileOpenError { path, source } =>
+                    format!("Unable to open or read file {}: {}", path, source),
+                Self::PermissionDenied { diagnosis, .. } =>
+                    format!("PermissionDenied: {}", diagnosis),
+                Self::KLogCtlError { request, errno } => format!(
+                    "Request ({}) to klogctl failed. errno={} ({})",
+                    request, errno.0, errno
+                ),
+                Self::BackendUnavailable(s) => format!("BackendUnavailable: {}", s),
+                Self::UnexpectedSettingValue { path, value } =>
+                    format!("Unexpected value in {}: {:?}", path, value),
+                Self::InvalidTimeBound { value, reason } =>
+                    format!("Invalid time {:?}: {}", value, reason),
             }
         )
     }
 }
-impl From<std::string::FromUtf8Error> for RMesgError {
-    fn from(err: std::string::FromUtf8Error) -> RMesgError {
-        RMesgError::Utf8StringConversionError(format!("{:?}", err))
+
+impl From<FromUtf8Error> for RMesgError {
+    fn from(err: FromUtf8Error) -> RMesgError {
+        RMesgError::Utf8StringConversionError(err)
     }
 }
 
 impl From<std::io::Error> for RMesgError {
     fn from(err: std::io::Error) -> RMesgError {
-        RMesgError::IOError(format!("{:?}", err))
+        RMesgError::IOError(err)
     }
 }
 
 impl From<entry::EntryParsingError> for RMesgError {
     fn from(err: entry::EntryParsingError) -> RMesgError {
-        RMesgError::EntryParsingError(format!("{:?}", err))
+        RMesgError::EntryParsingError {
+            line_number: None,
+            source: err,
+        }
+    }
+}
+
+/**********************************************************************************/
+// Tests! Tests! Tests!
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn test_kind_and_errno() {
+        let eacces: RMesgError = io::Error::from_raw_os_error(libc::EACCES).into();
+        assert_eq!(eacces.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(eacces.errno(), Some(libc::EACCES));
+        assert!(eacces.source().is_some());
+
+        let enoent = RMesgError::DevKMsgFileOpenError {
+            path: "/dev/kmsg".to_owned(),
+            source: io::Error::from_raw_os_error(libc::ENOENT),
+        };
+        assert_eq!(enoent.kind(), ErrorKind::NotFound);
+
+        let klogctl = RMesgError::KLogCtlError {
+            request: KLogType::SyslogActionReadAll,
+            errno: Errno(libc::EINVAL),
+        };
+        assert_eq!(klogctl.kind(), ErrorKind::Io);
+        assert_eq!(klogctl.errno(), Some(libc::EINVAL));
+    }
+
+    #[test]
+    fn test_permission_denied_source() {
+        let denied = crate::permissions::denied(
+            "Open File /nonexistent".to_owned(),
+            None,
+            io::Error::from_raw_os_error(libc::EACCES),
+            false,
+        );
+        assert_eq!(denied.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(denied.errno(), Some(libc::EACCES));
+
+        let source = denied
+            .source()
+            .unwrap()
+            .downcast_ref::<io::Error>()
+            .unwrap();
+        assert_eq!(source.raw_os_error(), Some(libc::EACCES));
+    }
+
+    #[test]
+    fn test_parsing_error_source() {
+        let err = RMesgError::EntryParsingError {
+            line_number: Some(3),
+            source: entry::EntryParsingError::InvalidFragment {
+                fragment: "x".to_owned(),
+                expected: "u32".to_owned(),
+                reason: "invalid digit found in string".to_owned(),
+                line: "x,1,0,-;message".to_owned(),
+            },
+        };
+        assert_eq!(err.kind(), ErrorKind::Parse);
+
+        let source = err
+            .source()
+            .unwrap()
+            .downcast_ref::<entry::EntryParsingError>()
+            .unwrap();
+        assert_eq!(source.line(), Some("x,1,0,-;message"));
+        assert!(format!("{}", err).contains("line 3"));
     }
 }
@@ -0,0 +1,132 @@
+/// Library-side diagnostics that used to be written to stderr are surfaced as structured
+/// events instead, so that daemons and TUIs stay in control of their own output.
+///
+/// Register a hook with `set_event_hook` to observe them. When the `log` feature is enabled,
+/// every event is also forwarded to the `log` crate at the warn level.
+///
+use crate::error::RMesgError;
+use crate::{Backend, SelectedBackend};
+
+use lazy_static::lazy_static;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::sync::RwLock;
+
+type EventHook = Box<dyn Fn(&RMesgEvent) + Send + Sync>;
+
+lazy_static! {
+    static ref EVENT_HOOK: RwLock<Option<EventHook>> = RwLock::new(None);
+}
+
+/// An observable event raised by the library
+#[derive(Debug)]
+pub enum RMesgEvent {
+    /// The requested backend could not be used, and another one was selected in its place
+    BackendFallback {
+        requested: Backend,
+        from: SelectedBackend,
+        to: SelectedBackend,
+        reason: RMesgError,
+    },
+
+    /// Entries were lost before they could be read (e.g. the kernel's ring buffer overflowed).
+    /// `count` is set when the backend can tell how many.
+    EntriesLost {
+        backend: SelectedBackend,
+        count: Option<u64>,
+    },
+}
+
+impl Display for RMesgEvent {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::BackendFallback {
+                requested,
+                from,
+                to,
+                reason,
+            } => write!(
+                f,
+                "Backend {:?} requested. Falling back from {} to {} due to error: {}",
+                requested, from, to, reason
+            ),
+            Self::EntriesLost {
+                backend,
+                count: Some(count),
+            } => write!(
+                f,
+                "{} entries were lost before {} could read them",
+                count, backend
+            ),
+            Self::EntriesLost {
+                backend,
+                count: None,
+            } => write!(f, "Entries were lost before {} could read them", backend),
+        }
+    }
+}
+
+/// Registers a process-wide hook that is called for every event. Replaces any previous hook.
+pub fn set_event_hook<F>(hook: F)
+where
+    F: Fn(&RMesgEvent) + Send + Sync + 'static,
+{
+    if let Ok(mut guard) = EVENT_HOOK.write() {
+        *guard = Some(Box::new(hook));
+    }
+}
+
+/// Removes the process-wide event hook, if any.
+pub fn clear_event_hook() {
+    if let Ok(mut guard) = EVENT_HOOK.write() {
+        *guard = None;
+    }
+}
+
+pub(crate) fn emit(event: RMesgEvent) {
+    #[cfg(feature = "log")]
+    log::warn!("{}", event);
+
+    if let Ok(guard) = EVENT_HOOK.read() {
+        if let Some(hook) = guard.as_ref() {
+            hook(&event);
+        }
+    }
+}
+
+/**********************************************************************************/
+// Tests! Tests! Tests!
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_event_hook() {
+        let seen = Arc::new(AtomicUsize::new(0));
+        let seen_by_hook = seen.clone();
+        set_event_hook(move |event| {
+            // other tests may raise events of their own concurrently
+            if matches!(event, RMesgEvent::BackendFallback { .. }) {
+                seen_by_hook.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+
+        emit(RMesgEvent::BackendFallback {
+            requested: Backend::Default,
+            from: SelectedBackend::DevKMsg,
+            to: SelectedBackend::KLogCtl,
+            reason: RMesgError::NotImplementedForThisPlatform,
+        });
+        clear_event_hook();
+        emit(RMesgEvent::BackendFallback {
+            requested: Backend::Default,
+            from: SelectedBackend::DevKMsg,
+            to: SelectedBackend::KLogCtl,
+            reason: RMesgError::NotImplementedForThisPlatform,
+        });
+
+        assert_eq!(seen.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,352 @@
+use crate::analysis::Analyzer;
+/// This module parses the lines the kernel logs when a userspace process is killed by a
+/// fault or a trap, e.g.:
+///
+/// ```text
+/// a.out[4054]: segfault at 7ffd5503d358 ip 00007ffd5503d358 sp 00007ffd5503d258 error 15 in a.out[400000+1000]
+/// traps: a.out[4055] general protection fault ip:401136 sp:7ffc6de5a6f0 error:0 in a.out[401000+1000]
+/// traps: a.out[4056] trap divide error ip:401136 sp:7ffc6de5a6f0 error:0 in a.out[401000+1000]
+/// ```
+///
+/// These are what exploit attempts (and plain bugs) look like from the kernel log, so
+/// the fault address, instruction and stack pointers, the decoded page fault error code
+/// and the object the instruction pointer fell in are all parsed out.
+///
+use crate::entry::Entry;
+
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::time::Duration;
+
+lazy_static! {
+    static ref RE_SEGFAULT: Regex = Regex::new(
+        r"(?x)^
+        (?P<comm>.+)\[(?P<pid>[[:digit:]]+)\]:[[:space:]]+segfault[[:space:]]+at[[:space:]]+(?P<address>[[:xdigit:]]+)
+        [[:space:]]+ip[[:space:]]+(?P<ip>[[:xdigit:]]+)
+        [[:space:]]+sp[[:space:]]+(?P<sp>[[:xdigit:]]+)
+        [[:space:]]+error[[:space:]]+(?P<error>[[:xdigit:]]+)
+        (?P<rest>.*)
+        $"
+    )
+    .unwrap();
+    static ref RE_TRAP: Regex = Regex::new(
+        r"(?x)^
+        (?:traps:[[:space:]]+)?
+        (?P<comm>.+?)\[(?P<pid>[[:digit:]]+)\]:?
+        [[:space:]]+(?:trap[[:space:]]+)?(?P<trap>[a-z0-9][a-z0-9[:space:]]*?)
+        [[:space:]]+ip:(?P<ip>[[:xdigit:]]+)
+        [[:space:]]+sp:(?P<sp>[[:xdigit:]]+)
+        [[:space:]]+error:(?P<error>[[:xdigit:]]+)
+        (?P<rest>.*)
+        $"
+    )
+    .unwrap();
+    // "in libc.so.6[7f8a1b228000+195000]", or with the offset within the object first
+    // "in libc.so.6[28a3f,7f8a1b228000+195000]"
+    static ref RE_MAPPING: Regex = Regex::new(
+        r"[[:space:]]in[[:space:]]+(?P<object>[^[:space:]]+?)\[(?:[[:xdigit:]]+,)?(?P<base>[[:xdigit:]]+)\+(?P<size>[[:xdigit:]]+)\]"
+    )
+    .unwrap();
+    static ref RE_CPU: Regex = Regex::new(r"likely on CPU (?P<cpu>[[:digit:]]+)").unwrap();
+}
+
+/// The fault or trap a process was killed by
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FaultKind {
+    /// An access to an address that isn't mapped, or not mapped for that access (SIGSEGV)
+    Segfault,
+    /// A general protection fault, e.g. on a non-canonical address (SIGSEGV)
+    GeneralProtection,
+    /// An integer division by zero, or overflow (SIGFPE)
+    DivideError,
+    /// An undefined instruction (SIGILL)
+    InvalidOpcode,
+    /// Any other trap, as the kernel names it (e.g. "int3", "stack segment")
+    Trap(String),
+}
+
+impl Display for FaultKind {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Segfault => write!(f, "segfault"),
+            Self::GeneralProtection => write!(f, "general protection fault"),
+            Self::DivideError => write!(f, "divide error"),
+            Self::InvalidOpcode => write!(f, "invalid opcode"),
+            Self::Trap(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// The object (executable or library) an instruction pointer fell in:
+/// `libc.so.6[7f8a1b228000+195000]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    pub object: String,
+    /// Where the object's executable mapping starts
+    pub base: u64,
+    pub size: u64,
+}
+
+impl Mapping {
+    /// The offset of an address within the object (e.g. to symbolize the instruction
+    /// pointer with addr2line), when it falls within it
+    pub fn offset_of(&self, address: u64) -> Option<u64> {
+        match address.checked_sub(self.base) {
+            Some(offset) if offset < self.size => Some(offset),
+            _ => None,
+        }
+    }
+}
+
+/// The page fault error code bits (x86)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PageFaultError {
+    /// The page was present (so this was a protection violation), rather than not present
+    pub protection_violation: bool,
+    /// The access was a write, rather than a read
+    pub write: bool,
+    /// The access was made in user mode
+    pub user: bool,
+    /// A reserved bit was set in a page table entry
+    pub reserved_bit: bool,
+    /// The access was an instruction fetch (e.g. executing the stack)
+    pub instruction_fetch: bool,
+    /// A protection key blocked the access
+    pub protection_key: bool,
+    /// The access was a shadow stack access
+    pub shadow_stack: bool,
+    /// The fault was related to SGX
+    pub sgx: bool,
+}
+
+impl PageFaultError {
+    pub fn from_code(code: u64) -> Self {
+        let bit = |n: u32| code & (1 << n) != 0;
+        Self {
+            protection_violation: bit(0),
+            write: bit(1),
+            user: bit(2),
+            reserved_bit: bit(3),
+            instruction_fetch: bit(4),
+            protection_key: bit(5),
+            shadow_stack: bit(6),
+            sgx: bit(15),
+        }
+    }
+}
+
+/// A userspace process killed by a fault or trap
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserFault {
+    pub kind: FaultKind,
+    /// The command (task name) of the process
+    pub comm: String,
+    pub pid: u32,
+    /// The address accessed (only known for segfaults)
+    pub address: Option<u64>,
+    /// The instruction pointer
+    pub ip: u64,
+    /// The stack pointer
+    pub sp: u64,
+    /// The error code (for segfaults, see `page_fault_error`)
+    pub error_code: u64,
+    /// The object the instruction pointer fell in
+    pub mapping: Option<Mapping>,
+    /// The CPU the fault likely happened on (newer kernels only)
+    pub cpu: Option<u32>,
+    /// The time since boot of the entry
+    pub timestamp_from_system_start: Option<Duration>,
+}
+
+impl UserFault {
+    /// Parses a fault or trap line (an entry's message), or `None` when it's neither.
+    pub fn parse(line: &str) -> Option<UserFault> {
+        let line = line.trim();
+        if let Some(parts) = RE_SEGFAULT.captures(line) {
+            let mut fault = Self::from_parts(FaultKind::Segfault, &parts)?;
+            fault.address = Some(hex(&parts["address"])?);
+            Some(fault)
+        } else if let Some(parts) = RE_TRAP.captures(line) {
+            let kind = match parts["trap"].trim() {
+                "general protection" | "general protection fault" => FaultKind::GeneralProtection,
+                "divide error" => FaultKind::DivideError,
+                "invalid opcode" => FaultKind::InvalidOpcode,
+                other => FaultKind::Trap(other.to_owned()),
+            };
+            Self::from_parts(kind, &parts)
+        } else {
+            None
+        }
+    }
+
+    /// Parses an entry, keeping its timestamp.
+    pub fn from_entry(entry: &Entry) -> Option<UserFault> {
+        let mut fault = Self::parse(&entry.message)?;
+        fault.timestamp_from_system_start = entry.timestamp_from_system_start;
+        Some(fault)
+    }
+
+    /// The decoded error code, for segfaults (for traps, it isn't a page fault error code)
+    pub fn page_fault_error(&self) -> Option<PageFaultError> {
+        match self.kind {
+            FaultKind::Segfault => Some(PageFaultError::from_code(self.error_code)),
+            _ => None,
+        }
+    }
+
+    /// The offset of the instruction pointer within the object it fell in
+    pub fn ip_offset(&self) -> Option<u64> {
+        self.mapping.as_ref()?.offset_of(self.ip)
+    }
+
+    fn from_parts(kind: FaultKind, parts: &Captures) -> Option<UserFault> {
+        let rest = &parts["rest"];
+        let mapping = match RE_MAPPING.captures(rest) {
+            Some(mapping) => Some(Mapping {
+                object: mapping["object"].to_owned(),
+                base: hex(&mapping["base"])?,
+                size: hex(&mapping["size"])?,
+            }),
+            None => None,
+        };
+
+        Some(UserFault {
+            kind,
+            comm: parts["comm"].to_owned(),
+            pid: parts["pid"].parse().ok()?,
+            address: None,
+            ip: hex(&parts["ip"])?,
+            sp: hex(&parts["sp"])?,
+            error_code: hex(&parts["error"])?,
+            mapping,
+            cpu: RE_CPU
+                .captures(rest)
+                .and_then(|cpu| cpu["cpu"].parse().ok()),
+            timestamp_from_system_start: None,
+        })
+    }
+}
+
+/// Finds `UserFault`s among entries
+#[derive(Debug, Default)]
+pub struct FaultAnalyzer;
+
+impl FaultAnalyzer {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Analyzer for FaultAnalyzer {
+    type Report = UserFault;
+
+    fn push(&mut self, entry: &Entry) -> Vec<UserFault> {
+        UserFault::from_entry(entry).into_iter().collect()
+    }
+
+    fn finish(&mut self) -> Vec<UserFault> {
+        Vec::new()
+    }
+}
+
+fn hex(value: &str) -> Option<u64> {
+    u64::from_str_radix(value, 16).ok()
+}
+
+/**********************************************************************************/
+// Tests! Tests! Tests!
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis;
+    use crate::klogctl;
+
+    #[test]
+    fn test_segfault() {
+        // the README's example
+        let fault = UserFault::parse("a.out[4054]: segfault at 7ffd5503d358 ip 00007ffd5503d358 sp 00007ffd5503d258 error 15").unwrap();
+        assert_eq!(fault.kind, FaultKind::Segfault);
+        assert_eq!((fault.comm.as_str(), fault.pid), ("a.out", 4054));
+        assert_eq!(fault.address, Some(0x7ffd5503d358));
+        assert_eq!(fault.ip, 0x7ffd5503d358);
+        assert_eq!(fault.sp, 0x7ffd5503d258);
+        assert_eq!(fault.mapping, None);
+
+        // executing the stack: present page, user mode instruction fetch (0x15)
+        assert_eq!(
+            fault.page_fault_error(),
+            Some(PageFaultError {
+                protection_violation: true,
+                user: true,
+                instruction_fetch: true,
+                ..PageFaultError::default()
+            })
+        );
+
+        let fault = UserFault::parse(
+            "Web Content[1234]: segfault at 0 ip 00007f8a1b2a3f4d sp 00007ffc6de5a6f0 error 4 in libc.so.6[7f8a1b228000+195000] likely on CPU 3 (core 3, socket 0)",
+        )
+        .unwrap();
+        assert_eq!(fault.comm, "Web Content");
+        assert_eq!(fault.address, Some(0));
+        assert_eq!(
+            fault.mapping,
+            Some(Mapping {
+                object: "libc.so.6".to_owned(),
+                base: 0x7f8a1b228000,
+                size: 0x195000,
+            })
+        );
+        assert_eq!(fault.ip_offset(), Some(0x7bf4d));
+        assert_eq!(fault.cpu, Some(3));
+        assert!(fault.page_fault_error().unwrap().user);
+        assert!(!fault.page_fault_error().unwrap().write);
+    }
+
+    #[test]
+    fn test_traps() {
+        let fault = UserFault::parse("traps: a.out[4055] general protection fault ip:401136 sp:7ffc6de5a6f0 error:0 in a.out[401000+1000]").unwrap();
+        assert_eq!(fault.kind, FaultKind::GeneralProtection);
+        assert_eq!(fault.ip_offset(), Some(0x136));
+        assert_eq!(fault.address, None);
+        assert_eq!(fault.page_fault_error(), None);
+
+        let fault = UserFault::parse(
+            "traps: a.out[4056] trap divide error ip:401136 sp:7ffc6de5a6f0 error:0 in a.out[28a3f,401000+1000]",
+        )
+        .unwrap();
+        assert_eq!(fault.kind, FaultKind::DivideError);
+        assert_eq!(fault.mapping.unwrap().base, 0x401000);
+
+        let fault = UserFault::parse(
+            "traps: a.out[4057] trap invalid opcode ip:401136 sp:7ffc6de5a6f0 error:0 in a.out[401000+1000]",
+        )
+        .unwrap();
+        assert_eq!(fault.kind, FaultKind::InvalidOpcode);
+
+        let fault =
+            UserFault::parse("a.out[4058] trap int3 ip:401137 sp:7ffc6de5a6f0 error:0").unwrap();
+        assert_eq!(fault.kind, FaultKind::Trap("int3".to_owned()));
+        assert_eq!(fault.comm, "a.out");
+
+        assert_eq!(UserFault::parse("eth0: link up"), None);
+    }
+
+    #[test]
+    fn test_fault_analyzer() {
+        let entries = klogctl::entries_from_lines(
+            "<6>[   10.000000] eth0: link up\n\
+             <6>[   12.500000] a.out[4054]: segfault at 7ffd5503d358 ip 00007ffd5503d358 sp 00007ffd5503d258 error 15\n\
+             <6>[   12.500001] Code: Unable to access opcode bytes at 0x7ffd5503d32e.",
+        )
+        .unwrap();
+        let faults = analysis::analyze(FaultAnalyzer::new(), &entries);
+        assert_eq!(faults.len(), 1);
+        assert_eq!(
+            faults[0].timestamp_from_system_start,
+            Some(Duration::from_millis(12_500))
+        );
+    }
+}
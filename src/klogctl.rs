@@ -10,12 +10,24 @@ use crate::entry::{Entry, EntryParsingError};
 /// This allows Rust programs to consume dmesg-like output programmatically.
 ///
 use crate::error::RMesgError;
+use crate::loss::{LossStats, LossTracker};
+use crate::paths::SystemPaths;
+use crate::permissions;
+#[cfg(feature = "async")]
+use crate::source::AsyncLogSource;
+#[cfg(feature = "sync")]
+use crate::source::LogSource;
+use crate::SelectedBackend;
 
 use errno::errno;
 use lazy_static::lazy_static;
 use regex::Regex;
+#[cfg(any(feature = "sync", feature = "async"))]
+use std::collections::hash_map::DefaultHasher;
 use std::convert::TryFrom;
 use std::fs;
+#[cfg(any(feature = "sync", feature = "async"))]
+use std::hash::{Hash, Hasher};
 use std::time::{Duration, SystemTime};
 use strum_macros::Display;
 
@@ -78,6 +90,78 @@ pub const SYS_MODULE_PRINTK_PARAMETERS_TIME: &str = "/sys/module/printk/paramete
 /// suggest polling every ten seconds
 pub const SUGGESTED_POLL_INTERVAL: std::time::Duration = Duration::from_secs(10);
 
+/// suggest polling no more often than four times a second, when polling adaptively
+pub const SUGGESTED_MIN_POLL_INTERVAL: std::time::Duration = Duration::from_millis(250);
+
+/// The interval an adaptive schedule backs off from when its minimum is zero
+#[cfg(any(feature = "sync", feature = "async"))]
+const MIN_BACKOFF_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Decides how long KLogEntries waits between two polls of the buffer.
+///
+/// An adaptive schedule polls at the minimum interval while new entries keep arriving,
+/// and doubles the interval (up to the maximum) on every poll that finds nothing new.
+/// A fixed schedule is simply one where both bounds are the same.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PollSchedule {
+    min: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl PollSchedule {
+    /// Always polls at the same interval
+    pub fn fixed(interval: Duration) -> Self {
+        Self::adaptive(interval, interval)
+    }
+
+    /// Polls between `min` and `max`, depending on how busy the log is.
+    /// A `max` lower than `min` is raised to `min`.
+    pub fn adaptive(min: Duration, max: Duration) -> Self {
+        Self {
+            min,
+            max: max.max(min),
+            current: min,
+        }
+    }
+
+    pub fn min(&self) -> Duration {
+        self.min
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// The interval until the next poll
+    pub fn current(&self) -> Duration {
+        self.current
+    }
+
+    #[cfg(any(feature = "sync", feature = "async"))]
+    fn is_backed_off(&self) -> bool {
+        self.current >= self.max
+    }
+
+    #[cfg(any(feature = "sync", feature = "async"))]
+    fn record(&mut self, entries_added: usize) {
+        self.current = match entries_added {
+            0 => self
+                .current
+                .max(MIN_BACKOFF_INTERVAL)
+                .saturating_mul(2)
+                .clamp(self.min, self.max),
+            _ => self.min,
+        };
+    }
+}
+
+impl Default for PollSchedule {
+    fn default() -> Self {
+        Self::adaptive(SUGGESTED_MIN_POLL_INTERVAL, SUGGESTED_POLL_INTERVAL)
+    }
+}
+
 lazy_static! {
     static ref RE_ENTRY_WITH_TIMESTAMP: Regex = Regex::new(
         r"(?x)^
@@ -95,25 +179,34 @@ lazy_static! {
 /// This struct provides the facilities to do that. It implements an iterator to easily iterate
 /// indefinitely over the lines.
 ///
-/// IMPORTANT NOTE: This iterator makes a best-effort attempt at eliminating duplicate lines
-/// so that it can only provide newer lines upon each iteration. The way it accomplishes this is
-/// by using the timestamp field, to track the last-seen timestamp of a line already buffered,
-/// and this only consuming lines past that timestamp on each poll.
+/// IMPORTANT NOTE: Each poll reads the entire buffer, so this iterator eliminates lines it has
+/// already provided by matching content: the longest run of lines at the end of the previous read
+/// that the new read starts with has already been seen, and everything after it is new.
+/// This doesn't depend on timestamps, so it works whether or not they're enabled.
 ///
-/// The timestamp may not always be set in kernel logs. The iterator will ignore lines without a timestamp.
-/// It is left to the consumers of this struct to ensure the timestamp is set, if they wish for
-/// lines to not be ignored. In order to aid this, two functions are provided in this crate to
-/// check `kernel_log_timestamps_enabled` and to set or unset `kernel_log_timestamps_enable`.
+/// When none of the previously read lines are left, the ring buffer wrapped around past all of
+/// them between two polls, and whatever was logged in between was lost. When clearing the buffer
+/// on every read, a read that (nearly) fills the whole buffer means the same. Such gaps are
+/// counted (see `loss_stats`) and raised as `RMesgEvent::EntriesLost` events. A buffer cleared by
+/// another reader (e.g. `dmesg -C`) and refilled in between looks the same, and is counted too.
 ///
-/// The UX is left to the consumer.
+/// Identical lines (e.g. repeated messages with timestamps disabled) make the overlap ambiguous;
+/// the longest overlap is assumed, which errs on the side of not repeating lines.
 ///
 pub struct KLogEntries {
+    #[cfg(any(feature = "sync", feature = "async"))]
     clear: bool,
+    #[cfg(any(feature = "sync", feature = "async"))]
     entries: Vec<Entry>,
-    last_timestamp: Option<Duration>,
-    poll_interval: Duration,
-    sleep_interval: Duration, // Just slightly longer than poll interval so the check passes
+    /// Hashes of the lines of the previous read (see `line_hash`)
+    #[cfg(any(feature = "sync", feature = "async"))]
+    last_read: Vec<u64>,
+    loss: LossTracker,
+    schedule: PollSchedule,
+    #[cfg(any(feature = "sync", feature = "async"))]
     last_poll: SystemTime,
+    #[cfg(any(feature = "sync", feature = "async"))]
+    last_unread_size: Option<usize>,
 
     #[cfg(feature = "async")]
     sleep_future: Option<Pin<Box<tokiotime::Sleep>>>,
@@ -136,78 +229,189 @@ impl KLogEntries {
     /// will be lost.
     ///
     /// This crate exports a constant `SUGGESTED_POLL_INTERVAL` which contains the recommended
-    /// default when in doubt.
+    /// default when in doubt. To poll faster when the log is busy, and back off when it is idle,
+    /// see `with_schedule`.
     ///
     pub fn with_options(clear: bool, poll_interval: Duration) -> Result<KLogEntries, RMesgError> {
-        let sleep_interval = match poll_interval.checked_add(Duration::from_millis(200)) {
-            Some(si) => si,
-            None => return Err(RMesgError::UnableToAddDurationToSystemTime),
-        };
+        Self::with_schedule(clear, PollSchedule::fixed(poll_interval))
+    }
 
+    /// Create a new KLogEntries that polls according to a `PollSchedule`.
+    ///
+    /// With an adaptive schedule, KLogEntries first asks the kernel for the size of its unread
+    /// log (`SyslogActionSizeUnread`), which is far cheaper than reading the entire buffer.
+    /// When that size is non-zero and hasn't changed, nothing was logged since the last poll,
+    /// and the read is skipped (until the schedule has backed off all the way to its maximum).
+    /// A zero size is not trusted, since a syslog daemon reading /proc/kmsg keeps it there.
+    /// Querying the size requires CAP_SYSLOG; without it, every poll reads the buffer.
+    ///
+    // without "sync" or "async" there is nothing to poll, so only the schedule is kept
+    #[cfg_attr(not(any(feature = "sync", feature = "async")), allow(unused_variables))]
+    pub fn with_schedule(clear: bool, schedule: PollSchedule) -> Result<KLogEntries, RMesgError> {
         // set last poll in the past so it polls the first time
-        let last_poll = match SystemTime::now().checked_sub(sleep_interval) {
+        let last_poll = match SystemTime::now().checked_sub(schedule.max()) {
             Some(lp) => lp,
             None => return Err(RMesgError::UnableToAddDurationToSystemTime),
         };
 
         Ok(KLogEntries {
+            #[cfg(any(feature = "sync", feature = "async"))]
             entries: Vec::new(),
-            poll_interval,
-            sleep_interval,
+            schedule,
+            #[cfg(any(feature = "sync", feature = "async"))]
             last_poll,
+            #[cfg(any(feature = "sync", feature = "async"))]
+            last_unread_size: None,
+            #[cfg(any(feature = "sync", feature = "async"))]
             clear,
-            last_timestamp: None,
+            #[cfg(any(feature = "sync", feature = "async"))]
+            last_read: Vec::new(),
+            loss: LossTracker::new(SelectedBackend::KLogCtl),
 
             #[cfg(feature = "async")]
             sleep_future: None,
         })
     }
 
+    /// The schedule polls are made on, with the interval until the next one
+    pub fn schedule(&self) -> &PollSchedule {
+        &self.schedule
+    }
+
+    /// The lines lost so far, because the ring buffer overflowed between two polls.
+    /// klogctl can't tell how many were lost, so every gap is of an unknown size.
+    pub fn loss_stats(&self) -> LossStats {
+        self.loss.stats()
+    }
+
     /// This method conducts the actual polling of the log buffer.
     ///
-    /// It tracks the timestamp of the last line buffered, and only adds lines
-    /// that have timestamps greater than that.
-    ///
-    /// Any lines without a timestamp are ignored. It is upto consumers to ensure timestamps
-    /// are set (possibly through the provided function `kernel_log_timestamps_enable`) before
-    /// polling/iterating.
+    /// It reads the entire buffer, skips the lines that overlap with the end of the
+    /// previous read, and adds the rest.
     ///
+    #[cfg(any(feature = "sync", feature = "async"))]
     fn poll(&mut self) -> Result<usize, RMesgError> {
         self.last_poll = SystemTime::now();
 
-        let mut entries = klog(self.clear)?;
-        let mut entriesadded: usize = 0;
-        match self.last_timestamp {
-            None => {
-                entriesadded += entries.len();
-                self.entries.append(&mut entries);
-            }
-            Some(last_timestamp) => {
-                while !entries.is_empty() {
-                    let entry = entries.remove(0);
-                    let skip = match entry.timestamp_from_system_start {
-                        // skip if entry timestamp is older than or equal to last timestamp
-                        Some(timestamp) => timestamp <= last_timestamp,
-                        // skip all without timestamp
-                        None => true,
-                    };
-
-                    if !skip {
-                        self.entries.push(entry);
-                        entriesadded += 1;
-                    }
+        let unread_size_changed = self.unread_size_changed();
+        if !unread_size_changed && !self.schedule.is_backed_off() {
+            self.schedule.record(0);
+            return Ok(0);
+        }
+
+        let (all_lines, buffer_size) = klog_read(self.clear)?;
+        let lines: Vec<&str> = all_lines.lines().collect();
+        let hashes: Vec<u64> = match self.clear {
+            true => Vec::new(),
+            false => lines.iter().map(|line| line_hash(line)).collect(),
+        };
+
+        // a buffer cleared on every read only ever holds lines not yet seen,
+        // but if it filled up in between, older ones were probably overwritten
+        let seen = match self.clear {
+            true => {
+                if is_full(all_lines.len(), buffer_size) {
+                    self.loss.gap(None);
                 }
+                0
             }
+            false => match overlap(&self.last_read, &hashes) {
+                Some(seen) => seen,
+                None => {
+                    self.loss.gap(None);
+                    0
+                }
+            },
         };
 
-        if let Some(entry) = self.entries.last() {
-            if entry.timestamp_from_system_start.is_some() {
-                self.last_timestamp = entry.timestamp_from_system_start;
+        let mut entriesadded: usize = 0;
+        for (index, line) in lines.iter().enumerate().skip(seen) {
+            match entry_from_line(line) {
+                Ok(entry) => self.entries.push(entry),
+                Err(source) => {
+                    return Err(RMesgError::EntryParsingError {
+                        line_number: Some(index + 1),
+                        source,
+                    })
+                }
             }
+            entriesadded += 1;
         }
 
+        self.last_read = hashes;
+
+        self.schedule.record(entriesadded);
         Ok(entriesadded)
     }
+
+    /// Cheaply checks whether anything may have been logged since the last poll.
+    #[cfg(any(feature = "sync", feature = "async"))]
+    fn unread_size_changed(&mut self) -> bool {
+        let unread_size = match klog_unread_size() {
+            Ok(size) => size,
+            // not permitted (or not supported): assume it changed, and read the buffer
+            Err(_) => return true,
+        };
+
+        let previous = self.last_unread_size.replace(unread_size);
+        unread_size == 0 || previous != Some(unread_size)
+    }
+}
+
+/// Whether a read of `bytes_read` filled (nearly) the whole buffer. The formatted lines returned
+/// aren't stored in the buffer as-is, so this leaves some slack.
+#[cfg(any(feature = "sync", feature = "async"))]
+fn is_full(bytes_read: usize, buffer_size: usize) -> bool {
+    bytes_read > 0 && bytes_read >= buffer_size - buffer_size / 10
+}
+
+/// Lines are remembered between reads by their hash, rather than kept whole
+#[cfg(any(feature = "sync", feature = "async"))]
+fn line_hash(line: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    line.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Returns how many lines at the start of `current` were already read at the end of `previous`,
+/// or None when they don't overlap at all (none of the previous lines are left in the buffer).
+/// Both are given as line hashes.
+#[cfg(any(feature = "sync", feature = "async"))]
+fn overlap(previous: &[u64], current: &[u64]) -> Option<usize> {
+    // nothing was read before, or the buffer was emptied since
+    if previous.is_empty() || current.is_empty() {
+        return Some(0);
+    }
+
+    // Knuth-Morris-Pratt, searching `previous` for `current`: after the last line of `previous`,
+    // the length matched is that of its longest suffix `current` starts with.
+    // fallback[i] is the length of the longest proper prefix of current[..=i] that is also its suffix
+    let mut fallback = vec![0; current.len()];
+    let mut matched = 0;
+    for (i, hash) in current.iter().enumerate().skip(1) {
+        while matched > 0 && *hash != current[matched] {
+            matched = fallback[matched - 1];
+        }
+        if *hash == current[matched] {
+            matched += 1;
+        }
+        fallback[i] = matched;
+    }
+
+    matched = 0;
+    for hash in previous {
+        while matched > 0 && (matched == current.len() || *hash != current[matched]) {
+            matched = fallback[matched - 1];
+        }
+        if *hash == current[matched] {
+            matched += 1;
+        }
+    }
+
+    match matched {
+        0 => None,
+        seen => Some(seen),
+    }
 }
 
 /// Trait to iterate over lines of the kernel log buffer.
@@ -228,13 +432,15 @@ impl Iterator for KLogEntries {
             // Poll once if entering next and time since last poll
             // is greater than interval
             // This prevents lots of calls to next from hitting the kernel.
-            if elapsed >= self.poll_interval {
-                // poll once anyway
-                if let Err(e) = self.poll() {
-                    return Some(Err(e));
+            match self.schedule.current().checked_sub(elapsed) {
+                // Poll once the interval since the last poll has passed
+                None | Some(Duration::ZERO) => {
+                    if let Err(e) = self.poll() {
+                        return Some(Err(e));
+                    }
                 }
-            } else {
-                thread::sleep(self.sleep_interval);
+                // Otherwise sleep until it has
+                Some(remaining) => thread::sleep(remaining),
             }
         }
 
@@ -242,6 +448,13 @@ impl Iterator for KLogEntries {
     }
 }
 
+#[cfg(feature = "sync")]
+impl LogSource for KLogEntries {
+    fn loss_stats(&self) -> LossStats {
+        KLogEntries::loss_stats(self)
+    }
+}
+
 /// Trait to iterate over lines of the kernel log buffer.
 #[cfg(feature = "async")]
 impl Stream for KLogEntries {
@@ -269,16 +482,19 @@ impl Stream for KLogEntries {
             };
 
             // Did enough time pass since last poll? If so try to poll
-            if elapsed >= self.poll_interval {
-                if let Err(e) = self.poll() {
-                    return Poll::Ready(Some(Err(e)));
+            match self.schedule.current().checked_sub(elapsed) {
+                None | Some(Duration::ZERO) => {
+                    if let Err(e) = self.poll() {
+                        return Poll::Ready(Some(Err(e)));
+                    }
                 }
-            } else {
-                let sf = tokiotime::sleep(self.sleep_interval);
-                let mut pinned_sf = Box::pin(sf);
-                if Future::poll(pinned_sf.as_mut(), cx).is_pending() {
-                    self.sleep_future = Some(pinned_sf);
-                    return Poll::Pending;
+                Some(remaining) => {
+                    let sf = tokiotime::sleep(remaining);
+                    let mut pinned_sf = Box::pin(sf);
+                    if Future::poll(pinned_sf.as_mut(), cx).is_pending() {
+                        self.sleep_future = Some(pinned_sf);
+                        return Poll::Pending;
+                    }
                 }
             }
         }
@@ -287,6 +503,13 @@ impl Stream for KLogEntries {
     }
 }
 
+#[cfg(feature = "async")]
+impl AsyncLogSource for KLogEntries {
+    fn loss_stats(&self) -> LossStats {
+        KLogEntries::loss_stats(self)
+    }
+}
+
 /// This is the key safe function that makes the klogctl syslog call with parameters.
 /// While the internally used function supports all klogctl parameters, this function
 /// only provides one bool parameter which indicates whether the buffer is to be cleared
@@ -296,9 +519,12 @@ impl Stream for KLogEntries {
 /// whether or not "async" feature is enabled
 ///
 pub fn klog_raw(clear: bool) -> Result<String, RMesgError> {
-    let mut dummy_buffer: Vec<u8> = vec![0; 0];
-    let kernel_buffer_size =
-        safely_wrapped_klogctl(KLogType::SyslogActionSizeBuffer, &mut dummy_buffer)?;
+    Ok(klog_read(clear)?.0)
+}
+
+/// Reads the buffer, and returns its contents along with the size of the buffer
+fn klog_read(clear: bool) -> Result<(String, usize), RMesgError> {
+    let kernel_buffer_size = klog_buffer_size()?;
 
     let klogtype = match clear {
         true => KLogType::SyslogActionReadClear,
@@ -313,7 +539,7 @@ pub fn klog_raw(clear: bool) -> Result<String, RMesgError> {
     let utf8_str = String::from_utf8(real_buffer)?;
 
     // if incremental,
-    Ok(utf8_str)
+    Ok((utf8_str, kernel_buffer_size))
 }
 
 /// This is the key safe function that makes the klogctl syslog call with parameters.
@@ -326,12 +552,30 @@ pub fn klog_raw(clear: bool) -> Result<String, RMesgError> {
 ///
 pub fn klog(clear: bool) -> Result<Vec<Entry>, RMesgError> {
     let all_lines = klog_raw(clear)?;
-    Ok(entries_from_lines(&all_lines)?)
+    common::entries_from_numbered_lines(&all_lines, entry_from_line)
+}
+
+/// Returns the size of the kernel log buffer, in bytes.
+pub fn klog_buffer_size() -> Result<usize, RMesgError> {
+    let mut dummy_buffer: Vec<u8> = vec![0; 0];
+    safely_wrapped_klogctl(KLogType::SyslogActionSizeBuffer, &mut dummy_buffer)
+}
+
+/// Returns the number of bytes in the kernel log buffer not yet consumed through
+/// `SyslogActionRead` (i.e. /proc/kmsg). Requires CAP_SYSLOG.
+pub fn klog_unread_size() -> Result<usize, RMesgError> {
+    let mut dummy_buffer: Vec<u8> = vec![0; 0];
+    safely_wrapped_klogctl(KLogType::SyslogActionSizeUnread, &mut dummy_buffer)
 }
 
 /// This function checks whether or not timestamps are enabled in the Linux Kernel log entries.
 pub fn klog_timestamps_enabled() -> Result<bool, RMesgError> {
-    Ok(fs::read_to_string(SYS_MODULE_PRINTK_PARAMETERS_TIME)?
+    klog_timestamps_enabled_in(&SystemPaths::default())
+}
+
+/// Same as `klog_timestamps_enabled`, with printk's parameters found through `paths`.
+pub fn klog_timestamps_enabled_in(paths: &SystemPaths) -> Result<bool, RMesgError> {
+    Ok(fs::read_to_string(paths.printk_time())?
         .trim()
         .to_uppercase()
         == "Y")
@@ -339,8 +583,13 @@ pub fn klog_timestamps_enabled() -> Result<bool, RMesgError> {
 
 /// This function can enable or disable whether or not timestamps are enabled in the Linux Kernel log entries.
 pub fn klog_timestamps_enable(desired: bool) -> Result<(), RMesgError> {
+    klog_timestamps_enable_in(&SystemPaths::default(), desired)
+}
+
+/// Same as `klog_timestamps_enable`, with printk's parameters found through `paths`.
+pub fn klog_timestamps_enable_in(paths: &SystemPaths, desired: bool) -> Result<(), RMesgError> {
     Ok(fs::write(
-        SYS_MODULE_PRINTK_PARAMETERS_TIME,
+        paths.printk_time(),
         match desired {
             true => "Y\n",
             false => "N\n",
@@ -379,6 +628,7 @@ pub fn entry_from_line(line: &str) -> Result<Entry, EntryParsingError> {
             level,
             sequence_num: None,
             timestamp_from_system_start,
+            wallclock_time: None,
             message,
         })
     } else {
@@ -387,6 +637,7 @@ pub fn entry_from_line(line: &str) -> Result<Entry, EntryParsingError> {
             level: None,
             sequence_num: None,
             timestamp_from_system_start: None,
+            wallclock_time: None,
             message: line.to_owned(),
         })
     }
@@ -423,13 +674,23 @@ pub fn safely_wrapped_klogctl(klogtype: KLogType, buf_u8: &mut [u8]) -> Result<u
     if response_cint < 0 {
         let err = errno();
 
-        if err.0 == libc::EPERM {
-            return Err(RMesgError::OperationNotPermitted(format!("{}", klogtype)));
+        if permissions::is_denial(Some(err.0)) {
+            // only reading everything and querying the size are allowed without CAP_SYSLOG
+            let requires_cap_syslog = !matches!(
+                klogtype,
+                KLogType::SyslogActionReadAll | KLogType::SyslogActionSizeBuffer
+            );
+            return Err(permissions::denied(
+                format!("{}", klogtype),
+                None,
+                std::io::Error::from_raw_os_error(err.0),
+                requires_cap_syslog,
+            ));
         } else {
-            return Err(RMesgError::InternalError(format!(
-                "Request ({}) to klogctl failed. errno={}",
-                klogtype, err
-            )));
+            return Err(RMesgError::KLogCtlError {
+                request: klogtype,
+                errno: err,
+            });
         }
     }
 
@@ -517,23 +778,122 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_timestamps_enable() {
+        let paths = crate::paths::temp_root("timestamps");
+        fs::write(paths.printk_time(), "Y\n").unwrap();
+        assert!(klog_timestamps_enabled_in(&paths).unwrap());
+
+        klog_timestamps_enable_in(&paths, false).unwrap();
+        assert!(!klog_timestamps_enabled_in(&paths).unwrap());
+        assert_eq!(fs::read_to_string(paths.printk_time()).unwrap(), "N\n");
+    }
+
+    #[cfg(any(feature = "sync", feature = "async"))]
+    #[test]
+    fn test_poll_schedule() {
+        let min = Duration::from_millis(100);
+        let max = Duration::from_millis(1000);
+        let mut schedule = PollSchedule::adaptive(min, max);
+        assert_eq!(schedule.current(), min);
+
+        // back off while idle, up to the maximum
+        for _ in 0..3 {
+            schedule.record(0);
+        }
+        assert_eq!(schedule.current(), Duration::from_millis(800));
+        schedule.record(0);
+        assert_eq!(schedule.current(), max);
+        assert!(schedule.is_backed_off());
+
+        // and speed right back up when entries arrive
+        schedule.record(5);
+        assert_eq!(schedule.current(), min);
+
+        let mut fixed = PollSchedule::fixed(max);
+        fixed.record(0);
+        assert_eq!(fixed.current(), max);
+        assert_eq!(PollSchedule::adaptive(max, min).max(), max);
+
+        // a zero minimum still backs off
+        let mut from_zero = PollSchedule::adaptive(Duration::ZERO, max);
+        from_zero.record(0);
+        assert_eq!(from_zero.current(), Duration::from_millis(2));
+        for _ in 0..10 {
+            from_zero.record(0);
+        }
+        assert_eq!(from_zero.current(), max);
+        from_zero.record(1);
+        assert_eq!(from_zero.current(), Duration::ZERO);
+
+        let mut zero = PollSchedule::fixed(Duration::ZERO);
+        zero.record(0);
+        assert_eq!(zero.current(), Duration::ZERO);
+    }
+
+    #[cfg(any(feature = "sync", feature = "async"))]
+    #[test]
+    fn test_is_full() {
+        assert!(is_full(1000, 1000));
+        assert!(is_full(950, 1000));
+        assert!(!is_full(500, 1000));
+        assert!(!is_full(0, 0));
+    }
+
+    #[cfg(any(feature = "sync", feature = "async"))]
+    #[test]
+    fn test_overlap() {
+        let hashes = |lines: &[&str]| -> Vec<u64> { lines.iter().map(|l| line_hash(l)).collect() };
+        let previous = hashes(&["a", "b", "b"]);
+
+        // nothing new
+        assert_eq!(overlap(&previous, &hashes(&["a", "b", "b"])), Some(3));
+        // new lines appended, and the oldest ones overwritten
+        assert_eq!(overlap(&previous, &hashes(&["a", "b", "b", "c"])), Some(3));
+        assert_eq!(overlap(&previous, &hashes(&["b", "b", "c", "d"])), Some(2));
+        assert_eq!(overlap(&previous, &hashes(&["b", "c"])), Some(1));
+        // all previous lines overwritten: a gap
+        assert_eq!(overlap(&previous, &hashes(&["c", "d"])), None);
+        // first read, or buffer cleared
+        assert_eq!(overlap(&[], &hashes(&["a"])), Some(0));
+        assert_eq!(overlap(&previous, &[]), Some(0));
+
+        // repeated lines: the longest overlap is assumed
+        let repeated = hashes(&["x", "a", "a", "a"]);
+        assert_eq!(overlap(&repeated, &hashes(&["a", "a", "a", "a"])), Some(3));
+        assert_eq!(overlap(&repeated, &hashes(&["a", "a", "b"])), Some(2));
+        assert_eq!(
+            overlap(
+                &hashes(&["a", "b", "a", "b"]),
+                &hashes(&["a", "b", "a", "c"])
+            ),
+            Some(2)
+        );
+
+        // a large buffer with a few new lines
+        let lines: Vec<String> = (0..20_000).map(|n| format!("line {}", n)).collect();
+        let previous: Vec<u64> = lines[..15_000].iter().map(|l| line_hash(l)).collect();
+        let current: Vec<u64> = lines[5_000..].iter().map(|l| line_hash(l)).collect();
+        assert_eq!(overlap(&previous, &current), Some(10_000));
+    }
+
     #[test]
     fn test_parse_serialize() {
         let line1 = "<6>a.out[4054]: segfault at 7ffd5503d358 ip 00007ffd5503d358 sp 00007ffd5503d258 error 15";
         let entries1 = entries_from_lines(line1).unwrap();
-        let e1r = entries1.get(0).unwrap();
+        let e1r = entries1.first().unwrap();
         let line1again = e1r.to_klog_str().unwrap();
         assert_eq!(line1, line1again);
 
         let line2 = "<7>[   233434.343533] a.out[4054]: segfault at 7ffd5503d358 ip 00007ffd5503d358 sp 00007ffd5503d258 error 15";
         let entries2 = entries_from_lines(line2).unwrap();
-        let e2r = entries2.get(0).unwrap();
+        let e2r = entries2.first().unwrap();
         let line2again = e2r.to_klog_str().unwrap();
         assert_eq!(line2, line2again);
 
         let line3 = "233434.343533] a.out[4054]: segfault at 7ffd5503d358 ip 00007ffd5503d358 sp 00007ffd5503d258 error 15";
         let entries3 = entries_from_lines(line3).unwrap();
-        let e3r = entries3.get(0).unwrap();
+        let e3r = entries3.first().unwrap();
         let line3again = e3r.to_klog_str().unwrap();
         assert_eq!(line3, line3again);
     }
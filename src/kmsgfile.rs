@@ -7,11 +7,24 @@ use crate::entry::{Entry, EntryParsingError};
 /// This allows Rust programs to consume dmesg-like output programmatically.
 ///
 use crate::error::RMesgError;
+#[cfg(any(feature = "sync", feature = "async"))]
+use crate::loss::{LossStats, LossTracker};
+use crate::paths::SystemPaths;
+use crate::permissions;
+#[cfg(feature = "async")]
+use crate::source::AsyncLogSource;
+#[cfg(feature = "sync")]
+use crate::source::LogSource;
+#[cfg(any(feature = "sync", feature = "async"))]
+use crate::SelectedBackend;
 
 use lazy_static::lazy_static;
 use nonblock::NonBlockingReader;
 use regex::Regex;
 use std::fs as stdfs;
+use std::io::{Seek, SeekFrom};
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
 
 #[cfg(feature = "sync")]
 use std::io as stdio;
@@ -31,9 +44,13 @@ use tokio::fs as tokiofs;
 #[cfg(feature = "async")]
 use tokio::io as tokioio;
 #[cfg(feature = "async")]
-use tokio::io::AsyncBufReadExt;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt};
+
+pub const DEV_KMSG_PATH: &str = "/dev/kmsg";
 
-const DEV_KMSG_PATH: &str = "/dev/kmsg";
+/// Reads from /dev/kmsg fail with EINVAL when the buffer can't hold a whole record
+/// (CONSOLE_EXT_LOG_MAX in the kernel)
+pub const KMSG_RECORD_MAX: usize = 8192;
 lazy_static! {
     static ref RE_ENTRY_WITH_TIMESTAMP: Regex = Regex::new(
         r"(?x)^
@@ -57,10 +74,14 @@ lazy_static! {
 ///
 /// Implements the synchronous std::iter::Iterator trait
 ///
+/// Records overwritten before they could be read are skipped (rather than failing with EPIPE),
+/// and accounted for in `loss_stats`.
+///
 #[cfg(feature = "sync")]
 pub struct KMsgEntriesIter {
     raw: bool,
     lines_iter: stdio::Lines<stdio::BufReader<stdfs::File>>,
+    loss: LossTracker,
 }
 
 #[cfg(feature = "sync")]
@@ -74,23 +95,25 @@ impl KMsgEntriesIter {
         let file = match stdfs::File::open(path) {
             Ok(fc) => fc,
             Err(e) => {
-                if e.raw_os_error() == Some(libc::EPERM) {
-                    return Err(RMesgError::OperationNotPermitted(format!(
-                        "Open File {}",
-                        path
-                    )));
-                } else {
-                    return Err(RMesgError::DevKMsgFileOpenError(format!(
-                        "Unable to open file {}: {}",
-                        path, e
-                    )));
-                }
+                return Err(open_error(path, e));
             }
         };
 
-        let lines_iter = stdio::BufReader::new(file).lines();
+        Ok(Self::from_file(file, raw))
+    }
+
+    /// Reads from a kmsg file that's already open (and positioned), e.g. by the backend probe
+    pub(crate) fn from_file(file: stdfs::File, raw: bool) -> Self {
+        Self {
+            raw,
+            lines_iter: stdio::BufReader::new(file).lines(),
+            loss: LossTracker::new(SelectedBackend::DevKMsg),
+        }
+    }
 
-        Ok(Self { raw, lines_iter })
+    /// The records lost so far, because they were overwritten before they could be read
+    pub fn loss_stats(&self) -> LossStats {
+        self.loss.stats()
     }
 }
 
@@ -103,18 +126,31 @@ impl Iterator for KMsgEntriesIter {
     /// NOT a thread-safe method either. It is suggested this method be always
     /// blocked on to ensure no messages are missed.
     fn next(&mut self) -> Option<Self::Item> {
-        match self.lines_iter.next() {
+        let mut next_line = self.lines_iter.next();
+
+        // skip past records that were overwritten; the next read starts at the oldest one left
+        while let Some(Err(e)) = &next_line {
+            if e.raw_os_error() != Some(libc::EPIPE) {
+                break;
+            }
+            self.loss.overwritten();
+            next_line = self.lines_iter.next();
+        }
+
+        match next_line {
             None => None,
-            Some(Err(e)) => Some(Err(RMesgError::IOError(format!(
-                "Error reading next line from kernel log device file: {}",
-                e
-            )))),
+            Some(Err(e)) => Some(Err(RMesgError::IOError(e))),
             Some(Ok(line)) => {
+                if let Some(sequence_num) = sequence_num_of(&line) {
+                    self.loss.sequence_num(sequence_num);
+                }
+
                 if self.raw {
                     Some(Ok(Entry {
                         facility: None,
                         level: None,
                         timestamp_from_system_start: None,
+                        wallclock_time: None,
                         sequence_num: None,
                         message: line,
                     }))
@@ -126,6 +162,13 @@ impl Iterator for KMsgEntriesIter {
     }
 }
 
+#[cfg(feature = "sync")]
+impl LogSource for KMsgEntriesIter {
+    fn loss_stats(&self) -> LossStats {
+        KMsgEntriesIter::loss_stats(self)
+    }
+}
+
 /// While reading the kernel log buffer is very useful in and of itself (especially when running the CLI),
 /// a lot more value is unlocked when it can be tailed line-by-line.
 ///
@@ -134,11 +177,15 @@ impl Iterator for KMsgEntriesIter {
 ///
 /// Implements the tokio::stream::Stream trait
 ///
+/// Records overwritten before they could be read are skipped (rather than failing with EPIPE),
+/// and accounted for in `loss_stats`.
+///
 #[cfg(feature = "async")]
 pub struct KMsgEntriesStream {
     raw: bool,
 
     lines_stream: Pin<Box<tokioio::Lines<tokioio::BufReader<tokiofs::File>>>>,
+    loss: LossTracker,
 }
 
 #[cfg(feature = "async")]
@@ -152,39 +199,51 @@ impl KMsgEntriesStream {
     ) -> Result<Self, RMesgError> {
         let path = file_override.as_deref().unwrap_or(DEV_KMSG_PATH);
 
-        let file = match tokiofs::File::open(path).await {
+        let mut file = match tokiofs::File::open(path).await {
             Ok(fc) => fc,
             Err(e) => {
-                if e.raw_os_error() == Some(libc::EPERM) {
-                    return Err(RMesgError::OperationNotPermitted(format!(
-                        "Open File {}",
-                        path
-                    )));
-                } else {
-                    return Err(RMesgError::DevKMsgFileOpenError(format!(
-                        "Unable to open file {}: {}",
-                        path, e
-                    )));
-                }
+                return Err(open_error(path, e));
             }
         };
 
-        // try to read from file
-        let mut lines_stream = Box::pin(tokioio::BufReader::new(file).lines());
-
-        //read a line
-        if let Err(e) = lines_stream.next_line().await {
-            return Err(RMesgError::DevKMsgFileOpenError(format!(
-                "Unable to read from file {}: {}",
-                path, e
-            )));
+        // try to read a record from file, then rewind to the first record
+        // (/dev/kmsg supports seeking to the start) rather than opening it twice
+        let mut record = vec![0u8; KMSG_RECORD_MAX];
+        if let Err(e) = file.read(&mut record).await {
+            // EPIPE only means the first record was overwritten; the file is readable
+            if e.raw_os_error() != Some(libc::EPIPE) {
+                return Err(RMesgError::DevKMsgFileOpenError {
+                    path: path.to_owned(),
+                    source: e,
+                });
+            }
+        }
+        if let Err(e) = file.seek(SeekFrom::Start(0)).await {
+            return Err(RMesgError::DevKMsgFileOpenError {
+                path: path.to_owned(),
+                source: e,
+            });
         }
 
-        // create a new lines_stream with a new file
-        let lines_stream =
-            Box::pin(tokioio::BufReader::new(tokiofs::File::open(path).await?).lines());
+        Ok(Self::from_tokio_file(file, raw))
+    }
+
+    /// Reads from a kmsg file that's already open (and positioned), e.g. by the backend probe
+    pub(crate) fn from_file(file: stdfs::File, raw: bool) -> Self {
+        Self::from_tokio_file(tokiofs::File::from_std(file), raw)
+    }
+
+    fn from_tokio_file(file: tokiofs::File, raw: bool) -> Self {
+        Self {
+            raw,
+            lines_stream: Box::pin(tokioio::BufReader::new(file).lines()),
+            loss: LossTracker::new(SelectedBackend::DevKMsg),
+        }
+    }
 
-        Ok(Self { raw, lines_stream })
+    /// The records lost so far, because they were overwritten before they could be read
+    pub fn loss_stats(&self) -> LossStats {
+        self.loss.stats()
     }
 }
 
@@ -194,16 +253,32 @@ impl Stream for KMsgEntriesStream {
     type Item = Result<Entry, RMesgError>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        match self.lines_stream.as_mut().poll_next_line(cx) {
+        let mut next_line = self.lines_stream.as_mut().poll_next_line(cx);
+
+        // skip past records that were overwritten; the next read starts at the oldest one left
+        while let Poll::Ready(Err(e)) = &next_line {
+            if e.raw_os_error() != Some(libc::EPIPE) {
+                break;
+            }
+            self.loss.overwritten();
+            next_line = self.lines_stream.as_mut().poll_next_line(cx);
+        }
+
+        match next_line {
             Poll::Pending => Poll::Pending,
             Poll::Ready(Err(e)) => Poll::Ready(Some(Err(e.into()))),
             Poll::Ready(Ok(None)) => Poll::Ready(None),
             Poll::Ready(Ok(Some(line))) => {
+                if let Some(sequence_num) = sequence_num_of(&line) {
+                    self.loss.sequence_num(sequence_num);
+                }
+
                 let value = if self.raw {
                     Some(Ok(Entry {
                         facility: None,
                         level: None,
                         timestamp_from_system_start: None,
+                        wallclock_time: None,
                         sequence_num: None,
                         message: line,
                     }))
@@ -217,49 +292,93 @@ impl Stream for KMsgEntriesStream {
     }
 }
 
+#[cfg(feature = "async")]
+impl AsyncLogSource for KMsgEntriesStream {
+    fn loss_stats(&self) -> LossStats {
+        KMsgEntriesStream::loss_stats(self)
+    }
+}
+
 pub fn kmsg_raw(file_override: Option<String>) -> Result<String, RMesgError> {
     let path = file_override.as_deref().unwrap_or(DEV_KMSG_PATH);
 
     let file = match stdfs::File::open(path) {
         Ok(fc) => fc,
         Err(e) => {
-            if e.raw_os_error() == Some(libc::EPERM) {
-                return Err(RMesgError::OperationNotPermitted(format!(
-                    "Open File {}",
-                    path
-                )));
-            } else {
-                return Err(RMesgError::DevKMsgFileOpenError(format!(
-                    "Unable to open file {}: {}",
-                    path, e
-                )));
-            }
+            return Err(open_error(path, e));
         }
     };
 
+    read_available(path, file)
+}
+
+/// Same as `kmsg_raw`, from a kmsg file that's already open (and positioned), e.g. by the
+/// backend probe
+pub(crate) fn kmsg_raw_from_file(file: stdfs::File) -> Result<String, RMesgError> {
+    read_available(DEV_KMSG_PATH, file)
+}
+
+fn read_available(path: &str, file: stdfs::File) -> Result<String, RMesgError> {
     let mut noblock_file = NonBlockingReader::from_fd(file)?;
 
     let mut file_contents = String::new();
     match noblock_file.read_available_to_string(&mut file_contents) {
         Ok(_) => {}
         Err(e) => {
-            if e.raw_os_error() == Some(libc::EPERM) {
-                return Err(RMesgError::OperationNotPermitted(format!(
-                    "Read from File {}",
-                    path
-                )));
-            } else {
-                return Err(RMesgError::DevKMsgFileOpenError(format!(
-                    "Unable to read from file {}: {}",
-                    path, e
-                )));
-            }
+            return Err(open_error(path, e));
         }
     }
 
     Ok(file_contents)
 }
 
+/// Readies a kmsg file that was opened without blocking and read from (as the backend probe
+/// does) for a reader: rewound to the first record, with reads blocking again.
+pub(crate) fn rewind(file: &mut stdfs::File) -> Result<(), std::io::Error> {
+    file.seek(SeekFrom::Start(0))?;
+
+    #[cfg(unix)]
+    {
+        let fd = file.as_raw_fd();
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// The sequence number of a record, without parsing the rest of it.
+/// Continuation lines (which start with a space) don't have one.
+pub(crate) fn sequence_num_of(line: &str) -> Option<u64> {
+    if line.starts_with(char::is_whitespace) {
+        return None;
+    }
+
+    let (prefix, _) = line.split_once(';')?;
+    prefix.split(',').nth(1)?.trim().parse().ok()
+}
+
+/// Maps an error opening or reading from the kmsg file into an RMesgError,
+/// diagnosing permission denials.
+pub(crate) fn open_error(path: &str, e: std::io::Error) -> RMesgError {
+    open_error_in(&SystemPaths::default(), path, e)
+}
+
+/// Same as `open_error`, diagnosed through `paths`.
+pub(crate) fn open_error_in(paths: &SystemPaths, path: &str, e: std::io::Error) -> RMesgError {
+    if permissions::is_denial(e.raw_os_error()) {
+        let operation = format!("Open File {}", path);
+        permissions::denied_in(paths, operation, Some(path), e, false)
+    } else {
+        RMesgError::DevKMsgFileOpenError {
+            path: path.to_owned(),
+            source: e,
+        }
+    }
+}
+
 /// This is the key safe function that makes the klogctl syslog call with parameters.
 /// While the internally used function supports all klogctl parameters, this function
 /// only provides one bool parameter which indicates whether the buffer is to be cleared
@@ -270,10 +389,14 @@ pub fn kmsg_raw(file_override: Option<String>) -> Result<String, RMesgError> {
 ///
 pub fn kmsg(file_override: Option<String>) -> Result<Vec<Entry>, RMesgError> {
     let file_contents = kmsg_raw(file_override)?;
-    let entry_results: Result<Vec<Entry>, EntryParsingError> =
-        file_contents.lines().map(entry_from_line).collect();
+    common::entries_from_numbered_lines(&file_contents, entry_from_line)
+}
 
-    Ok(entry_results?)
+/// Same as `kmsg`, from a kmsg file that's already open (and positioned), e.g. by the
+/// backend probe
+pub(crate) fn kmsg_from_file(file: stdfs::File) -> Result<Vec<Entry>, RMesgError> {
+    let file_contents = kmsg_raw_from_file(file)?;
+    common::entries_from_numbered_lines(&file_contents, entry_from_line)
 }
 
 // Message spec: https://github.com/torvalds/linux/blob/master/Documentation/ABI/testing/dev-kmsg
@@ -308,6 +431,7 @@ pub fn entry_from_line(line: &str) -> Result<Entry, EntryParsingError> {
             level,
             sequence_num,
             timestamp_from_system_start,
+            wallclock_time: None,
             message,
         })
     } else {
@@ -316,6 +440,7 @@ pub fn entry_from_line(line: &str) -> Result<Entry, EntryParsingError> {
             level: None,
             sequence_num: None,
             timestamp_from_system_start: None,
+            wallclock_time: None,
             message: line.to_owned(),
         })
     }
@@ -382,6 +507,16 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_sequence_num_of() {
+        assert_eq!(
+            sequence_num_of("6,779,91650777797,-;docker0: port 2 entered disabled state"),
+            Some(779)
+        );
+        assert_eq!(sequence_num_of(" LINE 3 = foobar ; with semicolon"), None);
+        assert_eq!(sequence_num_of("garbage"), None);
+    }
+
     #[test]
     fn test_parse_serialize() {
         let line1 = " LINE2=foobar";
@@ -1,11 +1,57 @@
 mod common;
 
+/// Analysis (turns entries into structured reports, e.g. kernel crashes)
+pub mod analysis;
+/// Buffer introspection (size, sequence numbers and settings of the kernel log buffer)
+pub mod bufferinfo;
+/// Capture Implementation (records entries to a file and replays them with their original timing)
+pub mod capture;
+/// dmesg Implementation (parses the human-readable output of the util-linux dmesg utility)
+pub mod dmesg;
 pub mod entry;
 pub mod error;
+/// Events Implementation (structured diagnostics instead of writing to stderr)
+pub mod events;
+/// Userspace faults (segfault and trap lines parsed into structured faults)
+pub mod fault;
 /// KLog Implementation (makes klogctl aka syslog system call through libc)
 pub mod klogctl;
 /// KMsg Implementation (reads from the /dev/kmsg file)
 pub mod kmsgfile;
+/// Loss accounting (entries lost to ring buffer overflows)
+pub mod loss;
+/// Mock Implementation (replays a scripted sequence of lines, delays and errors)
+pub mod mock;
+/// Network events (link state, renames, promiscuous mode and transmit timeouts)
+pub mod net;
+/// Netfilter packet logs (packets logged by iptables/nftables LOG rules)
+pub mod netfilter;
+/// OOM killer events (the OOM killer's output assembled into events)
+pub mod oom;
+/// Kernel crash reports (oops, BUG and panic reports grouped into incidents)
+pub mod oops;
+/// System paths (where /dev/kmsg, /sys/module/printk and /proc/sys/kernel are found)
+pub mod paths;
+/// Permissions diagnosis (explains why access to the kernel log was denied)
+pub mod permissions;
+/// printk settings (console log levels and /dev/kmsg access)
+pub mod printk;
+/// Backend probing (which backends are usable on this system, and why)
+pub mod probe;
+/// Security events (SELinux AVC, AppArmor and seccomp audit records, correlated into events)
+pub mod security;
+/// LogSource traits (implemented by every backend, and open for custom ones)
+pub mod source;
+/// Stall detection (hung tasks, soft/hard lockups and RCU stalls)
+pub mod stall;
+/// Storage errors (block device and filesystem errors, tallied per disk)
+pub mod storage;
+/// Time window filtering (keeps the entries logged between two points in time)
+pub mod timewindow;
+/// USB devices (plugged in and out, with a table of the devices attached)
+pub mod usb;
+/// Kernel warnings (WARN() splats, with signatures to count repeats by)
+pub mod warning;
 
 #[cfg(feature = "sync")]
 use std::iter::Iterator;
@@ -19,6 +65,8 @@ use futures::task::{Context, Poll};
 #[cfg(feature = "async")]
 use pin_project::pin_project;
 
+use strum_macros::Display;
+
 #[derive(Clone, Copy, Debug)]
 pub enum Backend {
     Default,
@@ -26,10 +74,20 @@ pub enum Backend {
     DevKMsg,
 }
 
+/// The backend that was actually used to read the logs.
+/// This may differ from the requested `Backend` (always so for `Backend::Default`).
+#[derive(Clone, Copy, Debug, PartialEq, Display)]
+pub enum SelectedBackend {
+    KLogCtl,
+    DevKMsg,
+    Custom,
+}
+
 #[cfg(feature = "sync")]
 pub enum EntriesIterator {
     KLogCtl(klogctl::KLogEntries),
     DevKMsg(kmsgfile::KMsgEntriesIter),
+    Custom(Box<dyn source::LogSource + Send>),
 }
 #[cfg(feature = "sync")]
 impl Iterator for EntriesIterator {
@@ -38,8 +96,35 @@ impl Iterator for EntriesIterator {
         match self {
             Self::KLogCtl(k) => k.next(),
             Self::DevKMsg(d) => d.next(),
+            Self::Custom(c) => c.next(),
+        }
+    }
+}
+#[cfg(feature = "sync")]
+impl source::LogSource for EntriesIterator {
+    fn loss_stats(&self) -> loss::LossStats {
+        match self {
+            Self::KLogCtl(k) => k.loss_stats(),
+            Self::DevKMsg(d) => d.loss_stats(),
+            Self::Custom(c) => c.loss_stats(),
+        }
+    }
+}
+#[cfg(feature = "sync")]
+impl EntriesIterator {
+    pub fn selected_backend(&self) -> SelectedBackend {
+        match self {
+            Self::KLogCtl(_) => SelectedBackend::KLogCtl,
+            Self::DevKMsg(_) => SelectedBackend::DevKMsg,
+            Self::Custom(_) => SelectedBackend::Custom,
         }
     }
+
+    /// The entries lost so far, e.g. to tell whether the logs read are complete.
+    /// Every gap is also raised as an `events::RMesgEvent::EntriesLost`.
+    pub fn loss_stats(&self) -> loss::LossStats {
+        source::LogSource::loss_stats(self)
+    }
 }
 
 #[pin_project(project = EntriesStreamPinnedProjection)]
@@ -47,6 +132,7 @@ impl Iterator for EntriesIterator {
 pub enum EntriesStream {
     KLogCtl(#[pin] klogctl::KLogEntries),
     DevKMsg(#[pin] kmsgfile::KMsgEntriesStream),
+    Custom(Pin<Box<dyn source::AsyncLogSource + Send>>),
 }
 #[cfg(feature = "async")]
 impl Stream for EntriesStream {
@@ -55,115 +141,195 @@ impl Stream for EntriesStream {
         match self.project() {
             EntriesStreamPinnedProjection::KLogCtl(k) => k.poll_next(cx),
             EntriesStreamPinnedProjection::DevKMsg(d) => d.poll_next(cx),
+            EntriesStreamPinnedProjection::Custom(c) => c.as_mut().poll_next(cx),
+        }
+    }
+}
+#[cfg(feature = "async")]
+impl source::AsyncLogSource for EntriesStream {
+    fn loss_stats(&self) -> loss::LossStats {
+        match self {
+            Self::KLogCtl(k) => k.loss_stats(),
+            Self::DevKMsg(d) => d.loss_stats(),
+            Self::Custom(c) => c.loss_stats(),
+        }
+    }
+}
+#[cfg(feature = "async")]
+impl EntriesStream {
+    pub fn selected_backend(&self) -> SelectedBackend {
+        match self {
+            Self::KLogCtl(_) => SelectedBackend::KLogCtl,
+            Self::DevKMsg(_) => SelectedBackend::DevKMsg,
+            Self::Custom(_) => SelectedBackend::Custom,
         }
     }
+
+    /// The entries lost so far, e.g. to tell whether the logs read are complete.
+    /// Every gap is also raised as an `events::RMesgEvent::EntriesLost`.
+    pub fn loss_stats(&self) -> loss::LossStats {
+        source::AsyncLogSource::loss_stats(self)
+    }
 }
 
 pub fn log_entries(b: Backend, clear: bool) -> Result<Vec<entry::Entry>, error::RMesgError> {
-    match b {
-        Backend::Default => match kmsgfile::kmsg(None) {
-            Ok(e) => Ok(e),
-            Err(error::RMesgError::DevKMsgFileOpenError(s)) => {
-                eprintln!(
-                    "Falling back from device file to klogctl syscall due to error: {}",
-                    s
-                );
-                klogctl::klog(clear)
-            }
-            Err(e) => Err(e),
-        },
-        Backend::KLogCtl => klogctl::klog(clear),
-        Backend::DevKMsg => kmsgfile::kmsg(None),
+    Ok(log_entries_and_backend(b, clear)?.0)
+}
+
+/// Same as `log_entries`, but also reports which backend the entries were read from.
+pub fn log_entries_and_backend(
+    b: Backend,
+    clear: bool,
+) -> Result<(Vec<entry::Entry>, SelectedBackend), error::RMesgError> {
+    match resolve_backend(b, clear)? {
+        Resolved::KLogCtl => Ok((klogctl::klog(clear)?, SelectedBackend::KLogCtl)),
+        Resolved::DevKMsg(Some(file)) => {
+            Ok((kmsgfile::kmsg_from_file(file)?, SelectedBackend::DevKMsg))
+        }
+        Resolved::DevKMsg(None) => Ok((kmsgfile::kmsg(None)?, SelectedBackend::DevKMsg)),
     }
 }
 
 pub fn logs_raw(b: Backend, clear: bool) -> Result<String, error::RMesgError> {
-    match b {
-        Backend::Default => match kmsgfile::kmsg_raw(None) {
-            Ok(e) => Ok(e),
-            Err(error::RMesgError::DevKMsgFileOpenError(s)) => {
-                eprintln!(
-                    "Falling back from device file to klogctl syscall due to error: {}",
-                    s
-                );
-                klogctl::klog_raw(clear)
-            }
-            Err(e) => Err(e),
-        },
-        Backend::KLogCtl => klogctl::klog_raw(clear),
-        Backend::DevKMsg => kmsgfile::kmsg_raw(None),
+    Ok(logs_raw_and_backend(b, clear)?.0)
+}
+
+/// Same as `logs_raw`, but also reports which backend the logs were read from.
+pub fn logs_raw_and_backend(
+    b: Backend,
+    clear: bool,
+) -> Result<(String, SelectedBackend), error::RMesgError> {
+    match resolve_backend(b, clear)? {
+        Resolved::KLogCtl => Ok((klogctl::klog_raw(clear)?, SelectedBackend::KLogCtl)),
+        Resolved::DevKMsg(Some(file)) => Ok((
+            kmsgfile::kmsg_raw_from_file(file)?,
+            SelectedBackend::DevKMsg,
+        )),
+        Resolved::DevKMsg(None) => Ok((kmsgfile::kmsg_raw(None)?, SelectedBackend::DevKMsg)),
     }
 }
 
+/// The backend actually selected is available through `EntriesIterator::selected_backend`.
+/// To read from a custom source instead of a built-in backend, use `logs_iter_from`.
 #[cfg(feature = "sync")]
 pub fn logs_iter(b: Backend, clear: bool, raw: bool) -> Result<EntriesIterator, error::RMesgError> {
-    match b {
-        Backend::Default => match kmsgfile::KMsgEntriesIter::with_options(None, raw) {
-            Ok(e) => Ok(EntriesIterator::DevKMsg(e)),
-            Err(error::RMesgError::DevKMsgFileOpenError(s)) => {
-                eprintln!(
-                    "Falling back from device file to klogctl syscall due to error: {}",
-                    s
-                );
-                Ok(EntriesIterator::KLogCtl(
-                    klog_entries_only_if_timestamp_enabled(clear)?,
-                ))
-            }
-            Err(e) => Err(e),
-        },
-        Backend::KLogCtl => Ok(EntriesIterator::KLogCtl(
-            klog_entries_only_if_timestamp_enabled(clear)?,
+    logs_iter_with_schedule(b, clear, raw, klogctl::PollSchedule::default())
+}
+
+/// Like `logs_iter`, with the `PollSchedule` the klogctl backend follows the log with
+/// (the kmsg backend doesn't poll, so it's ignored when that one is selected).
+#[cfg(feature = "sync")]
+pub fn logs_iter_with_schedule(
+    b: Backend,
+    clear: bool,
+    raw: bool,
+    schedule: klogctl::PollSchedule,
+) -> Result<EntriesIterator, error::RMesgError> {
+    match resolve_backend(b, clear)? {
+        Resolved::KLogCtl => Ok(EntriesIterator::KLogCtl(
+            klogctl::KLogEntries::with_schedule(clear, schedule)?,
         )),
-        Backend::DevKMsg => Ok(EntriesIterator::DevKMsg(
+        Resolved::DevKMsg(Some(file)) => Ok(EntriesIterator::DevKMsg(
+            kmsgfile::KMsgEntriesIter::from_file(file, raw),
+        )),
+        Resolved::DevKMsg(None) => Ok(EntriesIterator::DevKMsg(
             kmsgfile::KMsgEntriesIter::with_options(None, raw)?,
         )),
     }
 }
 
+/// The backend actually selected is available through `EntriesStream::selected_backend`.
+/// To read from a custom source instead of a built-in backend, use `logs_stream_from`.
 #[cfg(feature = "async")]
 pub async fn logs_stream(
     b: Backend,
     clear: bool,
     raw: bool,
 ) -> Result<EntriesStream, error::RMesgError> {
-    match b {
-        Backend::Default => match kmsgfile::KMsgEntriesStream::with_options(None, raw).await {
-            Ok(e) => Ok(EntriesStream::DevKMsg(e)),
-            Err(error::RMesgError::DevKMsgFileOpenError(s)) => {
-                eprintln!(
-                    "Falling back from device file to klogctl syscall due to error: {}",
-                    s
-                );
-                Ok(EntriesStream::KLogCtl(
-                    klog_entries_only_if_timestamp_enabled(clear)?,
-                ))
-            }
-            Err(e) => Err(e),
-        },
-        Backend::KLogCtl => Ok(EntriesStream::KLogCtl(
-            klog_entries_only_if_timestamp_enabled(clear)?,
+    logs_stream_with_schedule(b, clear, raw, klogctl::PollSchedule::default()).await
+}
+
+/// Like `logs_stream`, with the `PollSchedule` the klogctl backend follows the log with
+/// (the kmsg backend doesn't poll, so it's ignored when that one is selected).
+#[cfg(feature = "async")]
+pub async fn logs_stream_with_schedule(
+    b: Backend,
+    clear: bool,
+    raw: bool,
+    schedule: klogctl::PollSchedule,
+) -> Result<EntriesStream, error::RMesgError> {
+    match resolve_backend(b, clear)? {
+        Resolved::KLogCtl => Ok(EntriesStream::KLogCtl(klogctl::KLogEntries::with_schedule(
+            clear, schedule,
+        )?)),
+        Resolved::DevKMsg(Some(file)) => Ok(EntriesStream::DevKMsg(
+            kmsgfile::KMsgEntriesStream::from_file(file, raw),
         )),
-        Backend::DevKMsg => Ok(EntriesStream::DevKMsg(
+        Resolved::DevKMsg(None) => Ok(EntriesStream::DevKMsg(
             kmsgfile::KMsgEntriesStream::with_options(None, raw).await?,
         )),
     }
 }
 
-fn klog_entries_only_if_timestamp_enabled(
-    clear: bool,
-) -> Result<klogctl::KLogEntries, error::RMesgError> {
-    let log_timestamps_enabled = klogctl::klog_timestamps_enabled()?;
+/// Wraps any custom `LogSource` into the same `EntriesIterator` returned for built-in backends.
+/// This is how custom sources are plugged in, since `logs_iter` only takes a `Backend`.
+#[cfg(feature = "sync")]
+pub fn logs_iter_from<S>(source: S) -> EntriesIterator
+where
+    S: source::LogSource + Send + 'static,
+{
+    EntriesIterator::Custom(Box::new(source))
+}
 
-    // ensure timestamps in logs
-    if !log_timestamps_enabled {
-        eprintln!("WARNING: Timestamps are disabled but tailing/following logs (as you've requested) requires them.");
-        eprintln!("Aboring program.");
-        eprintln!("You can enable timestamps by running the following: ");
-        eprintln!("  echo Y > /sys/module/printk/parameters/time");
-        return Err(error::RMesgError::KLogTimestampsDisabled);
-    }
+/// Wraps any custom `AsyncLogSource` into the same `EntriesStream` returned for built-in backends.
+/// This is how custom sources are plugged in, since `logs_stream` only takes a `Backend`.
+#[cfg(feature = "async")]
+pub fn logs_stream_from<S>(source: S) -> EntriesStream
+where
+    S: source::AsyncLogSource + Send + 'static,
+{
+    EntriesStream::Custom(Box::pin(source))
+}
 
-    klogctl::KLogEntries::with_options(clear, klogctl::SUGGESTED_POLL_INTERVAL)
+/// The built-in backend `resolve_backend` settled on
+enum Resolved {
+    KLogCtl,
+    /// Along with /dev/kmsg as the probe opened it, when it was probed
+    DevKMsg(Option<std::fs::File>),
+}
+
+/// Resolves the requested backend into the one to use. For `Backend::Default`, the backends
+/// are probed (see the `probe` module), and a `BackendFallback` event is raised when the
+/// preferred one turned out to be unusable. When /dev/kmsg was probed, the file the probe
+/// opened is returned too, so that it isn't opened again.
+fn resolve_backend(b: Backend, clear: bool) -> Result<Resolved, error::RMesgError> {
+    match b {
+        Backend::KLogCtl => Ok(Resolved::KLogCtl),
+        Backend::DevKMsg => Ok(Resolved::DevKMsg(None)),
+        Backend::Default => {
+            let (selected, probes) = probe::select_backend(clear)?;
+
+            let mut dev_kmsg = None;
+            for mut probe in probes {
+                if probe.backend == selected {
+                    dev_kmsg = probe.take_dev_kmsg();
+                } else if probe.backend == probe::PREFERRED_BACKEND {
+                    events::emit(events::RMesgEvent::BackendFallback {
+                        requested: b,
+                        from: probe::PREFERRED_BACKEND,
+                        to: selected,
+                        reason: probe.into_error(),
+                    });
+                }
+            }
+
+            // only built-in backends are probed
+            match selected {
+                SelectedBackend::KLogCtl => Ok(Resolved::KLogCtl),
+                _ => Ok(Resolved::DevKMsg(dev_kmsg)),
+            }
+        }
+    }
 }
 
 /**********************************************************************************/
@@ -227,4 +393,53 @@ mod test {
             }
         }
     }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn test_iterator_with_schedule() {
+        let schedule = klogctl::PollSchedule::fixed(std::time::Duration::from_secs(1));
+        match logs_iter_with_schedule(Backend::KLogCtl, false, false, schedule).unwrap() {
+            EntriesIterator::KLogCtl(entries) => assert_eq!(entries.schedule(), &schedule),
+            _ => panic!("Expected the klogctl backend"),
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_stream_with_schedule() {
+        let schedule = klogctl::PollSchedule::fixed(std::time::Duration::from_secs(1));
+        match logs_stream_with_schedule(Backend::KLogCtl, false, false, schedule)
+            .await
+            .unwrap()
+        {
+            EntriesStream::KLogCtl(entries) => assert_eq!(entries.schedule(), &schedule),
+            _ => panic!("Expected the klogctl backend"),
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_custom_stream() {
+        struct Canned(Vec<entry::Entry>);
+        impl Stream for Canned {
+            type Item = Result<entry::Entry, error::RMesgError>;
+            fn poll_next(
+                mut self: Pin<&mut Self>,
+                _: &mut Context<'_>,
+            ) -> Poll<Option<Self::Item>> {
+                match self.0.is_empty() {
+                    true => Poll::Ready(None),
+                    false => Poll::Ready(Some(Ok(self.0.remove(0)))),
+                }
+            }
+        }
+        impl source::AsyncLogSource for Canned {}
+
+        let entry = kmsgfile::entry_from_line("6,1,100,-;serial console line").unwrap();
+        let mut stream = logs_stream_from(Canned(vec![entry.clone()]));
+        assert_eq!(stream.selected_backend(), SelectedBackend::Custom);
+
+        assert_eq!(stream.next().await.unwrap().unwrap(), entry);
+        assert!(stream.next().await.is_none());
+    }
 }
@@ -0,0 +1,149 @@
+/// Loss accounting: detects entries that were lost because the kernel's ring buffer
+/// wrapped around (overwriting them) before they could be read.
+///
+/// How losses are detected depends on the backend:
+///   * /dev/kmsg numbers every record, so a jump in sequence numbers tells exactly how
+///     many were lost. A read failing with EPIPE means the same thing.
+///   * klogctl carries no sequence numbers. When following without clearing, a read
+///     that shares no lines with the previous one means the buffer wrapped past all of
+///     them. When clearing, a read that (nearly) filled the whole buffer
+///     (`SyslogActionSizeBuffer`) means older lines were probably overwritten. The
+///     number of lines lost is unknown in both cases.
+///
+/// Only entries logged while reading are accounted for; whatever was overwritten before
+/// a reader was created is not.
+///
+#[cfg(any(feature = "sync", feature = "async"))]
+use crate::events::{self, RMesgEvent};
+use crate::SelectedBackend;
+
+/// Counters of the entries lost by a reader
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LossStats {
+    /// The number of gaps detected, i.e. the number of times entries were lost
+    pub gaps: u64,
+    /// The number of entries known to have been lost
+    pub entries_lost: u64,
+    /// The number of gaps of an unknown size (these aren't included in `entries_lost`)
+    pub gaps_of_unknown_size: u64,
+}
+
+impl LossStats {
+    /// Whether every entry logged while reading was read
+    pub fn is_complete(&self) -> bool {
+        self.gaps == 0
+    }
+}
+
+/// Tracks the losses of one reader, raising an `RMesgEvent::EntriesLost` for every gap.
+#[derive(Debug)]
+pub(crate) struct LossTracker {
+    #[cfg(any(feature = "sync", feature = "async"))]
+    backend: SelectedBackend,
+    stats: LossStats,
+    #[cfg(any(feature = "sync", feature = "async"))]
+    last_sequence_num: Option<u64>,
+}
+
+impl LossTracker {
+    #[cfg_attr(not(any(feature = "sync", feature = "async")), allow(unused_variables))]
+    pub fn new(backend: SelectedBackend) -> Self {
+        Self {
+            #[cfg(any(feature = "sync", feature = "async"))]
+            backend,
+            stats: LossStats::default(),
+            #[cfg(any(feature = "sync", feature = "async"))]
+            last_sequence_num: None,
+        }
+    }
+
+    pub fn stats(&self) -> LossStats {
+        self.stats
+    }
+
+    /// Records a gap of `count` entries, or of an unknown size when `None`
+    #[cfg(any(feature = "sync", feature = "async"))]
+    pub fn gap(&mut self, count: Option<u64>) {
+        self.stats.gaps += 1;
+        match count {
+            Some(count) => self.stats.entries_lost += count,
+            None => self.stats.gaps_of_unknown_size += 1,
+        }
+
+        events::emit(RMesgEvent::EntriesLost {
+            backend: self.backend,
+            count,
+        });
+    }
+
+    /// Records the sequence number of the entry just read, and a gap if any were skipped
+    #[cfg(any(feature = "sync", feature = "async"))]
+    pub fn sequence_num(&mut self, sequence_num: u64) {
+        if let Some(last) = self.last_sequence_num {
+            // numbers going backwards means a different log (e.g. a replayed file), not a loss
+            if sequence_num > last + 1 {
+                self.gap(Some(sequence_num - last - 1));
+            }
+        }
+        self.last_sequence_num = Some(sequence_num);
+    }
+
+    /// Records that entries were overwritten before they could be read.
+    ///
+    /// When an entry was read before, the jump to the next sequence number counts them instead.
+    #[cfg(any(feature = "sync", feature = "async"))]
+    pub fn overwritten(&mut self) {
+        if self.last_sequence_num.is_none() {
+            self.gap(None);
+        }
+    }
+}
+
+/**********************************************************************************/
+// Tests! Tests! Tests!
+
+#[cfg(all(test, any(feature = "sync", feature = "async")))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sequence_gaps() {
+        let mut tracker = LossTracker::new(SelectedBackend::DevKMsg);
+        for sequence_num in [4, 5, 6] {
+            tracker.sequence_num(sequence_num);
+        }
+        assert!(tracker.stats().is_complete());
+
+        // EPIPE, then the next readable record
+        tracker.overwritten();
+        tracker.sequence_num(10);
+        assert_eq!(
+            tracker.stats(),
+            LossStats {
+                gaps: 1,
+                entries_lost: 3,
+                gaps_of_unknown_size: 0,
+            }
+        );
+
+        // a different log starting over isn't a loss
+        tracker.sequence_num(0);
+        assert_eq!(tracker.stats().gaps, 1);
+    }
+
+    #[test]
+    fn test_unknown_gaps() {
+        let mut tracker = LossTracker::new(SelectedBackend::KLogCtl);
+        tracker.overwritten();
+        tracker.gap(None);
+        assert_eq!(
+            tracker.stats(),
+            LossStats {
+                gaps: 2,
+                entries_lost: 0,
+                gaps_of_unknown_size: 2,
+            }
+        );
+        assert!(!tracker.stats().is_complete());
+    }
+}
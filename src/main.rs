@@ -3,7 +3,11 @@
 ///
 use clap::{App, Arg};
 use futures_util::stream::StreamExt;
+use rmesg::entry::Entry;
+use rmesg::netfilter::PacketLogRecord;
+use rmesg::timewindow::{TimeBound, TimeWindow, TimeWindowStream};
 use std::error::Error;
+use std::str::FromStr;
 
 #[derive(Debug)]
 struct Options {
@@ -11,37 +15,60 @@ struct Options {
     clear: bool,
     raw: bool,
     backend: rmesg::Backend,
+    since: Option<TimeBound>,
+    until: Option<TimeBound>,
+    packets: Option<PacketFormat>,
+}
+
+/// How to print the packets logged by netfilter (the LOG target), instead of the entries
+#[derive(Debug, Clone, Copy)]
+enum PacketFormat {
+    Table,
+    NdJson,
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), Box<dyn Error>> {
     let opts = parse_args();
 
+    // the library doesn't write to stderr; the CLI does.
+    rmesg::events::set_event_hook(|event| eprintln!("{}", event));
+
+    let window = match (opts.since, opts.until) {
+        (None, None) => None,
+        (since, until) => match TimeWindow::new(since, until) {
+            Ok(window) => Some(window),
+            Err(e) => {
+                eprintln!("Unable to filter logs by time: {}", e);
+                return Ok(());
+            }
+        },
+    };
+
     if !opts.follow {
-        nofollow(opts);
+        nofollow(opts, window);
     } else {
         let mut entries = match rmesg::logs_stream(opts.backend, opts.clear, opts.raw).await {
             Ok(entries) => entries,
             Err(e) => {
                 eprintln!("Unable to get logs stream: {}", e);
-
-                if let rmesg::error::RMesgError::OperationNotPermitted(_) = e {
-                    eprintln!("\nHint: Try using 'sudo' or run the program as root/superuser.");
-                }
+                print_hint(&e);
 
                 return Ok(());
             }
         };
 
+        if let Some(window) = window {
+            entries = rmesg::logs_stream_from(TimeWindowStream::new(entries, window));
+        }
+
+        print_header(opts.packets);
         while let Some(result) = entries.next().await {
             match result {
-                Ok(entry) => println!("{}", entry),
+                Ok(entry) => print_entry(&entry, opts.packets),
                 Err(e) => {
                     eprintln!("Unable to get logs stream: {}", e);
-
-                    if let rmesg::error::RMesgError::OperationNotPermitted(_) = e {
-                        eprintln!("\nHint: Try using 'sudo' or run the program as root/superuser.");
-                    }
+                    print_hint(&e);
 
                     return Ok(());
                 }
@@ -52,7 +79,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn nofollow(opts: Options) {
+fn nofollow(opts: Options, window: Option<TimeWindow>) {
     if opts.raw {
         match rmesg::logs_raw(opts.backend, opts.clear) {
             Ok(raw) => {
@@ -60,30 +87,77 @@ fn nofollow(opts: Options) {
             }
             Err(e) => {
                 eprintln!("Unable to get raw logs: {}", e);
-
-                if let rmesg::error::RMesgError::OperationNotPermitted(_) = e {
-                    eprintln!("\nHint: Try using 'sudo' or run the program as root/superuser.");
-                }
+                print_hint(&e);
             }
         }
     } else {
         match rmesg::log_entries(opts.backend, opts.clear) {
-            Ok(entries) => {
+            Ok(mut entries) => {
+                if let Some(mut window) = window {
+                    entries = rmesg::timewindow::filter_entries(entries, &mut window);
+                }
+                print_header(opts.packets);
                 for entry in entries {
-                    println!("{}", entry)
+                    print_entry(&entry, opts.packets)
                 }
             }
             Err(e) => {
                 eprintln!("Unable to get log entries: {}", e);
-
-                if let rmesg::error::RMesgError::OperationNotPermitted(_) = e {
-                    eprintln!("\nHint: Try using 'sudo' or run the program as root/superuser.");
-                }
+                print_hint(&e);
             }
         }
     }
 }
 
+fn print_header(packets: Option<PacketFormat>) {
+    if let Some(PacketFormat::Table) = packets {
+        println!(
+            "{:>14} {:<16} {:<8} {:<8} {:<6} {:<15} {:>5} {:<15} {:>5} FLAGS",
+            "TIME", "PREFIX", "IN", "OUT", "PROTO", "SRC", "SPT", "DST", "DPT"
+        );
+    }
+}
+
+fn print_entry(entry: &Entry, packets: Option<PacketFormat>) {
+    let format = match packets {
+        None => return println!("{}", entry),
+        Some(format) => format,
+    };
+    let record = match PacketLogRecord::from_entry(entry) {
+        Some(record) => record,
+        None => return,
+    };
+
+    match format {
+        PacketFormat::NdJson => println!("{}", record.to_json()),
+        PacketFormat::Table => {
+            let time = record
+                .timestamp_from_system_start
+                .map(|ts| format!("{:.6}", ts.as_secs_f64()));
+            let show = |value: Option<String>| value.unwrap_or_else(|| "-".to_owned());
+            println!(
+                "{:>14} {:<16} {:<8} {:<8} {:<6} {:<15} {:>5} {:<15} {:>5} {}",
+                show(time),
+                show(record.prefix.clone()),
+                show(record.in_interface.clone()),
+                show(record.out_interface.clone()),
+                show(record.protocol.clone()),
+                show(record.src.map(|src| src.to_string())),
+                show(record.src_port.map(|port| port.to_string())),
+                show(record.dst.map(|dst| dst.to_string())),
+                show(record.dst_port.map(|port| port.to_string())),
+                record.tcp_flags.join(",")
+            );
+        }
+    }
+}
+
+fn print_hint(e: &rmesg::error::RMesgError) {
+    if let rmesg::error::RMesgError::PermissionDenied { diagnosis, .. } = e {
+        eprintln!("\nHint: {}", diagnosis.hint());
+    }
+}
+
 fn parse_args() -> Options {
     let matches = App::new("rmesg: A 'dmesg' port onto Rust")
         .version("0.2.0")
@@ -113,6 +187,30 @@ fn parse_args() -> Options {
                 .possible_values(&["klogctl", "devkmsg"])
                 .help("Select backend from where to read the logs. klog is the syslog/klogctl system call through libc. kmsg is the /dev/kmsg file."),
         )
+        .arg(
+            Arg::with_name("since")
+                .long("since")
+                .takes_value(true)
+                .conflicts_with("raw")
+                .validator(validate_time_bound)
+                .help("Only print entries logged at or after this time: seconds since boot (e.g. 1234.5), a date/time (e.g. \"2026-10-18 10:00\", UTC unless an offset is given, or @<epoch seconds>), or relative to now (e.g. \"10 min ago\")."),
+        )
+        .arg(
+            Arg::with_name("until")
+                .long("until")
+                .takes_value(true)
+                .conflicts_with("raw")
+                .validator(validate_time_bound)
+                .help("Only print entries logged at or before this time (same formats as --since). When following, stops once past it."),
+        )
+        .arg(
+            Arg::with_name("packets")
+                .long("packets")
+                .takes_value(true)
+                .possible_values(&["table", "ndjson"])
+                .conflicts_with("raw")
+                .help("Only print the packets logged by netfilter (iptables/nftables LOG rules), as a table or as newline-delimited JSON."),
+        )
         .get_matches();
 
     let follow = !matches!(matches.occurrences_of("follow"), 0);
@@ -125,10 +223,35 @@ fn parse_args() -> Options {
         Some(v) => panic!("Something went wrong. Possible values for backend were not restricted by the CLI parser and this value slipped through somehow: {}", v),
     };
 
+    // both were validated by the parser already
+    let since = matches
+        .value_of("since")
+        .and_then(|v| TimeBound::from_str(v).ok());
+    let until = matches
+        .value_of("until")
+        .and_then(|v| TimeBound::from_str(v).ok());
+
+    let packets = match matches.value_of("packets") {
+        None => None,
+        Some("table") => Some(PacketFormat::Table),
+        Some("ndjson") => Some(PacketFormat::NdJson),
+        Some(v) => panic!("Something went wrong. Possible values for packets were not restricted by the CLI parser and this value slipped through somehow: {}", v),
+    };
+
     Options {
         follow,
         clear,
         raw,
         backend,
+        since,
+        until,
+        packets,
+    }
+}
+
+fn validate_time_bound(value: String) -> Result<(), String> {
+    match TimeBound::from_str(&value) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
     }
 }
@@ -0,0 +1,350 @@
+use crate::entry::Entry;
+/// This module provides an in-memory log source that replays a scripted sequence
+/// of lines, delays, EPIPE gaps and errors.
+///
+/// It is surfaced through the very same `EntriesIterator`/`EntriesStream` types as the
+/// real backends (see `ScriptedSource::into_iter_entries` and `ScriptedSource::into_stream_entries`),
+/// so log processing can be tested against exact inputs, without root and without
+/// depending on whatever happens to be in the host's kernel log buffer.
+///
+use crate::error::RMesgError;
+#[cfg(any(feature = "sync", feature = "async"))]
+use crate::kmsgfile;
+#[cfg(any(feature = "sync", feature = "async"))]
+use crate::loss::{LossStats, LossTracker};
+#[cfg(feature = "async")]
+use crate::source::AsyncLogSource;
+#[cfg(feature = "sync")]
+use crate::source::LogSource;
+#[cfg(any(feature = "sync", feature = "async"))]
+use crate::SelectedBackend;
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+#[cfg(feature = "async")]
+use core::future::Future;
+#[cfg(feature = "async")]
+use core::pin::Pin;
+#[cfg(feature = "async")]
+use futures::stream::Stream;
+#[cfg(feature = "async")]
+use futures::task::{Context, Poll};
+#[cfg(feature = "async")]
+use tokio::time as tokiotime;
+
+#[cfg(feature = "sync")]
+use std::iter::Iterator;
+#[cfg(feature = "sync")]
+use std::thread;
+
+/// A single step in the script replayed by a `ScriptedSource`
+#[derive(Debug)]
+pub enum ScriptStep {
+    /// A line in the /dev/kmsg format, parsed exactly as the kmsg backend would parse it
+    Line(String),
+
+    /// An already structured entry, emitted as-is
+    Entry(Entry),
+
+    /// Wait this long before moving on to the next step
+    Delay(Duration),
+
+    /// Messages were overwritten before being read (/dev/kmsg returns EPIPE). Like the kmsg
+    /// backend, the source skips past them, and counts them in `loss_stats`.
+    BrokenPipe,
+
+    /// An arbitrary error
+    Error(RMesgError),
+}
+
+/// A log source that replays a scripted sequence of steps, and then ends.
+///
+/// Losses are accounted for as the kmsg backend does: at EPIPE, and at sequence numbers
+/// skipped between two scripted lines.
+#[derive(Debug)]
+pub struct ScriptedSource {
+    raw: bool,
+    steps: VecDeque<ScriptStep>,
+    #[cfg(any(feature = "sync", feature = "async"))]
+    loss: LossTracker,
+
+    #[cfg(feature = "async")]
+    sleep_future: Option<Pin<Box<tokiotime::Sleep>>>,
+}
+
+impl Default for ScriptedSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Alias for when a test reads better with a "mock".
+pub type MockSource = ScriptedSource;
+
+impl ScriptedSource {
+    pub fn new() -> Self {
+        Self {
+            raw: false,
+            steps: VecDeque::new(),
+            #[cfg(any(feature = "sync", feature = "async"))]
+            loss: LossTracker::new(SelectedBackend::Custom),
+
+            #[cfg(feature = "async")]
+            sleep_future: None,
+        }
+    }
+
+    /// `raw: bool` When set, does not parse lines and instead sets the entire line in the "message" field
+    pub fn raw(mut self, raw: bool) -> Self {
+        self.raw = raw;
+        self
+    }
+
+    /// Appends a single line in the /dev/kmsg format
+    pub fn line<S: Into<String>>(self, line: S) -> Self {
+        self.step(ScriptStep::Line(line.into()))
+    }
+
+    /// Appends every line in `lines` (each in the /dev/kmsg format)
+    pub fn lines(mut self, lines: &str) -> Self {
+        for line in lines.lines() {
+            self.push(ScriptStep::Line(line.to_owned()));
+        }
+        self
+    }
+
+    /// Appends an already structured entry
+    pub fn entry(self, entry: Entry) -> Self {
+        self.step(ScriptStep::Entry(entry))
+    }
+
+    /// Appends a pause before the next step
+    pub fn delay(self, delay: Duration) -> Self {
+        self.step(ScriptStep::Delay(delay))
+    }
+
+    /// Appends an EPIPE, as /dev/kmsg reports when the ring buffer wrapped past unread messages
+    pub fn broken_pipe(self) -> Self {
+        self.step(ScriptStep::BrokenPipe)
+    }
+
+    /// Appends an arbitrary error
+    pub fn error(self, err: RMesgError) -> Self {
+        self.step(ScriptStep::Error(err))
+    }
+
+    /// Appends any step
+    pub fn step(mut self, step: ScriptStep) -> Self {
+        self.push(step);
+        self
+    }
+
+    /// Appends any step without consuming the source (useful when scripting in a loop)
+    pub fn push(&mut self, step: ScriptStep) {
+        self.steps.push_back(step);
+    }
+
+    /// Number of steps that have not been replayed yet
+    pub fn remaining(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Wraps this source into the `EntriesIterator` returned for the built-in backends
+    #[cfg(feature = "sync")]
+    pub fn into_iter_entries(self) -> crate::EntriesIterator {
+        crate::logs_iter_from(self)
+    }
+
+    /// Wraps this source into the `EntriesStream` returned for the built-in backends
+    #[cfg(feature = "async")]
+    pub fn into_stream_entries(self) -> crate::EntriesStream {
+        crate::logs_stream_from(self)
+    }
+
+    /// The entries lost so far, at EPIPE steps and skipped sequence numbers
+    #[cfg(any(feature = "sync", feature = "async"))]
+    pub fn loss_stats(&self) -> LossStats {
+        self.loss.stats()
+    }
+
+    #[cfg(any(feature = "sync", feature = "async"))]
+    fn entry_from_step(&mut self, step: ScriptStep) -> Result<Entry, RMesgError> {
+        match step {
+            ScriptStep::Line(line) => {
+                if let Some(sequence_num) = kmsgfile::sequence_num_of(&line) {
+                    self.loss.sequence_num(sequence_num);
+                }
+                match self.raw {
+                    true => Ok(Entry {
+                        facility: None,
+                        level: None,
+                        timestamp_from_system_start: None,
+                        wallclock_time: None,
+                        sequence_num: None,
+                        message: line,
+                    }),
+                    false => Ok(kmsgfile::entry_from_line(&line)?),
+                }
+            }
+            ScriptStep::Entry(entry) => Ok(entry),
+            ScriptStep::Error(err) => Err(err),
+            ScriptStep::Delay(_) | ScriptStep::BrokenPipe => Err(RMesgError::InternalError(
+                "A delay or EPIPE step cannot be converted into an entry.".to_owned(),
+            )),
+        }
+    }
+}
+
+/// Trait to iterate over the scripted lines.
+#[cfg(feature = "sync")]
+impl Iterator for ScriptedSource {
+    type Item = Result<Entry, RMesgError>;
+
+    /// Delays block the calling thread, just as waiting on the kernel would.
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.steps.pop_front()? {
+                ScriptStep::Delay(delay) => thread::sleep(delay),
+                ScriptStep::BrokenPipe => self.loss.overwritten(),
+                step => return Some(self.entry_from_step(step)),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "sync")]
+impl LogSource for ScriptedSource {
+    fn loss_stats(&self) -> LossStats {
+        ScriptedSource::loss_stats(self)
+    }
+}
+
+/// Trait to iterate over the scripted lines.
+#[cfg(feature = "async")]
+impl Stream for ScriptedSource {
+    type Item = Result<Entry, RMesgError>;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(mut sf) = self.sleep_future.take() {
+                if Future::poll(sf.as_mut(), cx).is_pending() {
+                    // still sleeping? put the future back in
+                    self.sleep_future = Some(sf);
+                    return Poll::Pending;
+                }
+            }
+
+            match self.steps.pop_front() {
+                None => return Poll::Ready(None),
+                Some(ScriptStep::Delay(delay)) => {
+                    self.sleep_future = Some(Box::pin(tokiotime::sleep(delay)));
+                }
+                Some(ScriptStep::BrokenPipe) => self.loss.overwritten(),
+                Some(step) => return Poll::Ready(Some(self.entry_from_step(step))),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl AsyncLogSource for ScriptedSource {
+    fn loss_stats(&self) -> LossStats {
+        ScriptedSource::loss_stats(self)
+    }
+}
+
+/**********************************************************************************/
+// Tests! Tests! Tests!
+
+#[cfg(all(test, any(feature = "sync", feature = "async")))]
+mod test {
+    use super::*;
+    use crate::entry::LogLevel;
+    #[cfg(feature = "async")]
+    use tokio_stream::StreamExt;
+
+    fn script() -> ScriptedSource {
+        ScriptedSource::new()
+            .line("6,1,1000000,-;first line")
+            .delay(Duration::from_millis(10))
+            .broken_pipe()
+            .line("3,5,2000000,-;after the gap")
+            .error(RMesgError::BackendUnavailable("gone".to_owned()))
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn test_iterator() {
+        let mut iter = script().into_iter_entries();
+
+        let first = iter.next().unwrap().unwrap();
+        assert_eq!(first.message, "first line");
+        assert_eq!(first.sequence_num, Some(1));
+        assert_eq!(
+            first.timestamp_from_system_start,
+            Some(Duration::from_secs(1))
+        );
+
+        // the gap is skipped, and the entries overwritten (2 to 4) are counted
+        let second = iter.next().unwrap().unwrap();
+        assert_eq!(second.level, Some(LogLevel::Error));
+        assert_eq!(second.sequence_num, Some(5));
+        let stats = iter.loss_stats();
+        assert_eq!((stats.gaps, stats.entries_lost), (1, 3));
+
+        assert!(matches!(
+            iter.next(),
+            Some(Err(RMesgError::BackendUnavailable(_)))
+        ));
+        assert!(iter.next().is_none());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_stream() {
+        let mut stream = script().into_stream_entries();
+
+        let first = stream.next().await.unwrap().unwrap();
+        assert_eq!(first.message, "first line");
+
+        let second = stream.next().await.unwrap().unwrap();
+        assert_eq!(second.level, Some(LogLevel::Error));
+        assert_eq!(second.message, "after the gap");
+        assert_eq!(stream.loss_stats().entries_lost, 3);
+
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(RMesgError::BackendUnavailable(_)))
+        ));
+        assert!(stream.next().await.is_none());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_raw() {
+        let mut stream = ScriptedSource::new()
+            .raw(true)
+            .lines("6,1,0,-;one\n6,2,0,-;two")
+            .into_stream_entries();
+
+        assert_eq!(stream.next().await.unwrap().unwrap().message, "6,1,0,-;one");
+        assert_eq!(stream.next().await.unwrap().unwrap().message, "6,2,0,-;two");
+        assert!(stream.next().await.is_none());
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn test_overwritten_before_first_read() {
+        let mut iter = ScriptedSource::new()
+            .broken_pipe()
+            .line("6,7,0,-;oldest left")
+            .into_iter_entries();
+
+        assert_eq!(iter.next().unwrap().unwrap().message, "oldest left");
+        assert!(iter.next().is_none());
+
+        // nothing was read before, so how many were lost is unknown
+        let stats = iter.loss_stats();
+        assert_eq!((stats.gaps, stats.gaps_of_unknown_size), (1, 1));
+    }
+}
@@ -0,0 +1,383 @@
+use crate::analysis::Analyzer;
+/// This module parses the lines network drivers and the networking core log about their
+/// interfaces (link state, renames, promiscuous mode, carrier and transmit timeouts) into
+/// `NetEvent`s. Drivers word these differently, e.g.:
+///
+/// ```text
+/// r8169 0000:02:00.0 enp2s0: Link is Up - 1Gbps/Full - flow control rx/tx
+/// e1000e: eth0 NIC Link is Up 1000 Mbps Full Duplex, Flow Control: Rx/Tx
+/// ixgbe 0000:03:00.0 eth2: NIC Link is Up 10 Gbps, Flow Control: RX/TX
+/// tg3 0000:01:00.0 eth0: Link is up at 1000 Mbps, full duplex
+/// mlx5_core 0000:5e:00.0 enp94s0f0: Link down
+/// virtio_net virtio0 ens3: renamed from eth0
+/// device eth0 entered promiscuous mode
+/// IPv6: ADDRCONF(NETDEV_CHANGE): eth0: link becomes ready
+/// NETDEV WATCHDOG: eth0 (e1000e): transmit queue 0 timed out
+/// ```
+///
+use crate::entry::Entry;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::time::Duration;
+use strum_macros::Display;
+
+lazy_static! {
+    static ref RE_WATCHDOG: Regex = Regex::new(
+        r"^NETDEV WATCHDOG: (?:CPU: [[:digit:]]+: )?(?P<interface>[^[:space:]]+) \((?P<driver>[^)]+)\): transmit queue (?P<queue>[[:digit:]]+) timed out"
+    )
+    .unwrap();
+    static ref RE_DEVICE_PROMISCUOUS: Regex = Regex::new(
+        r"^device (?P<interface>[^[:space:]]+) (?P<event>(?:entered|left) promiscuous mode)"
+    )
+    .unwrap();
+    static ref RE_ADDRCONF: Regex = Regex::new(
+        r"ADDRCONF\(NETDEV_[A-Z]+\): (?P<interface>[^[:space:]:]+): (?P<event>.*)$"
+    )
+    .unwrap();
+    // "e1000e: eth0 NIC Link is Up ..." (also nested after the device, as igb does)
+    static ref RE_DRIVER_FIRST: Regex = Regex::new(
+        r"^(?P<driver>[[:alnum:]_-]+): (?P<interface>[^[:space:]]+) (?P<event>NIC Link .*)$"
+    )
+    .unwrap();
+    // "<driver> <bus id> <interface>: <event>", or "<interface>: <event>"
+    static ref RE_DEVICE: Regex = Regex::new(
+        r"^(?:(?P<driver>[[:alnum:]_-]+) (?P<bus>[^[:space:]]+) )?(?P<interface>[^[:space:]:]+): (?P<event>.*)$"
+    )
+    .unwrap();
+    static ref RE_LINK_UP: Regex = Regex::new(r"(?i)^(?:NIC )?link(?: is)? up\b(?P<details>.*)$").unwrap();
+    static ref RE_LINK_DOWN: Regex = Regex::new(r"(?i)^(?:NIC )?link(?: is)? down\b").unwrap();
+    static ref RE_SPEED: Regex =
+        Regex::new(r"(?P<speed>[[:digit:]]+)[[:space:]]*(?P<unit>[MG])bps").unwrap();
+    static ref RE_RENAMED: Regex = Regex::new(r"^renamed from (?P<from>[^[:space:]]+)").unwrap();
+    static ref RE_TX_TIMEOUT: Regex =
+        Regex::new(r"(?i)^(?:tx timeout|transmit timed out|transmit timeout)").unwrap();
+}
+
+/// Whether a link is full or half duplex
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum Duplex {
+    Full,
+    Half,
+}
+
+/// What happened to an interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetEventKind {
+    /// The link came up (the speed and duplex are only known when the driver logs them)
+    LinkUp {
+        speed_mbps: Option<u32>,
+        duplex: Option<Duplex>,
+    },
+    /// The link went down
+    LinkDown,
+    /// The carrier came up ("link becomes ready")
+    CarrierUp,
+    /// The interface was brought up without carrier ("link is not ready"), or lost it
+    CarrierDown,
+    /// The interface was renamed (e.g. by udev), from its former name
+    Renamed {
+        from: String,
+    },
+    PromiscuousEntered,
+    PromiscuousLeft,
+    /// A transmit queue stalled (the queue is only known when the watchdog reports it)
+    TxTimeout {
+        queue: Option<u32>,
+    },
+}
+
+/// An event of a network interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetEvent {
+    /// The interface's name (for `Renamed`, its new name)
+    pub interface: String,
+    /// The driver, when the line names it
+    pub driver: Option<String>,
+    pub kind: NetEventKind,
+    /// The time since boot of the entry
+    pub timestamp_from_system_start: Option<Duration>,
+}
+
+impl NetEvent {
+    /// Parses a line (an entry's message), or `None` when it's no network event.
+    pub fn parse(line: &str) -> Option<NetEvent> {
+        let line = line.trim();
+
+        if let Some(parts) = RE_WATCHDOG.captures(line) {
+            let queue = parts["queue"].parse().ok();
+            return Some(Self::new(
+                &parts["interface"],
+                Some(&parts["driver"]),
+                NetEventKind::TxTimeout { queue },
+            ));
+        }
+
+        let (interface, driver, event) = if let Some(parts) = RE_DEVICE_PROMISCUOUS
+            .captures(line)
+            .or_else(|| RE_ADDRCONF.captures(line))
+            .or_else(|| RE_DRIVER_FIRST.captures(line))
+        {
+            (
+                parts.name("interface")?.as_str(),
+                parts.name("driver").map(|driver| driver.as_str()),
+                parts.name("event")?.as_str(),
+            )
+        } else if let Some(parts) = RE_DEVICE.captures(line) {
+            match RE_DRIVER_FIRST.captures(parts.name("event")?.as_str()) {
+                Some(nested) => (
+                    nested.name("interface")?.as_str(),
+                    nested.name("driver").map(|driver| driver.as_str()),
+                    nested.name("event")?.as_str(),
+                ),
+                None => (
+                    parts.name("interface")?.as_str(),
+                    parts.name("driver").map(|driver| driver.as_str()),
+                    parts.name("event")?.as_str(),
+                ),
+            }
+        } else {
+            return None;
+        };
+
+        Some(Self::new(interface, driver, event_kind(event)?))
+    }
+
+    /// Parses an entry, keeping its timestamp.
+    pub fn from_entry(entry: &Entry) -> Option<NetEvent> {
+        let mut event = Self::parse(&entry.message)?;
+        event.timestamp_from_system_start = entry.timestamp_from_system_start;
+        Some(event)
+    }
+
+    /// Whether the event changed the link's state (e.g. to detect flapping links)
+    pub fn is_link_change(&self) -> bool {
+        matches!(
+            self.kind,
+            NetEventKind::LinkUp { .. }
+                | NetEventKind::LinkDown
+                | NetEventKind::CarrierUp
+                | NetEventKind::CarrierDown
+        )
+    }
+
+    fn new(interface: &str, driver: Option<&str>, kind: NetEventKind) -> Self {
+        Self {
+            interface: interface.to_owned(),
+            driver: driver.map(|driver| driver.to_owned()),
+            kind,
+            timestamp_from_system_start: None,
+        }
+    }
+}
+
+/// Finds `NetEvent`s among entries
+#[derive(Debug, Default)]
+pub struct NetAnalyzer;
+
+impl NetAnalyzer {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Analyzer for NetAnalyzer {
+    type Report = NetEvent;
+
+    fn push(&mut self, entry: &Entry) -> Vec<NetEvent> {
+        NetEvent::from_entry(entry).into_iter().collect()
+    }
+
+    fn finish(&mut self) -> Vec<NetEvent> {
+        Vec::new()
+    }
+}
+
+fn event_kind(event: &str) -> Option<NetEventKind> {
+    let lowercase = event.to_lowercase();
+
+    if lowercase.starts_with("link becomes ready") || lowercase.starts_with("carrier on") {
+        Some(NetEventKind::CarrierUp)
+    } else if lowercase.starts_with("link is not ready")
+        || lowercase.starts_with("carrier off")
+        || lowercase.starts_with("carrier lost")
+    {
+        Some(NetEventKind::CarrierDown)
+    } else if let Some(parts) = RE_LINK_UP.captures(event) {
+        let details = &parts["details"];
+        let speed_mbps = RE_SPEED.captures(details).and_then(|speed| {
+            let value: u32 = speed["speed"].parse().ok()?;
+            match &speed["unit"] {
+                "G" => value.checked_mul(1000),
+                _ => Some(value),
+            }
+        });
+        let details = details.to_lowercase();
+        let duplex = if details.contains("full") {
+            Some(Duplex::Full)
+        } else if details.contains("half") {
+            Some(Duplex::Half)
+        } else {
+            None
+        };
+        Some(NetEventKind::LinkUp { speed_mbps, duplex })
+    } else if RE_LINK_DOWN.is_match(event) {
+        Some(NetEventKind::LinkDown)
+    } else if let Some(parts) = RE_RENAMED.captures(event) {
+        Some(NetEventKind::Renamed {
+            from: parts["from"].to_owned(),
+        })
+    } else if lowercase.starts_with("entered promiscuous mode") {
+        Some(NetEventKind::PromiscuousEntered)
+    } else if lowercase.starts_with("left promiscuous mode") {
+        Some(NetEventKind::PromiscuousLeft)
+    } else if RE_TX_TIMEOUT.is_match(event) {
+        Some(NetEventKind::TxTimeout { queue: None })
+    } else {
+        None
+    }
+}
+
+/**********************************************************************************/
+// Tests! Tests! Tests!
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn link_up(speed_mbps: Option<u32>, duplex: Option<Duplex>) -> NetEventKind {
+        NetEventKind::LinkUp { speed_mbps, duplex }
+    }
+
+    fn parsed(line: &str) -> (String, Option<String>, NetEventKind) {
+        let event = NetEvent::parse(line).unwrap();
+        (event.interface, event.driver, event.kind)
+    }
+
+    #[test]
+    fn test_link_state() {
+        assert_eq!(
+            parsed("r8169 0000:02:00.0 enp2s0: Link is Up - 1Gbps/Full - flow control rx/tx"),
+            (
+                "enp2s0".to_owned(),
+                Some("r8169".to_owned()),
+                link_up(Some(1000), Some(Duplex::Full))
+            )
+        );
+        assert_eq!(
+            parsed("e1000e: eth0 NIC Link is Up 1000 Mbps Full Duplex, Flow Control: Rx/Tx"),
+            (
+                "eth0".to_owned(),
+                Some("e1000e".to_owned()),
+                link_up(Some(1000), Some(Duplex::Full))
+            )
+        );
+        assert_eq!(
+            parsed("igb 0000:01:00.0 eth1: igb: eth1 NIC Link is Up 100 Mbps Half Duplex, Flow Control: None").2,
+            link_up(Some(100), Some(Duplex::Half))
+        );
+        assert_eq!(
+            parsed("ixgbe 0000:03:00.0 eth2: NIC Link is Up 10 Gbps, Flow Control: RX/TX").2,
+            link_up(Some(10_000), None)
+        );
+        assert_eq!(
+            parsed("tg3 0000:01:00.0 eth0: Link is up at 1000 Mbps, full duplex").2,
+            link_up(Some(1000), Some(Duplex::Full))
+        );
+        assert_eq!(
+            parsed("mlx5_core 0000:5e:00.0 enp94s0f0: Link up").2,
+            link_up(None, None)
+        );
+        assert_eq!(
+            parsed("e1000e: eth0 NIC Link is Down").2,
+            NetEventKind::LinkDown
+        );
+        assert_eq!(
+            parsed("mlx5_core 0000:5e:00.0 enp94s0f0: Link down").2,
+            NetEventKind::LinkDown
+        );
+        assert_eq!(
+            parsed("IPv6: ADDRCONF(NETDEV_CHANGE): eth0: link becomes ready"),
+            ("eth0".to_owned(), None, NetEventKind::CarrierUp)
+        );
+        assert_eq!(
+            parsed("IPv6: ADDRCONF(NETDEV_UP): eth0: link is not ready").2,
+            NetEventKind::CarrierDown
+        );
+    }
+
+    #[test]
+    fn test_other_events() {
+        assert_eq!(
+            parsed("virtio_net virtio0 ens3: renamed from eth0"),
+            (
+                "ens3".to_owned(),
+                Some("virtio_net".to_owned()),
+                NetEventKind::Renamed {
+                    from: "eth0".to_owned()
+                }
+            )
+        );
+        assert_eq!(
+            parsed("device eth0 entered promiscuous mode"),
+            ("eth0".to_owned(), None, NetEventKind::PromiscuousEntered)
+        );
+        assert_eq!(
+            parsed("eth0: left promiscuous mode").2,
+            NetEventKind::PromiscuousLeft
+        );
+        assert_eq!(
+            parsed("NETDEV WATCHDOG: eth0 (e1000e): transmit queue 0 timed out"),
+            (
+                "eth0".to_owned(),
+                Some("e1000e".to_owned()),
+                NetEventKind::TxTimeout { queue: Some(0) }
+            )
+        );
+        assert_eq!(
+            parsed("NETDEV WATCHDOG: CPU: 3: enp2s0 (r8169): transmit queue 2 timed out 5264 ms").2,
+            NetEventKind::TxTimeout { queue: Some(2) }
+        );
+        assert_eq!(
+            parsed("e1000e 0000:00:19.0 eth0: TX timeout, resetting").2,
+            NetEventKind::TxTimeout { queue: None }
+        );
+
+        assert_eq!(
+            NetEvent::parse("usb 1-1: new high-speed USB device number 2 using xhci_hcd"),
+            None
+        );
+        assert_eq!(NetEvent::parse("EXT4-fs (sda1): mounted filesystem"), None);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_net_event_stream() {
+        use crate::analysis::AnalyzerStream;
+        use tokio_stream::StreamExt;
+
+        let mut source = crate::mock::ScriptedSource::new();
+        for entry in crate::klogctl::entries_from_lines(
+            "<6>[    1.000000] e1000e: eth0 NIC Link is Down\n\
+             <6>[    2.000000] kworker: unrelated\n\
+             <6>[    3.000000] e1000e: eth0 NIC Link is Up 1000 Mbps Full Duplex, Flow Control: None",
+        )
+        .unwrap()
+        {
+            source.push(crate::mock::ScriptStep::Entry(entry));
+        }
+
+        let events: Vec<NetEvent> = AnalyzerStream::new(source, NetAnalyzer::new())
+            .map(|event| event.unwrap())
+            .collect()
+            .await;
+
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|event| event.is_link_change()));
+        assert_eq!(events[0].kind, NetEventKind::LinkDown);
+        assert_eq!(
+            events[1].timestamp_from_system_start,
+            Some(Duration::from_secs(3))
+        );
+    }
+}
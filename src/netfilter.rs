@@ -0,0 +1,273 @@
+use crate::analysis::Analyzer;
+/// This module parses the packets logged by the netfilter LOG target (iptables' `-j LOG`,
+/// nftables' `log` statement) into `PacketLogRecord`s:
+///
+/// ```text
+/// [UFW BLOCK] IN=eth0 OUT= MAC=52:54:00:12:34:56:52:54:00:65:43:21:08:00 SRC=203.0.113.7 DST=192.0.2.10 LEN=60 TOS=0x00 PREC=0x00 TTL=52 ID=54321 DF PROTO=TCP SPT=51234 DPT=22 WINDOW=64240 RES=0x00 SYN URGP=0
+/// nft drop: IN=eth0 OUT= MAC=... SRC=2001:db8::1 DST=2001:db8::2 LEN=104 TC=0 HOPLIMIT=64 FLOWLBL=0 PROTO=ICMPv6 TYPE=128 CODE=0 ID=7 SEQ=1
+/// ```
+///
+use crate::entry::Entry;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::time::Duration;
+
+lazy_static! {
+    static ref RE_PACKET: Regex = Regex::new(
+        r"^(?P<prefix>.*?)IN=(?P<in>[^[:space:]]*) OUT=(?P<out>[^[:space:]]*)(?P<fields>.*)$"
+    )
+    .unwrap();
+}
+
+/// The flags the LOG target prints for TCP segments
+const TCP_FLAGS: [&str; 8] = ["CWR", "ECE", "URG", "ACK", "PSH", "RST", "SYN", "FIN"];
+
+/// A packet logged by the netfilter LOG target
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PacketLogRecord {
+    /// The rule's log prefix (e.g. "[UFW BLOCK]"), trimmed
+    pub prefix: Option<String>,
+    /// The interface the packet came in on (none for locally generated packets)
+    pub in_interface: Option<String>,
+    /// The interface the packet goes out on (none for packets to this host)
+    pub out_interface: Option<String>,
+    /// The link layer header: destination and source MAC addresses, and the EtherType
+    pub mac: Option<String>,
+    pub src: Option<IpAddr>,
+    pub dst: Option<IpAddr>,
+    /// The protocol (e.g. "TCP", "UDP", "ICMP", "ICMPv6", or a number)
+    pub protocol: Option<String>,
+    pub src_port: Option<u16>,
+    pub dst_port: Option<u16>,
+    /// The packet's length
+    pub length: Option<u32>,
+    /// The TTL (IPv4) or hop limit (IPv6)
+    pub ttl: Option<u8>,
+    /// The TCP flags set (e.g. "SYN", "ACK"), in the order they were logged
+    pub tcp_flags: Vec<String>,
+    pub icmp_type: Option<u8>,
+    pub icmp_code: Option<u8>,
+    /// The time since boot of the entry
+    pub timestamp_from_system_start: Option<Duration>,
+}
+
+impl PacketLogRecord {
+    /// Parses a line (an entry's message), or `None` when it's no logged packet.
+    pub fn parse(line: &str) -> Option<PacketLogRecord> {
+        let parts = RE_PACKET.captures(line.trim())?;
+
+        let mut record = PacketLogRecord {
+            prefix: non_empty(parts["prefix"].trim()),
+            in_interface: non_empty(&parts["in"]),
+            out_interface: non_empty(&parts["out"]),
+            ..PacketLogRecord::default()
+        };
+
+        // the header of the packet an ICMP error quotes is printed between brackets
+        let fields = parts["fields"].split('[').next().unwrap_or_default();
+        for field in fields.split_whitespace() {
+            match field.split_once('=') {
+                Some(("MAC", mac)) => record.mac = non_empty(mac),
+                Some(("SRC", src)) => record.src = src.parse().ok(),
+                Some(("DST", dst)) => record.dst = dst.parse().ok(),
+                Some(("LEN", length)) => record.length = record.length.or(length.parse().ok()),
+                Some(("TTL", ttl)) | Some(("HOPLIMIT", ttl)) => record.ttl = ttl.parse().ok(),
+                Some(("PROTO", protocol)) => record.protocol = non_empty(protocol),
+                Some(("SPT", port)) => record.src_port = port.parse().ok(),
+                Some(("DPT", port)) => record.dst_port = port.parse().ok(),
+                Some(("TYPE", icmp_type)) => record.icmp_type = icmp_type.parse().ok(),
+                Some(("CODE", icmp_code)) => record.icmp_code = icmp_code.parse().ok(),
+                Some(_) => {}
+                None => {
+                    if record.protocol.as_deref() == Some("TCP") && TCP_FLAGS.contains(&field) {
+                        record.tcp_flags.push(field.to_owned());
+                    }
+                }
+            }
+        }
+
+        // "IN=" alone is too little to go by
+        if record.src.is_none() && record.mac.is_none() {
+            return None;
+        }
+
+        Some(record)
+    }
+
+    /// Parses an entry, keeping its timestamp.
+    pub fn from_entry(entry: &Entry) -> Option<PacketLogRecord> {
+        let mut record = Self::parse(&entry.message)?;
+        record.timestamp_from_system_start = entry.timestamp_from_system_start;
+        Some(record)
+    }
+
+    /// The record as one line of JSON (absent values are null), e.g. for NDJSON output
+    pub fn to_json(&self) -> String {
+        let mut json = String::with_capacity(256);
+        json.push('{');
+
+        json.push_str("\"timestamp\":");
+        match self.timestamp_from_system_start {
+            Some(ts) => write!(json, "{:.6}", ts.as_secs_f64()).unwrap_or_default(),
+            None => json.push_str("null"),
+        }
+
+        let strings = [
+            ("prefix", self.prefix.clone()),
+            ("in", self.in_interface.clone()),
+            ("out", self.out_interface.clone()),
+            ("mac", self.mac.clone()),
+            ("src", self.src.map(|src| src.to_string())),
+            ("dst", self.dst.map(|dst| dst.to_string())),
+            ("proto", self.protocol.clone()),
+        ];
+        for (key, value) in strings.iter() {
+            write!(json, ",\"{}\":", key).unwrap_or_default();
+            match value {
+                Some(value) => push_json_string(&mut json, value),
+                None => json.push_str("null"),
+            }
+        }
+
+        let numbers = [
+            ("spt", self.src_port.map(u32::from)),
+            ("dpt", self.dst_port.map(u32::from)),
+            ("len", self.length),
+            ("ttl", self.ttl.map(u32::from)),
+            ("icmp_type", self.icmp_type.map(u32::from)),
+            ("icmp_code", self.icmp_code.map(u32::from)),
+        ];
+        for (key, value) in numbers.iter() {
+            match value {
+                Some(value) => write!(json, ",\"{}\":{}", key, value),
+                None => write!(json, ",\"{}\":null", key),
+            }
+            .unwrap_or_default();
+        }
+
+        json.push_str(",\"flags\":[");
+        for (index, flag) in self.tcp_flags.iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            push_json_string(&mut json, flag);
+        }
+        json.push_str("]}");
+
+        json
+    }
+}
+
+/// Finds `PacketLogRecord`s among entries
+#[derive(Debug, Default)]
+pub struct PacketLogAnalyzer;
+
+impl PacketLogAnalyzer {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Analyzer for PacketLogAnalyzer {
+    type Report = PacketLogRecord;
+
+    fn push(&mut self, entry: &Entry) -> Vec<PacketLogRecord> {
+        PacketLogRecord::from_entry(entry).into_iter().collect()
+    }
+
+    fn finish(&mut self) -> Vec<PacketLogRecord> {
+        Vec::new()
+    }
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    match value {
+        "" => None,
+        value => Some(value.to_owned()),
+    }
+}
+
+fn push_json_string(json: &mut String, value: &str) {
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => write!(json, "\\u{:04x}", c as u32).unwrap_or_default(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+/**********************************************************************************/
+// Tests! Tests! Tests!
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis;
+    use crate::klogctl;
+
+    const UFW_BLOCK: &str = "[UFW BLOCK] IN=eth0 OUT= MAC=52:54:00:12:34:56:52:54:00:65:43:21:08:00 SRC=203.0.113.7 DST=192.0.2.10 LEN=60 TOS=0x00 PREC=0x00 TTL=52 ID=54321 DF PROTO=TCP SPT=51234 DPT=22 WINDOW=64240 RES=0x00 SYN URGP=0";
+
+    #[test]
+    fn test_parse() {
+        let record = PacketLogRecord::parse(UFW_BLOCK).unwrap();
+        assert_eq!(record.prefix.as_deref(), Some("[UFW BLOCK]"));
+        assert_eq!(record.in_interface.as_deref(), Some("eth0"));
+        assert_eq!(record.out_interface, None);
+        assert_eq!(record.src, Some("203.0.113.7".parse().unwrap()));
+        assert_eq!(record.dst, Some("192.0.2.10".parse().unwrap()));
+        assert_eq!(record.protocol.as_deref(), Some("TCP"));
+        assert_eq!((record.src_port, record.dst_port), (Some(51234), Some(22)));
+        assert_eq!((record.length, record.ttl), (Some(60), Some(52)));
+        assert_eq!(record.tcp_flags, vec!["SYN"]);
+
+        // no space after the prefix, IPv6, ICMPv6
+        let record = PacketLogRecord::parse("DROPIN= OUT=eth1 SRC=2001:db8::1 DST=2001:db8::2 LEN=104 TC=0 HOPLIMIT=64 FLOWLBL=0 PROTO=ICMPv6 TYPE=128 CODE=0 ID=7 SEQ=1").unwrap();
+        assert_eq!(record.prefix.as_deref(), Some("DROP"));
+        assert_eq!(record.out_interface.as_deref(), Some("eth1"));
+        assert_eq!(record.src, Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(record.ttl, Some(64));
+        assert_eq!((record.icmp_type, record.icmp_code), (Some(128), Some(0)));
+
+        // the quoted header of an ICMP error is not the packet's
+        let record = PacketLogRecord::parse("IN=eth0 OUT= SRC=198.51.100.1 DST=192.0.2.10 LEN=88 TTL=63 PROTO=ICMP TYPE=3 CODE=3 [SRC=192.0.2.10 DST=198.51.100.1 LEN=60 TTL=64 PROTO=UDP SPT=5353 DPT=53 LEN=40 ]").unwrap();
+        assert_eq!(record.src, Some("198.51.100.1".parse().unwrap()));
+        assert_eq!(record.protocol.as_deref(), Some("ICMP"));
+        assert_eq!(record.dst_port, None);
+
+        assert_eq!(PacketLogRecord::parse("ACPI: IN= OUT= nothing"), None);
+        assert_eq!(PacketLogRecord::parse("eth0: Link is Up"), None);
+    }
+
+    #[test]
+    fn test_to_json() {
+        let entries =
+            klogctl::entries_from_lines(&format!("<4>[  120.500000] {}", UFW_BLOCK)).unwrap();
+        let records = analysis::analyze(PacketLogAnalyzer::new(), &entries);
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            records[0].to_json(),
+            "{\"timestamp\":120.500000,\"prefix\":\"[UFW BLOCK]\",\"in\":\"eth0\",\"out\":null,\
+             \"mac\":\"52:54:00:12:34:56:52:54:00:65:43:21:08:00\",\"src\":\"203.0.113.7\",\
+             \"dst\":\"192.0.2.10\",\"proto\":\"TCP\",\"spt\":51234,\"dpt\":22,\"len\":60,\"ttl\":52,\
+             \"icmp_type\":null,\"icmp_code\":null,\"flags\":[\"SYN\"]}"
+        );
+
+        let record = PacketLogRecord {
+            prefix: Some("say \"hi\"\\".to_owned()),
+            ..PacketLogRecord::default()
+        };
+        assert!(record
+            .to_json()
+            .contains("\"prefix\":\"say \\\"hi\\\"\\\\\""));
+    }
+}
@@ -0,0 +1,397 @@
+use crate::analysis::{Analyzer, Frame, TaskInfo};
+/// This module assembles the output of the OOM killer into an `OomEvent`. That output
+/// starts with "invoked oom-killer", continues with the triggering task's call trace, the
+/// memory cgroup's usage (for a cgroup OOM), the Mem-Info dump and the task table, and ends
+/// with the "Killed process" line (and the "oom_reaper" line that usually follows it):
+///
+/// ```text
+/// stress invoked oom-killer: gfp_mask=0xcc0(GFP_KERNEL), order=0, oom_score_adj=0
+/// CPU: 1 PID: 1234 Comm: stress Not tainted 6.1.0 #1
+/// Call Trace:
+///  ...
+/// Mem-Info:
+///  ...
+/// Tasks state (memory values in pages):
+/// [  pid  ]   uid  tgid total_vm      rss pgtables_bytes swapents oom_score_adj name
+/// [   1234]  1000  1234   263000   250000  2097152        0             0 stress
+/// oom-kill:constraint=CONSTRAINT_NONE,nodemask=(null),cpuset=/,mems_allowed=0,global_oom,task_memcg=/user.slice,task=stress,pid=1234,uid=1000
+/// Out of memory: Killed process 1234 (stress) total-vm:1052000kB, anon-rss:999000kB, file-rss:4kB, shmem-rss:0kB, UID:1000 pgtables:2048kB oom_score_adj:0
+/// oom_reaper: reaped process 1234 (stress), now anon-rss:0kB, file-rss:0kB, shmem-rss:0kB
+/// ```
+///
+/// Only the task table format of kernels 4.19 and later (with pgtables_bytes) is parsed.
+///
+use crate::entry::Entry;
+
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The task table lists every process; an event is closed after so many entries regardless,
+/// so that a missed end doesn't swallow the rest of the log.
+const MAX_OOM_ENTRIES: usize = 10_000;
+
+lazy_static! {
+    static ref RE_INVOKED: Regex = Regex::new(
+        r"(?x)^
+        (?P<comm>.+?)[[:space:]]invoked[[:space:]]oom-killer:
+        [[:space:]]+gfp_mask=0x(?P<gfp_mask>[[:xdigit:]]+)(?:\((?P<gfp_flags>[^)]*)\))?,
+        (?:[[:space:]]+nodemask=[^,]*,)?
+        [[:space:]]+order=(?P<order>-?[[:digit:]]+),
+        [[:space:]]+oom_score_adj=(?P<oom_score_adj>-?[[:digit:]]+)"
+    )
+    .unwrap();
+    static ref RE_KILLED: Regex = Regex::new(
+        r"(?x)^
+        (?:Memory[[:space:]]cgroup[[:space:]]out[[:space:]]of[[:space:]]memory|Out[[:space:]]of[[:space:]]memory[^:]*):
+        [[:space:]]+Killed[[:space:]]process[[:space:]]+(?P<pid>[[:digit:]]+)[[:space:]]+\((?P<comm>.*)\)
+        [[:space:]]+total-vm:(?P<total_vm>[[:digit:]]+)kB,
+        [[:space:]]+anon-rss:(?P<anon_rss>[[:digit:]]+)kB,
+        [[:space:]]+file-rss:(?P<file_rss>[[:digit:]]+)kB
+        (?:,[[:space:]]+shmem-rss:(?P<shmem_rss>[[:digit:]]+)kB)?
+        (?:,?[[:space:]]+UID:(?P<uid>[[:digit:]]+))?
+        (?:[[:space:]]+pgtables:(?P<pgtables>[[:digit:]]+)kB)?
+        (?:[[:space:]]+oom_score_adj:(?P<oom_score_adj>-?[[:digit:]]+))?"
+    )
+    .unwrap();
+    static ref RE_REAPED: Regex =
+        Regex::new(r"^oom_reaper: reaped process (?P<pid>[[:digit:]]+) ").unwrap();
+    static ref RE_TASK_ROW: Regex = Regex::new(
+        r"(?x)^
+        \[[[:space:]]*(?P<pid>[[:digit:]]+)\]
+        [[:space:]]+(?P<uid>[[:digit:]]+)
+        [[:space:]]+(?P<tgid>[[:digit:]]+)
+        [[:space:]]+(?P<total_vm>[[:digit:]]+)
+        [[:space:]]+(?P<rss>[[:digit:]]+)
+        [[:space:]]+(?P<pgtables_bytes>[[:digit:]]+)
+        [[:space:]]+(?P<swapents>[[:digit:]]+)
+        [[:space:]]+(?P<oom_score_adj>-?[[:digit:]]+)
+        [[:space:]]+(?P<name>.+)
+        $"
+    )
+    .unwrap();
+}
+
+/// The allocation that invoked the OOM killer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OomTrigger {
+    /// The command (task name) that was allocating
+    pub comm: String,
+    pub gfp_mask: u64,
+    /// The GFP flags as the kernel decodes them, e.g. "GFP_HIGHUSER_MOVABLE|__GFP_COMP"
+    pub gfp_flags: Option<String>,
+    /// The allocation order (-1 when the OOM killer wasn't invoked by an allocation, e.g. a memcg limit or sysrq)
+    pub order: i32,
+    pub oom_score_adj: i32,
+}
+
+/// The process the OOM killer killed, with its memory when it was killed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OomVictim {
+    pub pid: u32,
+    pub comm: String,
+    pub uid: Option<u32>,
+    pub total_vm_kb: u64,
+    pub anon_rss_kb: u64,
+    pub file_rss_kb: u64,
+    pub shmem_rss_kb: Option<u64>,
+    pub pgtables_kb: Option<u64>,
+    pub oom_score_adj: Option<i32>,
+}
+
+impl OomVictim {
+    /// The victim's resident memory (anonymous, file-backed and shared)
+    pub fn rss_kb(&self) -> u64 {
+        self.anon_rss_kb + self.file_rss_kb + self.shmem_rss_kb.unwrap_or_default()
+    }
+}
+
+/// A row of the task table (memory values are in pages)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OomTask {
+    pub pid: u32,
+    pub uid: u32,
+    pub tgid: u32,
+    pub total_vm: u64,
+    pub rss: u64,
+    pub pgtables_bytes: u64,
+    pub swapents: u64,
+    pub oom_score_adj: i32,
+    pub name: String,
+}
+
+/// An invocation of the OOM killer, and the entries it was assembled from
+#[derive(Debug, Clone, PartialEq)]
+pub struct OomEvent {
+    pub trigger: OomTrigger,
+    /// The time since boot of the first entry
+    pub timestamp_from_system_start: Option<Duration>,
+    /// The task that was allocating (CPU, PID, command, taint flags and kernel release)
+    pub task: Option<TaskInfo>,
+    pub call_trace: Vec<Frame>,
+    /// The constraint the OOM was under, e.g. "CONSTRAINT_NONE" or "CONSTRAINT_MEMCG"
+    pub constraint: Option<String>,
+    /// The memory cgroup that ran out of memory, for a cgroup OOM
+    pub memcg: Option<String>,
+    /// The memory cgroup of the victim
+    pub task_memcg: Option<String>,
+    /// The task table
+    pub tasks: Vec<OomTask>,
+    /// The process that was killed (`None` when none could be)
+    pub victim: Option<OomVictim>,
+    /// Whether the OOM reaper reclaimed the victim's memory
+    pub reaped: bool,
+    /// Every entry of the event
+    pub entries: Vec<Entry>,
+}
+
+impl OomEvent {
+    fn new(trigger: OomTrigger, entry: &Entry) -> Self {
+        Self {
+            trigger,
+            timestamp_from_system_start: entry.timestamp_from_system_start,
+            task: None,
+            call_trace: Vec::new(),
+            constraint: None,
+            memcg: None,
+            task_memcg: None,
+            tasks: Vec::new(),
+            victim: None,
+            reaped: false,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Whether it was a memory cgroup (rather than the whole system) that ran out of memory
+    pub fn is_memcg_oom(&self) -> bool {
+        self.memcg.is_some() || self.constraint.as_deref() == Some("CONSTRAINT_MEMCG")
+    }
+
+    /// Adds a line, and returns whether the event is over.
+    fn add(&mut self, line: &str, entry: &Entry) -> bool {
+        self.entries.push(entry.clone());
+
+        if let Some(row) = task_row(line) {
+            self.tasks.push(row);
+        } else if let Some(victim) = victim(line) {
+            if line.starts_with("Memory cgroup") {
+                // older kernels print no "oom-kill:" line
+                self.constraint
+                    .get_or_insert_with(|| "CONSTRAINT_MEMCG".to_owned());
+            }
+            self.victim = Some(victim);
+        } else if self.victim.is_some() && RE_REAPED.is_match(line) {
+            self.reaped = true;
+            return true;
+        } else if let Some(fields) = line.strip_prefix("oom-kill:") {
+            for field in fields.split(',') {
+                match field.split_once('=') {
+                    Some(("constraint", value)) => self.constraint = Some(value.to_owned()),
+                    Some(("oom_memcg", value)) => self.memcg = Some(value.to_owned()),
+                    Some(("task_memcg", value)) => self.task_memcg = Some(value.to_owned()),
+                    _ => {}
+                }
+            }
+        } else if let Some(path) = line
+            .strip_prefix("Memory cgroup stats for ")
+            .and_then(|path| path.strip_suffix(':'))
+        {
+            self.memcg.get_or_insert_with(|| path.to_owned());
+        } else if let Some(task) = TaskInfo::parse(line) {
+            self.task = Some(task);
+        } else if let Some(frame) = Frame::parse(line) {
+            self.call_trace.push(frame);
+        } else if line.starts_with("Out of memory and no killable processes") {
+            return true;
+        }
+
+        self.entries.len() >= MAX_OOM_ENTRIES
+    }
+}
+
+/// Finds `OomEvent`s among entries
+#[derive(Debug, Default)]
+pub struct OomAnalyzer {
+    current: Option<OomEvent>,
+}
+
+impl OomAnalyzer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Analyzer for OomAnalyzer {
+    type Report = OomEvent;
+
+    fn push(&mut self, entry: &Entry) -> Vec<OomEvent> {
+        let line = entry.message.trim();
+        let trigger = trigger(line);
+        let mut reports = Vec::new();
+
+        if let Some(event) = &self.current {
+            // once the victim was killed, only the reaper's line may follow
+            let reaping = match (&event.victim, RE_REAPED.captures(line)) {
+                (Some(victim), Some(parts)) => parts["pid"].parse() == Ok(victim.pid),
+                _ => false,
+            };
+            if trigger.is_some() || (event.victim.is_some() && !reaping) {
+                reports.extend(self.current.take());
+            }
+        }
+
+        let event = match (&mut self.current, trigger) {
+            (Some(event), _) => event,
+            (None, Some(trigger)) => self.current.insert(OomEvent::new(trigger, entry)),
+            (None, None) => return reports,
+        };
+
+        if event.add(line, entry) {
+            reports.extend(self.current.take());
+        }
+        reports
+    }
+
+    fn finish(&mut self) -> Vec<OomEvent> {
+        self.current.take().into_iter().collect()
+    }
+}
+
+fn trigger(line: &str) -> Option<OomTrigger> {
+    let parts = RE_INVOKED.captures(line)?;
+    Some(OomTrigger {
+        comm: parts["comm"].to_owned(),
+        gfp_mask: u64::from_str_radix(&parts["gfp_mask"], 16).ok()?,
+        gfp_flags: parts.name("gfp_flags").map(|f| f.as_str().to_owned()),
+        order: parts["order"].parse().ok()?,
+        oom_score_adj: parts["oom_score_adj"].parse().ok()?,
+    })
+}
+
+fn victim(line: &str) -> Option<OomVictim> {
+    let parts = RE_KILLED.captures(line)?;
+    Some(OomVictim {
+        pid: number(&parts, "pid")?,
+        comm: parts["comm"].to_owned(),
+        uid: number(&parts, "uid"),
+        total_vm_kb: number(&parts, "total_vm")?,
+        anon_rss_kb: number(&parts, "anon_rss")?,
+        file_rss_kb: number(&parts, "file_rss")?,
+        shmem_rss_kb: number(&parts, "shmem_rss"),
+        pgtables_kb: number(&parts, "pgtables"),
+        oom_score_adj: number(&parts, "oom_score_adj"),
+    })
+}
+
+fn task_row(line: &str) -> Option<OomTask> {
+    let parts = RE_TASK_ROW.captures(line)?;
+    Some(OomTask {
+        pid: number(&parts, "pid")?,
+        uid: number(&parts, "uid")?,
+        tgid: number(&parts, "tgid")?,
+        total_vm: number(&parts, "total_vm")?,
+        rss: number(&parts, "rss")?,
+        pgtables_bytes: number(&parts, "pgtables_bytes")?,
+        swapents: number(&parts, "swapents")?,
+        oom_score_adj: number(&parts, "oom_score_adj")?,
+        name: parts["name"].to_owned(),
+    })
+}
+
+fn number<N: FromStr>(parts: &Captures, name: &str) -> Option<N> {
+    parts.name(name)?.as_str().parse().ok()
+}
+
+/**********************************************************************************/
+// Tests! Tests! Tests!
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis;
+    use crate::klogctl;
+
+    const MEMCG_OOM: &str = r"<4>[  300.000000] stress invoked oom-killer: gfp_mask=0xcc0(GFP_KERNEL), order=0, oom_score_adj=0
+<4>[  300.000001] CPU: 1 PID: 1234 Comm: stress Not tainted 6.1.0 #1
+<4>[  300.000002] Call Trace:
+<4>[  300.000003]  <TASK>
+<4>[  300.000004]  dump_stack_lvl+0x44/0x5c
+<4>[  300.000005]  dump_header+0x4a/0x211
+<4>[  300.000006]  oom_kill_process.cold+0xb/0x10
+<4>[  300.000007]  out_of_memory+0x1ed/0x2e0
+<4>[  300.000008]  </TASK>
+<6>[  300.000009] memory: usage 524288kB, limit 524288kB, failcnt 123
+<6>[  300.000010] swap: usage 0kB, limit 0kB, failcnt 0
+<6>[  300.000011] Memory cgroup stats for /system.slice/stress.service:
+<6>[  300.000012] anon 530000000
+<6>[  300.000013] Tasks state (memory values in pages):
+<6>[  300.000014] [  pid  ]   uid  tgid total_vm      rss pgtables_bytes swapents oom_score_adj name
+<6>[  300.000015] [   1233]  1000  1233     2000      100    49152        0             0 sh
+<6>[  300.000016] [   1234]  1000  1234   263000   250000  2097152        0          -100 stress
+<6>[  300.000017] oom-kill:constraint=CONSTRAINT_MEMCG,nodemask=(null),cpuset=/,mems_allowed=0,oom_memcg=/system.slice/stress.service,task_memcg=/system.slice/stress.service,task=stress,pid=1234,uid=1000
+<3>[  300.000018] Memory cgroup out of memory: Killed process 1234 (stress) total-vm:1052000kB, anon-rss:999000kB, file-rss:4kB, shmem-rss:8kB, UID:1000 pgtables:2048kB oom_score_adj:-100
+<6>[  300.000019] oom_reaper: reaped process 1234 (stress), now anon-rss:0kB, file-rss:0kB, shmem-rss:0kB
+<6>[  301.000000] eth0: link up";
+
+    #[test]
+    fn test_memcg_oom() {
+        let entries = klogctl::entries_from_lines(MEMCG_OOM).unwrap();
+        let events = analysis::analyze(OomAnalyzer::new(), &entries);
+        assert_eq!(events.len(), 1);
+
+        let event = &events[0];
+        assert_eq!(
+            event.trigger,
+            OomTrigger {
+                comm: "stress".to_owned(),
+                gfp_mask: 0xcc0,
+                gfp_flags: Some("GFP_KERNEL".to_owned()),
+                order: 0,
+                oom_score_adj: 0,
+            }
+        );
+        assert_eq!(event.task.as_ref().unwrap().pid, 1234);
+        assert_eq!(event.call_trace.len(), 4);
+        assert_eq!(event.constraint.as_deref(), Some("CONSTRAINT_MEMCG"));
+        assert_eq!(event.memcg.as_deref(), Some("/system.slice/stress.service"));
+        assert!(event.is_memcg_oom());
+        assert_eq!(event.tasks.len(), 2);
+        assert_eq!(event.tasks[1].rss, 250000);
+        assert_eq!(event.tasks[1].oom_score_adj, -100);
+        assert_eq!(event.tasks[1].name, "stress");
+
+        let victim = event.victim.as_ref().unwrap();
+        assert_eq!((victim.pid, victim.comm.as_str()), (1234, "stress"));
+        assert_eq!(victim.uid, Some(1000));
+        assert_eq!(victim.rss_kb(), 999_012);
+        assert_eq!(victim.pgtables_kb, Some(2048));
+        assert_eq!(victim.oom_score_adj, Some(-100));
+        assert!(event.reaped);
+        assert_eq!(event.entries.len(), 20);
+    }
+
+    #[test]
+    fn test_global_oom() {
+        let entries = klogctl::entries_from_lines(
+            "<4>[   20.000000] java invoked oom-killer: gfp_mask=0x140cca(GFP_HIGHUSER_MOVABLE|__GFP_COMP), order=0, oom_score_adj=0\n\
+             <4>[   20.000001] Mem-Info:\n\
+             <4>[   20.000002] oom-kill:constraint=CONSTRAINT_NONE,nodemask=(null),cpuset=/,mems_allowed=0,global_oom,task_memcg=/user.slice,task=java,pid=99,uid=0\n\
+             <3>[   20.000003] Out of memory: Killed process 99 (java) total-vm:4000kB, anon-rss:3000kB, file-rss:0kB, shmem-rss:0kB, UID:0 pgtables:100kB oom_score_adj:0\n\
+             <4>[   21.000000] cron invoked oom-killer: gfp_mask=0xcc0(GFP_KERNEL), order=-1, oom_score_adj=0",
+        )
+        .unwrap();
+        let events = analysis::analyze(OomAnalyzer::new(), &entries);
+        assert_eq!(events.len(), 2);
+
+        assert!(!events[0].is_memcg_oom());
+        assert_eq!(events[0].task_memcg.as_deref(), Some("/user.slice"));
+        assert_eq!(events[0].victim.as_ref().unwrap().comm, "java");
+        assert!(!events[0].reaped);
+        assert_eq!(events[0].entries.len(), 4);
+
+        // cut short by the end of the log
+        assert_eq!(events[1].trigger.order, -1);
+        assert_eq!(events[1].victim, None);
+    }
+}
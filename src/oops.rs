@@ -0,0 +1,341 @@
+use crate::analysis::{self, Analyzer, Frame, TaskInfo, TraceReport};
+/// This module groups the many entries a kernel crash report spans (the "BUG:" or "Oops:"
+/// header, CPU/PID/Comm, Hardware name, RIP, the registers, the Call Trace and the
+/// `---[ end trace ]---` marker) into a single `KernelIncident`, with the fields crash
+/// triage usually looks for parsed out of it.
+///
+/// A report is assumed to continue until its end marker, or until, after its call trace,
+/// a line that can't be part of it comes along. A report whose end marker was seen is
+/// only handed back with the next entry, since a "Kernel panic" line may follow it.
+///
+use crate::entry::Entry;
+
+use std::time::Duration;
+use strum_macros::Display;
+
+/// What a kernel incident was reported as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum IncidentKind {
+    /// "BUG: ..." (e.g. a NULL pointer dereference)
+    Bug,
+    /// "kernel BUG at file:line!" (a `BUG()`/`BUG_ON()` assertion)
+    KernelBug,
+    /// "Oops: ..." or "Unable to handle kernel ..."
+    Oops,
+    /// "general protection fault ..."
+    GeneralProtectionFault,
+    /// "Kernel panic - not syncing: ..." without a report before it
+    Panic,
+}
+
+/// A kernel crash report, and the entries it was parsed from
+#[derive(Debug, Clone, PartialEq)]
+pub struct KernelIncident {
+    pub kind: IncidentKind,
+    /// The line the report started with, e.g. "BUG: kernel NULL pointer dereference, address: 0000000000000000"
+    pub summary: String,
+    /// The time since boot of the first entry
+    pub timestamp_from_system_start: Option<Duration>,
+    /// The task that was running (CPU, PID, command, taint flags and kernel release)
+    pub task: Option<TaskInfo>,
+    /// The "Hardware name:" line
+    pub hardware: Option<String>,
+    /// The function the fault happened in (from RIP, or pc on arm64)
+    pub faulting_frame: Option<Frame>,
+    /// The modules loaded at the time (without their taint flags)
+    pub modules: Vec<String>,
+    pub call_trace: Vec<Frame>,
+    /// The reason given when the incident led to a panic
+    pub panic: Option<String>,
+    /// Whether the report's end marker was seen (an incomplete report was cut short)
+    pub complete: bool,
+    /// Every entry of the report
+    pub entries: Vec<Entry>,
+}
+
+impl KernelIncident {
+    fn from_report(kind: IncidentKind, summary: String, report: TraceReport) -> Self {
+        Self {
+            kind,
+            summary,
+            timestamp_from_system_start: report
+                .entries
+                .first()
+                .and_then(|entry| entry.timestamp_from_system_start),
+            task: report.task,
+            hardware: report.hardware,
+            faulting_frame: report.faulting_frame,
+            modules: report.modules,
+            call_trace: report.call_trace,
+            panic: report.panic,
+            complete: report.complete,
+            entries: report.entries,
+        }
+    }
+}
+
+/// Finds `KernelIncident`s among entries
+#[derive(Debug, Default)]
+pub struct OopsAnalyzer {
+    current: Option<(IncidentKind, String, TraceReport)>,
+}
+
+impl OopsAnalyzer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn close(&mut self) -> Option<KernelIncident> {
+        self.current
+            .take()
+            .map(|(kind, summary, report)| KernelIncident::from_report(kind, summary, report))
+    }
+}
+
+impl Analyzer for OopsAnalyzer {
+    type Report = KernelIncident;
+
+    fn push(&mut self, entry: &Entry) -> Vec<KernelIncident> {
+        let line = entry.message.trim();
+        let header = header_kind(line);
+        let mut reports = Vec::new();
+
+        if let Some((_, _, report)) = &self.current {
+            let belongs = match header {
+                Some(IncidentKind::Panic) => true,
+                _ if report.has_ended() => false,
+                // e.g. the "Oops:" line after "BUG: kernel NULL pointer dereference"
+                Some(_) => !report.has_body(),
+                None => report.continues_with(line),
+            };
+            if !belongs {
+                reports.extend(self.close());
+            }
+        }
+
+        let report = match (&mut self.current, header) {
+            (Some((_, _, report)), _) => report,
+            (None, Some(kind)) => {
+                &mut self
+                    .current
+                    .insert((kind, line.to_owned(), TraceReport::default()))
+                    .2
+            }
+            (None, None) => return reports,
+        };
+
+        if report.add(line, entry) {
+            reports.extend(self.close());
+        }
+        reports
+    }
+
+    fn finish(&mut self) -> Vec<KernelIncident> {
+        self.close().into_iter().collect()
+    }
+}
+
+/// The kind of incident a line starts, if any
+pub(crate) fn header_kind(line: &str) -> Option<IncidentKind> {
+    if line.starts_with("BUG: ") {
+        Some(IncidentKind::Bug)
+    } else if line.starts_with("kernel BUG at ") {
+        Some(IncidentKind::KernelBug)
+    } else if line.starts_with("Oops: ") || line.starts_with("Unable to handle kernel ") {
+        Some(IncidentKind::Oops)
+    } else if line.starts_with("general protection fault") {
+        Some(IncidentKind::GeneralProtectionFault)
+    } else if analysis::panic_reason(line).is_some() {
+        Some(IncidentKind::Panic)
+    } else {
+        None
+    }
+}
+
+/**********************************************************************************/
+// Tests! Tests! Tests!
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::klogctl;
+
+    const NULL_DEREF: &str = r"<6>[  100.000000] crash: loading out-of-tree module taints kernel.
+<1>[  100.100000] BUG: kernel NULL pointer dereference, address: 0000000000000000
+<1>[  100.100001] #PF: supervisor read access in kernel mode
+<1>[  100.100002] #PF: error_code(0x0000) - not-present page
+<6>[  100.100003] PGD 0 P4D 0
+<4>[  100.100004] Oops: 0000 [#1] PREEMPT SMP NOPTI
+<4>[  100.100005] CPU: 2 PID: 1234 Comm: insmod Tainted: G           O      6.1.0-13-amd64 #1
+<4>[  100.100006] Hardware name: QEMU Standard PC (i440FX + PIIX, 1996), BIOS 1.15.0-1 04/01/2014
+<4>[  100.100007] RIP: 0010:crash_init+0x5/0x1000 [crash]
+<4>[  100.100008] Code: Unable to access opcode bytes at 0xffffffffc0a0dfdb.
+<4>[  100.100009] RSP: 0018:ffffc90000b4fd38 EFLAGS: 00010246
+<4>[  100.100010] RAX: 0000000000000000 RBX: 0000000000000000 RCX: 0000000000000000
+<4>[  100.100011] Call Trace:
+<4>[  100.100012]  <TASK>
+<4>[  100.100013]  do_one_initcall+0x41/0x200
+<4>[  100.100014]  ? kmem_cache_alloc_trace+0x16b/0x2c0
+<4>[  100.100015]  do_init_module+0x4c/0x1f0
+<4>[  100.100016]  entry_SYSCALL_64_after_hwframe+0x63/0xcd
+<4>[  100.100017] RIP: 0033:0x7f5b2a1e5a3d
+<4>[  100.100018]  </TASK>
+<4>[  100.100019] Modules linked in: crash(O+) nls_utf8 isofs [last unloaded: crash]
+<4>[  100.100020] CR2: 0000000000000000
+<4>[  100.100021] ---[ end trace 0000000000000000 ]---
+<6>[  101.000000] eth0: link up";
+
+    #[test]
+    fn test_null_deref() {
+        let entries = klogctl::entries_from_lines(NULL_DEREF).unwrap();
+        let incidents = analysis::analyze(OopsAnalyzer::new(), &entries);
+        assert_eq!(incidents.len(), 1);
+
+        let incident = &incidents[0];
+        assert_eq!(incident.kind, IncidentKind::Bug);
+        assert_eq!(
+            incident.summary,
+            "BUG: kernel NULL pointer dereference, address: 0000000000000000"
+        );
+        assert_eq!(
+            incident.timestamp_from_system_start,
+            Some(Duration::from_millis(100_100))
+        );
+        let task = incident.task.as_ref().unwrap();
+        assert_eq!(
+            (task.cpu, task.pid, task.comm.as_str()),
+            (2, 1234, "insmod")
+        );
+        assert_eq!(task.taint.as_deref(), Some("GO"));
+        assert_eq!(task.kernel_release, "6.1.0-13-amd64");
+        assert!(incident.hardware.as_deref().unwrap().starts_with("QEMU"));
+        assert_eq!(
+            incident.faulting_frame.as_ref().map(|f| f.to_string()),
+            Some("crash_init+0x5/0x1000 [crash]".to_owned())
+        );
+        assert_eq!(incident.modules, vec!["crash", "nls_utf8", "isofs"]);
+        let functions: Vec<&str> = incident
+            .call_trace
+            .iter()
+            .map(|f| f.function.as_str())
+            .collect();
+        assert_eq!(
+            functions,
+            vec![
+                "do_one_initcall",
+                "kmem_cache_alloc_trace",
+                "do_init_module",
+                "entry_SYSCALL_64_after_hwframe"
+            ]
+        );
+        assert!(!incident.call_trace[1].reliable);
+        assert!(incident.complete);
+        assert_eq!(incident.panic, None);
+        assert_eq!(incident.entries.len(), 22);
+    }
+
+    #[test]
+    fn test_panics() {
+        // a report leading to a panic
+        let lines = NULL_DEREF.replace(
+            "<6>[  101.000000] eth0: link up",
+            "<0>[  100.200000] Kernel panic - not syncing: Fatal exception\n\
+             <0>[  100.200001] Kernel Offset: disabled\n\
+             <0>[  100.200002] ---[ end Kernel panic - not syncing: Fatal exception ]---",
+        );
+        let mut analyzer = OopsAnalyzer::new();
+        let mut incidents = Vec::new();
+        for entry in klogctl::entries_from_lines(&lines).unwrap() {
+            incidents.extend(analyzer.push(&entry));
+        }
+        // handed back on the panic's end marker, without waiting for another entry
+        assert_eq!(incidents.len(), 1);
+        assert_eq!(incidents[0].kind, IncidentKind::Bug);
+        assert_eq!(incidents[0].panic.as_deref(), Some("Fatal exception"));
+        assert!(analyzer.finish().is_empty());
+
+        // a panic on its own
+        let entries = klogctl::entries_from_lines(
+            "<0>[    2.000000] Kernel panic - not syncing: VFS: Unable to mount root fs on unknown-block(0,0)\n\
+             <4>[    2.000001] CPU: 0 PID: 1 Comm: swapper/0 Not tainted 5.15.0 #1\n\
+             <4>[    2.000002] Call Trace:\n\
+             <4>[    2.000003]  dump_stack_lvl+0x45/0x5b\n\
+             <4>[    2.000004]  panic+0x102/0x2c9\n\
+             <6>[    2.000005] unrelated line",
+        )
+        .unwrap();
+        let incidents = analysis::analyze(OopsAnalyzer::new(), &entries);
+        assert_eq!(incidents.len(), 1);
+        assert_eq!(incidents[0].kind, IncidentKind::Panic);
+        assert_eq!(incidents[0].task.as_ref().unwrap().taint, None);
+        assert_eq!(incidents[0].call_trace.len(), 2);
+        assert!(!incidents[0].complete);
+        assert_eq!(incidents[0].entries.len(), 5);
+    }
+
+    #[test]
+    fn test_consecutive_incidents() {
+        let entries = klogctl::entries_from_lines(
+            "<4>[    5.000000] kernel BUG at mm/slub.c:321!\n\
+             <4>[    5.000001] invalid opcode: 0000 [#1] SMP\n\
+             <4>[    5.000002] CPU: 1 PID: 77 Comm: kworker/1:1 Tainted: G      D 5.10.0 #2\n\
+             <4>[    5.000003] Call Trace:\n\
+             <4>[    5.000004]  kfree+0x1a/0x80\n\
+             <4>[    6.000000] general protection fault, probably for non-canonical address 0xdead000000000100: 0000 [#2] SMP\n\
+             <4>[    6.000001] CPU: 3 PID: 78 Comm: bash Tainted: G      D 5.10.0 #2",
+        )
+        .unwrap();
+        let incidents = analysis::analyze(OopsAnalyzer::new(), &entries);
+        let kinds: Vec<IncidentKind> = incidents.iter().map(|i| i.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                IncidentKind::KernelBug,
+                IncidentKind::GeneralProtectionFault
+            ]
+        );
+        assert_eq!(
+            incidents[0].task.as_ref().unwrap().taint.as_deref(),
+            Some("GD")
+        );
+        assert_eq!(incidents[1].task.as_ref().unwrap().pid, 78);
+    }
+
+    #[test]
+    fn test_traceless_header() {
+        let entries = klogctl::entries_from_lines(
+            "<3>[   50.000000] BUG: workqueue lockup - pool cpus=0 node=0 flags=0x0 nice=0 stuck for 35s!\n\
+             <6>[   50.000001] Showing busy workqueues and worker pools:\n\
+             <6>[   51.000000] eth0: link up\n\
+             <6>[   52.000000] systemd[1]: Started Session 3 of user root.",
+        )
+        .unwrap();
+        let incidents = analysis::analyze(OopsAnalyzer::new(), &entries);
+        assert_eq!(incidents.len(), 1);
+        assert_eq!(incidents[0].kind, IncidentKind::Bug);
+        // ends with the first line that can't be part of it
+        assert_eq!(incidents[0].entries.len(), 1);
+        assert!(!incidents[0].complete);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_incident_stream() {
+        use crate::error::RMesgError;
+        use tokio_stream::StreamExt;
+
+        let mut source = crate::mock::ScriptedSource::new();
+        for entry in klogctl::entries_from_lines(NULL_DEREF).unwrap() {
+            source.push(crate::mock::ScriptStep::Entry(entry));
+        }
+        source.push(crate::mock::ScriptStep::Error(RMesgError::InternalError(
+            "scripted".to_owned(),
+        )));
+
+        let mut incidents = analysis::AnalyzerStream::new(source, OopsAnalyzer::new());
+        let incident = incidents.next().await.unwrap().unwrap();
+        assert_eq!(incident.faulting_frame.unwrap().function, "crash_init");
+        assert!(incidents.next().await.unwrap().is_err());
+        assert!(incidents.next().await.is_none());
+    }
+}
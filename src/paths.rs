@@ -0,0 +1,153 @@
+/// This module locates the files the kernel log and its settings are read from (and
+/// written to): /dev/kmsg, /sys/module/printk, /proc/sys/kernel, and the rest of /proc, along
+/// with the files that give a container away (e.g. /.dockerenv).
+///
+/// Every function that reads one of them has an `_in` variant taking `SystemPaths`, so that
+/// tests can point it at a temporary directory, and tools can inspect a mounted chroot or a
+/// container's root filesystem. The variants without `_in` use `SystemPaths::default()`.
+///
+/// Note that klogctl is a system call, and always reads the running kernel's log.
+///
+use crate::kmsgfile;
+
+use std::path::{Path, PathBuf};
+
+const SYS_MODULE_PRINTK: &str = "/sys/module/printk";
+const PROC_SYS_KERNEL: &str = "/proc/sys/kernel";
+const PROC: &str = "/proc";
+
+/// Where the kernel log files are found
+#[derive(Debug, Clone, PartialEq)]
+pub struct SystemPaths {
+    /// The root directory, for the files not covered by the other paths (e.g. /.dockerenv)
+    pub root: PathBuf,
+    /// The kmsg device (/dev/kmsg)
+    pub dev_kmsg: PathBuf,
+    /// The printk module's sysfs directory (/sys/module/printk)
+    pub sys_module_printk: PathBuf,
+    /// The kernel's sysctl directory (/proc/sys/kernel)
+    pub proc_sys_kernel: PathBuf,
+    /// Where procfs is mounted (/proc), for process information such as /proc/self/status
+    pub proc: PathBuf,
+}
+
+impl SystemPaths {
+    /// The paths of the running system
+    pub fn new() -> Self {
+        Self {
+            root: PathBuf::from("/"),
+            dev_kmsg: PathBuf::from(kmsgfile::DEV_KMSG_PATH),
+            sys_module_printk: PathBuf::from(SYS_MODULE_PRINTK),
+            proc_sys_kernel: PathBuf::from(PROC_SYS_KERNEL),
+            proc: PathBuf::from(PROC),
+        }
+    }
+
+    /// The same paths, under another root directory (e.g. a chroot or a container's rootfs)
+    pub fn with_root<P: AsRef<Path>>(root: P) -> Self {
+        let root = root.as_ref();
+        let under_root = |path: &str| root.join(path.trim_start_matches('/'));
+
+        Self {
+            root: root.to_path_buf(),
+            dev_kmsg: under_root(kmsgfile::DEV_KMSG_PATH),
+            sys_module_printk: under_root(SYS_MODULE_PRINTK),
+            proc_sys_kernel: under_root(PROC_SYS_KERNEL),
+            proc: under_root(PROC),
+        }
+    }
+
+    /// The printk.time parameter (see `klogctl::SYS_MODULE_PRINTK_PARAMETERS_TIME`)
+    pub fn printk_time(&self) -> PathBuf {
+        self.sys_module_printk.join("parameters").join("time")
+    }
+
+    /// A sysctl under /proc/sys/kernel, e.g. "printk" or "dmesg_restrict"
+    pub fn kernel_sysctl(&self, name: &str) -> PathBuf {
+        self.proc_sys_kernel.join(name)
+    }
+
+    /// Any other file, by its absolute path on the running system, e.g. "/.dockerenv"
+    pub fn root_file(&self, path: &str) -> PathBuf {
+        self.root.join(path.trim_start_matches('/'))
+    }
+
+    /// A file under /proc, e.g. "self/status"
+    pub fn proc_file(&self, name: &str) -> PathBuf {
+        self.proc.join(name)
+    }
+
+    /// The kmsg device, as the `file_override` the `kmsgfile` readers take
+    pub fn dev_kmsg_override(&self) -> Option<String> {
+        Some(self.dev_kmsg.to_string_lossy().into_owned())
+    }
+}
+
+impl Default for SystemPaths {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Creates an empty root directory with the /sys and /proc directories `SystemPaths` expects,
+/// for tests to write fake settings into.
+#[cfg(test)]
+pub(crate) fn temp_root(name: &str) -> SystemPaths {
+    let root = std::env::temp_dir().join(format!("rmesg-{}-{}", name, std::process::id()));
+    let paths = SystemPaths::with_root(&root);
+
+    // start afresh, in case a previous run left it behind
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(paths.printk_time().parent().unwrap()).unwrap();
+    std::fs::create_dir_all(&paths.proc_sys_kernel).unwrap();
+    std::fs::create_dir_all(paths.proc_file("self")).unwrap();
+    paths
+}
+
+/**********************************************************************************/
+// Tests! Tests! Tests!
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::klogctl;
+
+    #[test]
+    fn test_default_paths() {
+        let paths = SystemPaths::default();
+        assert_eq!(
+            paths.printk_time(),
+            Path::new(klogctl::SYS_MODULE_PRINTK_PARAMETERS_TIME)
+        );
+        assert_eq!(
+            paths.kernel_sysctl("printk"),
+            Path::new("/proc/sys/kernel/printk")
+        );
+        assert_eq!(
+            paths.proc_file("self/status"),
+            Path::new("/proc/self/status")
+        );
+    }
+
+    #[test]
+    fn test_with_root() {
+        let paths = SystemPaths::with_root("/mnt/rootfs");
+        assert_eq!(paths.dev_kmsg, Path::new("/mnt/rootfs/dev/kmsg"));
+        assert_eq!(
+            paths.printk_time(),
+            Path::new("/mnt/rootfs/sys/module/printk/parameters/time")
+        );
+        assert_eq!(
+            paths.dev_kmsg_override(),
+            Some("/mnt/rootfs/dev/kmsg".to_owned())
+        );
+        assert_eq!(
+            paths.root_file("/.dockerenv"),
+            Path::new("/mnt/rootfs/.dockerenv")
+        );
+        assert_eq!(
+            paths.proc_file("1/cgroup"),
+            Path::new("/mnt/rootfs/proc/1/cgroup")
+        );
+    }
+}
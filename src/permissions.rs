@@ -0,0 +1,424 @@
+/// This module diagnoses why access to the kernel log buffer was denied, and how to fix it.
+///
+/// The kernel denies reading the log for a handful of distinct reasons that all surface as
+/// EPERM or EACCES:
+///   * kernel.dmesg_restrict=1 without CAP_SYSLOG (EPERM, both for /dev/kmsg and klogctl)
+///   * clearing the buffer without CAP_SYSLOG (EPERM)
+///   * the file mode of /dev/kmsg not permitting reads (EACCES)
+///   * a security module (SELinux, AppArmor, seccomp) vetoing the access (EACCES or EPERM)
+///
+/// `diagnose` (or `diagnose_in`) collects the facts needed to tell these apart.
+///
+use crate::error::RMesgError;
+use crate::paths::SystemPaths;
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+
+/// Capability numbers from linux/capability.h
+pub const CAP_SYS_ADMIN: u32 = 21;
+pub const CAP_SYSLOG: u32 = 34;
+
+/// The most likely reason access was denied
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DenialCause {
+    /// kernel.dmesg_restrict=1 and the process lacks CAP_SYSLOG
+    DmesgRestrict,
+    /// The operation (e.g. clearing the buffer) always requires CAP_SYSLOG, which the process lacks
+    MissingCapSyslog,
+    /// The file mode/ownership of the device does not permit reading it
+    FileMode,
+    /// File permissions and capabilities allow access, so a security module likely denied it
+    SecurityModule,
+    /// None of the above could be established
+    Unknown,
+}
+
+/// Everything that was found out about a denied access
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessDiagnosis {
+    /// The operation that was denied, e.g. "Open File /dev/kmsg"
+    pub operation: String,
+    /// The errno the operation failed with (EPERM or EACCES)
+    pub errno: i32,
+    /// Whether the operation needed CAP_SYSLOG regardless of dmesg_restrict (e.g. clearing)
+    pub requires_cap_syslog: bool,
+    /// The value of /proc/sys/kernel/dmesg_restrict, when readable
+    pub dmesg_restrict: Option<bool>,
+    /// The effective capability set (CapEff in /proc/self/status), when readable
+    pub effective_capabilities: Option<u64>,
+    pub euid: Option<u32>,
+    /// The file involved, if any
+    pub path: Option<String>,
+    /// Permission bits of the file, when it could be inspected
+    pub file_mode: Option<u32>,
+    pub file_uid: Option<u32>,
+    pub file_gid: Option<u32>,
+    /// Whether the file mode permits reading to this process (ignoring capabilities)
+    pub file_readable: Option<bool>,
+}
+
+impl AccessDiagnosis {
+    pub fn has_capability(&self, cap: u32) -> Option<bool> {
+        self.effective_capabilities
+            .map(|caps| caps & (1u64 << cap) != 0)
+    }
+
+    pub fn has_cap_syslog(&self) -> Option<bool> {
+        self.has_capability(CAP_SYSLOG)
+    }
+
+    pub fn has_cap_sys_admin(&self) -> Option<bool> {
+        self.has_capability(CAP_SYS_ADMIN)
+    }
+
+    /// The most likely reason access was denied, given the collected facts.
+    pub fn cause(&self) -> DenialCause {
+        // Historically CAP_SYS_ADMIN is also accepted by the kernel in lieu of CAP_SYSLOG
+        let privileged = match (self.has_cap_syslog(), self.has_cap_sys_admin()) {
+            (Some(syslog), Some(admin)) => Some(syslog || admin),
+            _ => None,
+        };
+
+        if self.errno == libc::EACCES && self.file_readable == Some(false) {
+            return DenialCause::FileMode;
+        }
+
+        match (self.dmesg_restrict, privileged) {
+            (Some(true), Some(false)) => return DenialCause::DmesgRestrict,
+            (_, Some(false)) if self.requires_cap_syslog => return DenialCause::MissingCapSyslog,
+            _ => {}
+        }
+
+        match (privileged, self.file_readable) {
+            (Some(true), Some(true)) | (Some(true), None) => DenialCause::SecurityModule,
+            (Some(false), Some(true)) if self.dmesg_restrict == Some(false) => {
+                DenialCause::SecurityModule
+            }
+            _ => DenialCause::Unknown,
+        }
+    }
+
+    /// Human-readable advice on how to fix the denial
+    pub fn hint(&self) -> String {
+        match self.cause() {
+            DenialCause::DmesgRestrict => "kernel.dmesg_restrict is 1, which limits reading the kernel log to processes with CAP_SYSLOG. \
+                Run as root (e.g. with 'sudo'), grant CAP_SYSLOG (e.g. 'setcap cap_syslog+ep <binary>', or '--cap-add SYSLOG' for a container), \
+                or allow unprivileged reads with 'sysctl -w kernel.dmesg_restrict=0'.".to_owned(),
+            DenialCause::MissingCapSyslog => "This operation (e.g. clearing the ring buffer) requires CAP_SYSLOG. \
+                Run as root (e.g. with 'sudo'), or grant CAP_SYSLOG (e.g. 'setcap cap_syslog+ep <binary>', or '--cap-add SYSLOG' for a container).".to_owned(),
+            DenialCause::FileMode => format!(
+                "{} is mode {:o} owned by uid {} and gid {}, which does not allow reading by uid {}. \
+                Run as root (e.g. with 'sudo'), or add this user to the owning group.",
+                self.path.as_deref().unwrap_or("The file"),
+                self.file_mode.unwrap_or(0) & 0o7777,
+                display_opt(self.file_uid),
+                display_opt(self.file_gid),
+                display_opt(self.euid),
+            ),
+            DenialCause::SecurityModule => "File permissions and capabilities allow this access, so a security module \
+                (SELinux, AppArmor or a seccomp profile) most likely denied it. Check the audit log for denials.".to_owned(),
+            DenialCause::Unknown => "Try running as root (e.g. with 'sudo') or granting CAP_SYSLOG. Inside a container, \
+                the capability must be granted to the container (e.g. '--cap-add SYSLOG'), and the kernel log may not be \
+                readable at all from a non-initial user namespace.".to_owned(),
+        }
+    }
+}
+
+impl Display for AccessDiagnosis {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(
+            f,
+            "{} denied ({}): {}",
+            self.operation,
+            errno::Errno(self.errno),
+            match self.cause() {
+                DenialCause::DmesgRestrict => "kernel.dmesg_restrict=1 and CAP_SYSLOG is missing",
+                DenialCause::MissingCapSyslog => "CAP_SYSLOG is missing",
+                DenialCause::FileMode => "file mode does not permit reading",
+                DenialCause::SecurityModule => "denied by a security module",
+                DenialCause::Unknown => "cause unknown",
+            }
+        )
+    }
+}
+
+/// Reads whether kernel.dmesg_restrict restricts the kernel log to processes with CAP_SYSLOG
+pub fn dmesg_restrict() -> Result<bool, RMesgError> {
+    dmesg_restrict_in(&SystemPaths::default())
+}
+
+/// Same as `dmesg_restrict`, with the sysctl found through `paths`.
+pub fn dmesg_restrict_in(paths: &SystemPaths) -> Result<bool, RMesgError> {
+    Ok(fs::read_to_string(paths.kernel_sysctl("dmesg_restrict"))?.trim() != "0")
+}
+
+/// Collects the facts about a denied `operation` (failed with `errno`) on an optional `path`.
+/// `requires_cap_syslog` is set for operations that need CAP_SYSLOG even when dmesg_restrict=0,
+/// such as clearing the buffer.
+pub fn diagnose(
+    operation: &str,
+    path: Option<&str>,
+    errno: i32,
+    requires_cap_syslog: bool,
+) -> AccessDiagnosis {
+    diagnose_in(
+        &SystemPaths::default(),
+        operation,
+        path,
+        errno,
+        requires_cap_syslog,
+    )
+}
+
+/// Same as `diagnose`, with dmesg_restrict and /proc/self/status found through `paths`.
+pub fn diagnose_in(
+    paths: &SystemPaths,
+    operation: &str,
+    path: Option<&str>,
+    errno: i32,
+    requires_cap_syslog: bool,
+) -> AccessDiagnosis {
+    let dmesg_restrict = dmesg_restrict_in(paths).ok();
+
+    let effective_capabilities = fs::read_to_string(paths.proc_file("self/status"))
+        .ok()
+        .and_then(|status| effective_capabilities_from_status(&status));
+
+    let euid = current_euid();
+
+    let mut diagnosis = AccessDiagnosis {
+        operation: operation.to_owned(),
+        errno,
+        requires_cap_syslog,
+        dmesg_restrict,
+        effective_capabilities,
+        euid,
+        path: path.map(|p| p.to_owned()),
+        file_mode: None,
+        file_uid: None,
+        file_gid: None,
+        file_readable: None,
+    };
+
+    #[cfg(unix)]
+    if let Some(metadata) = path.and_then(|p| fs::metadata(p).ok()) {
+        diagnosis.file_mode = Some(metadata.mode());
+        diagnosis.file_uid = Some(metadata.uid());
+        diagnosis.file_gid = Some(metadata.gid());
+        diagnosis.file_readable = euid.map(|euid| {
+            mode_permits_read(
+                metadata.mode(),
+                metadata.uid(),
+                metadata.gid(),
+                euid,
+                &current_groups(),
+            )
+        });
+    }
+
+    diagnosis
+}
+
+/// Builds the `PermissionDenied` error for a denied operation, along with its diagnosis.
+/// `source` is the error the operation failed with.
+pub(crate) fn denied(
+    operation: String,
+    path: Option<&str>,
+    source: std::io::Error,
+    requires_cap_syslog: bool,
+) -> RMesgError {
+    denied_in(
+        &SystemPaths::default(),
+        operation,
+        path,
+        source,
+        requires_cap_syslog,
+    )
+}
+
+/// Same as `denied`, diagnosed through `paths`.
+pub(crate) fn denied_in(
+    paths: &SystemPaths,
+    operation: String,
+    path: Option<&str>,
+    source: std::io::Error,
+    requires_cap_syslog: bool,
+) -> RMesgError {
+    let errno = source.raw_os_error().unwrap_or_default();
+    let diagnosis = diagnose_in(paths, &operation, path, errno, requires_cap_syslog);
+    RMesgError::PermissionDenied {
+        operation,
+        diagnosis: Box::new(diagnosis),
+        source,
+    }
+}
+
+/// Whether an errno denotes a permission problem that `diagnose` can explain
+pub(crate) fn is_denial(errno: Option<i32>) -> bool {
+    matches!(errno, Some(libc::EPERM) | Some(libc::EACCES))
+}
+
+fn effective_capabilities_from_status(status: &str) -> Option<u64> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))
+        .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+}
+
+fn mode_permits_read(mode: u32, uid: u32, gid: u32, euid: u32, groups: &[u32]) -> bool {
+    if euid == 0 {
+        return true;
+    }
+
+    if uid == euid {
+        mode & 0o400 != 0
+    } else if groups.contains(&gid) {
+        mode & 0o040 != 0
+    } else {
+        mode & 0o004 != 0
+    }
+}
+
+#[cfg(unix)]
+fn current_euid() -> Option<u32> {
+    Some(unsafe { libc::geteuid() })
+}
+
+#[cfg(not(unix))]
+fn current_euid() -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn current_groups() -> Vec<u32> {
+    // a size of zero returns how many supplementary groups there are
+    let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    let mut groups: Vec<libc::gid_t> = vec![0; count.max(0) as usize];
+    let count = unsafe { libc::getgroups(count.max(0), groups.as_mut_ptr()) };
+    groups.truncate(count.max(0) as usize);
+    groups.push(unsafe { libc::getegid() });
+    groups
+}
+
+fn display_opt(value: Option<u32>) -> String {
+    match value {
+        Some(v) => v.to_string(),
+        None => "?".to_owned(),
+    }
+}
+
+/**********************************************************************************/
+// Tests! Tests! Tests!
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn unprivileged() -> AccessDiagnosis {
+        AccessDiagnosis {
+            operation: "Open File /dev/kmsg".to_owned(),
+            errno: libc::EPERM,
+            requires_cap_syslog: false,
+            dmesg_restrict: Some(true),
+            effective_capabilities: Some(0),
+            euid: Some(1000),
+            path: Some("/dev/kmsg".to_owned()),
+            file_mode: Some(0o20644),
+            file_uid: Some(0),
+            file_gid: Some(0),
+            file_readable: Some(true),
+        }
+    }
+
+    #[test]
+    fn test_dmesg_restrict() {
+        let diagnosis = unprivileged();
+        assert_eq!(diagnosis.cause(), DenialCause::DmesgRestrict);
+        assert!(diagnosis.hint().contains("dmesg_restrict"));
+
+        let with_cap_syslog = AccessDiagnosis {
+            effective_capabilities: Some(1 << CAP_SYSLOG),
+            ..unprivileged()
+        };
+        assert_eq!(with_cap_syslog.cause(), DenialCause::SecurityModule);
+    }
+
+    #[test]
+    fn test_clear_requires_cap_syslog() {
+        let diagnosis = AccessDiagnosis {
+            dmesg_restrict: Some(false),
+            requires_cap_syslog: true,
+            ..unprivileged()
+        };
+        assert_eq!(diagnosis.cause(), DenialCause::MissingCapSyslog);
+    }
+
+    #[test]
+    fn test_file_mode() {
+        let diagnosis = AccessDiagnosis {
+            errno: libc::EACCES,
+            dmesg_restrict: Some(false),
+            file_mode: Some(0o20600),
+            file_readable: Some(false),
+            ..unprivileged()
+        };
+        assert_eq!(diagnosis.cause(), DenialCause::FileMode);
+        assert!(diagnosis.hint().contains("mode 600"));
+    }
+
+    #[test]
+    fn test_unknown() {
+        let diagnosis = AccessDiagnosis {
+            dmesg_restrict: None,
+            effective_capabilities: None,
+            file_readable: None,
+            ..unprivileged()
+        };
+        assert_eq!(diagnosis.cause(), DenialCause::Unknown);
+    }
+
+    #[test]
+    fn test_parse_status() {
+        let status = "Name:\trmesg\nCapInh:\t0000000000000000\nCapEff:\t0000000400000000\n";
+        assert_eq!(
+            effective_capabilities_from_status(status),
+            Some(1 << CAP_SYSLOG)
+        );
+    }
+
+    #[test]
+    fn test_diagnose_in() {
+        let paths = crate::paths::temp_root("diagnose");
+        fs::write(paths.kernel_sysctl("dmesg_restrict"), "1\n").unwrap();
+        fs::write(
+            paths.proc_file("self/status"),
+            "Name:\trmesg\nCapEff:\t0000000000000000\n",
+        )
+        .unwrap();
+
+        let diagnosis = diagnose_in(&paths, "Open File /dev/kmsg", None, libc::EPERM, false);
+        assert_eq!(diagnosis.dmesg_restrict, Some(true));
+        assert_eq!(diagnosis.effective_capabilities, Some(0));
+        assert_eq!(diagnosis.cause(), DenialCause::DmesgRestrict);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_current_groups() {
+        let groups = current_groups();
+        assert!(groups.contains(&unsafe { libc::getegid() }));
+        let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+        assert_eq!(groups.len(), count as usize + 1);
+    }
+
+    #[test]
+    fn test_mode_permits_read() {
+        assert!(mode_permits_read(0o644, 0, 0, 1000, &[1000]));
+        assert!(!mode_permits_read(0o640, 0, 4, 1000, &[1000]));
+        assert!(mode_permits_read(0o640, 0, 4, 1000, &[1000, 4]));
+        assert!(mode_permits_read(0o600, 0, 0, 0, &[]));
+    }
+}
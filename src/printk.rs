@@ -0,0 +1,318 @@
+use crate::entry::LogLevel;
+/// This module reads and writes the kernel's printk settings under /proc/sys/kernel:
+/// which levels of messages are printed to the console, how messages are rate-limited,
+/// and whether /dev/kmsg accepts writes. Writing them requires root (CAP_SYS_ADMIN).
+///
+/// Whether printk timestamps are enabled is in `klogctl::klog_timestamps_enabled`
+/// (and `klogctl::klog_timestamps_enable`).
+///
+use crate::error::RMesgError;
+use crate::paths::SystemPaths;
+
+use num::FromPrimitive;
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use strum_macros::{Display as StrumDisplay, EnumString};
+
+/// The path under /proc where the console log levels reside
+pub const PROC_SYS_KERNEL_PRINTK: &str = "/proc/sys/kernel/printk";
+
+/// The path under /proc where access to /dev/kmsg from userspace is configured
+pub const PROC_SYS_KERNEL_PRINTK_DEVKMSG: &str = "/proc/sys/kernel/printk_devkmsg";
+
+/// The path under /proc where the interval of printk_ratelimit() resides (in seconds)
+pub const PROC_SYS_KERNEL_PRINTK_RATELIMIT: &str = "/proc/sys/kernel/printk_ratelimit";
+
+/// The path under /proc where the burst of messages printk_ratelimit() allows resides
+pub const PROC_SYS_KERNEL_PRINTK_RATELIMIT_BURST: &str = "/proc/sys/kernel/printk_ratelimit_burst";
+
+// The names of the sysctls above, under /proc/sys/kernel (see `SystemPaths::kernel_sysctl`)
+const PRINTK: &str = "printk";
+const PRINTK_DEVKMSG: &str = "printk_devkmsg";
+const PRINTK_RATELIMIT: &str = "printk_ratelimit";
+const PRINTK_RATELIMIT_BURST: &str = "printk_ratelimit_burst";
+
+/// The four log levels in /proc/sys/kernel/printk.
+///
+/// The kernel prints a message to the console when its level is more severe than the console
+/// log level. The console levels here are expressed as the least severe `LogLevel` still printed,
+/// the way `dmesg -n` takes them (e.g. a console log level of 4 is `LogLevel::Error`). Console log
+/// levels of 8 and above let every message through, and are read as `LogLevel::Debug`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConsoleLogLevels {
+    /// The least severe level of messages printed to the console
+    pub console_loglevel: LogLevel,
+    /// The level of messages printed without an explicit level
+    pub default_message_loglevel: LogLevel,
+    /// The most severe level the console log level can be set to
+    pub minimum_console_loglevel: LogLevel,
+    /// The console log level at boot
+    pub default_console_loglevel: LogLevel,
+}
+
+/// Access to /dev/kmsg from userspace (the kernel.printk_devkmsg sysctl)
+#[derive(EnumString, Debug, PartialEq, StrumDisplay, Copy, Clone)]
+pub enum DevKMsgAccess {
+    /// Userspace may write to /dev/kmsg without a limit
+    #[strum(serialize = "on")]
+    On,
+
+    /// Writes to /dev/kmsg are ignored
+    #[strum(serialize = "off")]
+    Off,
+
+    /// Writes to /dev/kmsg are rate-limited (the default)
+    #[strum(serialize = "ratelimit")]
+    Ratelimit,
+}
+
+/// Reads the console log levels from /proc/sys/kernel/printk
+pub fn console_log_levels() -> Result<ConsoleLogLevels, RMesgError> {
+    console_log_levels_in(&SystemPaths::default())
+}
+
+/// Same as `console_log_levels`, with the sysctl found through `paths`.
+pub fn console_log_levels_in(paths: &SystemPaths) -> Result<ConsoleLogLevels, RMesgError> {
+    let path = paths.kernel_sysctl(PRINTK);
+    console_log_levels_from_str(&fs::read_to_string(&path)?, &path)
+}
+
+/// Writes all four console log levels to /proc/sys/kernel/printk
+pub fn set_console_log_levels(levels: &ConsoleLogLevels) -> Result<(), RMesgError> {
+    set_console_log_levels_in(&SystemPaths::default(), levels)
+}
+
+/// Same as `set_console_log_levels`, with the sysctl found through `paths`.
+pub fn set_console_log_levels_in(
+    paths: &SystemPaths,
+    levels: &ConsoleLogLevels,
+) -> Result<(), RMesgError> {
+    write_setting(
+        &paths.kernel_sysctl(PRINTK),
+        console_log_levels_to_string(levels),
+    )
+}
+
+/// Sets only the console log level, i.e. the least severe level printed to the console
+pub fn set_console_loglevel(level: LogLevel) -> Result<(), RMesgError> {
+    set_console_loglevel_in(&SystemPaths::default(), level)
+}
+
+/// Same as `set_console_loglevel`, with the sysctl found through `paths`.
+pub fn set_console_loglevel_in(paths: &SystemPaths, level: LogLevel) -> Result<(), RMesgError> {
+    // the kernel leaves the levels that aren't written as they are
+    write_setting(&paths.kernel_sysctl(PRINTK), console_value(level))
+}
+
+/// Reads the interval printk_ratelimit() limits messages over
+pub fn printk_ratelimit() -> Result<Duration, RMesgError> {
+    printk_ratelimit_in(&SystemPaths::default())
+}
+
+/// Same as `printk_ratelimit`, with the sysctl found through `paths`.
+pub fn printk_ratelimit_in(paths: &SystemPaths) -> Result<Duration, RMesgError> {
+    Ok(Duration::from_secs(read_setting(
+        &paths.kernel_sysctl(PRINTK_RATELIMIT),
+    )?))
+}
+
+/// Sets the interval printk_ratelimit() limits messages over (at a granularity of seconds)
+pub fn set_printk_ratelimit(interval: Duration) -> Result<(), RMesgError> {
+    set_printk_ratelimit_in(&SystemPaths::default(), interval)
+}
+
+/// Same as `set_printk_ratelimit`, with the sysctl found through `paths`.
+pub fn set_printk_ratelimit_in(paths: &SystemPaths, interval: Duration) -> Result<(), RMesgError> {
+    write_setting(&paths.kernel_sysctl(PRINTK_RATELIMIT), interval.as_secs())
+}
+
+/// Reads the number of messages printk_ratelimit() allows per interval
+pub fn printk_ratelimit_burst() -> Result<u32, RMesgError> {
+    printk_ratelimit_burst_in(&SystemPaths::default())
+}
+
+/// Same as `printk_ratelimit_burst`, with the sysctl found through `paths`.
+pub fn printk_ratelimit_burst_in(paths: &SystemPaths) -> Result<u32, RMesgError> {
+    read_setting(&paths.kernel_sysctl(PRINTK_RATELIMIT_BURST))
+}
+
+/// Sets the number of messages printk_ratelimit() allows per interval
+pub fn set_printk_ratelimit_burst(burst: u32) -> Result<(), RMesgError> {
+    set_printk_ratelimit_burst_in(&SystemPaths::default(), burst)
+}
+
+/// Same as `set_printk_ratelimit_burst`, with the sysctl found through `paths`.
+pub fn set_printk_ratelimit_burst_in(paths: &SystemPaths, burst: u32) -> Result<(), RMesgError> {
+    write_setting(&paths.kernel_sysctl(PRINTK_RATELIMIT_BURST), burst)
+}
+
+/// Reads whether (and how) userspace may write to /dev/kmsg
+pub fn printk_devkmsg() -> Result<DevKMsgAccess, RMesgError> {
+    printk_devkmsg_in(&SystemPaths::default())
+}
+
+/// Same as `printk_devkmsg`, with the sysctl found through `paths`.
+pub fn printk_devkmsg_in(paths: &SystemPaths) -> Result<DevKMsgAccess, RMesgError> {
+    read_setting(&paths.kernel_sysctl(PRINTK_DEVKMSG))
+}
+
+/// Sets whether (and how) userspace may write to /dev/kmsg.
+/// This fails when printk.devkmsg was set on the kernel command line, which locks it.
+pub fn set_printk_devkmsg(access: DevKMsgAccess) -> Result<(), RMesgError> {
+    set_printk_devkmsg_in(&SystemPaths::default(), access)
+}
+
+/// Same as `set_printk_devkmsg`, with the sysctl found through `paths`.
+pub fn set_printk_devkmsg_in(paths: &SystemPaths, access: DevKMsgAccess) -> Result<(), RMesgError> {
+    write_setting(&paths.kernel_sysctl(PRINTK_DEVKMSG), access)
+}
+
+fn read_setting<T: FromStr>(path: &Path) -> Result<T, RMesgError> {
+    let value = fs::read_to_string(path)?;
+    match T::from_str(value.trim()) {
+        Ok(setting) => Ok(setting),
+        Err(_) => Err(RMesgError::UnexpectedSettingValue {
+            path: path.display().to_string(),
+            value,
+        }),
+    }
+}
+
+fn write_setting<T: Display>(path: &Path, value: T) -> Result<(), RMesgError> {
+    Ok(fs::write(path, format!("{}\n", value))?)
+}
+
+fn console_log_levels_from_str(value: &str, path: &Path) -> Result<ConsoleLogLevels, RMesgError> {
+    let unexpected = || RMesgError::UnexpectedSettingValue {
+        path: path.display().to_string(),
+        value: value.to_owned(),
+    };
+
+    let levels: Vec<u32> = match value.split_whitespace().map(u32::from_str).collect() {
+        Ok(levels) => levels,
+        Err(_) => return Err(unexpected()),
+    };
+
+    match levels[..] {
+        [console, default_message, minimum_console, default_console] => Ok(ConsoleLogLevels {
+            console_loglevel: console_level(console),
+            default_message_loglevel: message_level(default_message),
+            minimum_console_loglevel: console_level(minimum_console),
+            default_console_loglevel: console_level(default_console),
+        }),
+        _ => Err(unexpected()),
+    }
+}
+
+fn console_log_levels_to_string(levels: &ConsoleLogLevels) -> String {
+    format!(
+        "{} {} {} {}",
+        console_value(levels.console_loglevel),
+        levels.default_message_loglevel as u32,
+        console_value(levels.minimum_console_loglevel),
+        console_value(levels.default_console_loglevel)
+    )
+}
+
+/// A console log level lets messages of a lower level through
+fn console_level(value: u32) -> LogLevel {
+    message_level(value.saturating_sub(1))
+}
+
+fn console_value(level: LogLevel) -> u32 {
+    level as u32 + 1
+}
+
+fn message_level(value: u32) -> LogLevel {
+    LogLevel::from_u32(value).unwrap_or(LogLevel::Debug)
+}
+
+/**********************************************************************************/
+// Tests! Tests! Tests!
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn levels_from_str(value: &str) -> Result<ConsoleLogLevels, RMesgError> {
+        console_log_levels_from_str(value, Path::new(PROC_SYS_KERNEL_PRINTK))
+    }
+
+    #[test]
+    fn test_console_log_levels() {
+        assert_eq!(
+            levels_from_str("4\t4\t1\t7\n").unwrap(),
+            ConsoleLogLevels {
+                console_loglevel: LogLevel::Error,
+                default_message_loglevel: LogLevel::Warning,
+                minimum_console_loglevel: LogLevel::Emergency,
+                default_console_loglevel: LogLevel::Info,
+            }
+        );
+
+        // "debug" on the kernel command line sets the console log level to 10
+        let levels = levels_from_str("10 4 1 7").unwrap();
+        assert_eq!(levels.console_loglevel, LogLevel::Debug);
+
+        assert!(levels_from_str("4 4 1").is_err());
+        assert!(levels_from_str("four 4 1 7").is_err());
+    }
+
+    #[test]
+    fn test_console_log_levels_round_trip() {
+        let levels = levels_from_str("4 4 1 7").unwrap();
+        assert_eq!(console_log_levels_to_string(&levels), "4 4 1 7");
+
+        let verbose = ConsoleLogLevels {
+            console_loglevel: LogLevel::Debug,
+            ..levels
+        };
+        assert_eq!(console_log_levels_to_string(&verbose), "8 4 1 7");
+    }
+
+    #[test]
+    fn test_settings_in() {
+        let paths = crate::paths::temp_root("printk");
+        fs::write(paths.kernel_sysctl(PRINTK), "4\t4\t1\t7\n").unwrap();
+
+        set_console_loglevel_in(&paths, LogLevel::Debug).unwrap();
+        assert_eq!(
+            fs::read_to_string(paths.kernel_sysctl(PRINTK)).unwrap(),
+            "8\n"
+        );
+
+        let levels = ConsoleLogLevels {
+            console_loglevel: LogLevel::Notice,
+            default_message_loglevel: LogLevel::Warning,
+            minimum_console_loglevel: LogLevel::Emergency,
+            default_console_loglevel: LogLevel::Info,
+        };
+        set_console_log_levels_in(&paths, &levels).unwrap();
+        assert_eq!(console_log_levels_in(&paths).unwrap(), levels);
+
+        set_printk_ratelimit_in(&paths, Duration::from_secs(5)).unwrap();
+        assert_eq!(printk_ratelimit_in(&paths).unwrap(), Duration::from_secs(5));
+        set_printk_ratelimit_burst_in(&paths, 10).unwrap();
+        assert_eq!(printk_ratelimit_burst_in(&paths).unwrap(), 10);
+        set_printk_devkmsg_in(&paths, DevKMsgAccess::Off).unwrap();
+        assert_eq!(printk_devkmsg_in(&paths).unwrap(), DevKMsgAccess::Off);
+
+        fs::write(paths.kernel_sysctl(PRINTK_DEVKMSG), "sometimes\n").unwrap();
+        assert_eq!(
+            printk_devkmsg_in(&paths).unwrap_err().kind(),
+            crate::error::ErrorKind::Parse
+        );
+    }
+
+    #[test]
+    fn test_devkmsg_access() {
+        assert_eq!(
+            DevKMsgAccess::from_str("ratelimit"),
+            Ok(DevKMsgAccess::Ratelimit)
+        );
+        assert_eq!(format!("{}", DevKMsgAccess::Off), "off");
+    }
+}
@@ -0,0 +1,347 @@
+use crate::error::RMesgError;
+/// This module probes which backends are usable on this system, and why, before
+/// committing to one. `Backend::Default` uses it, rather than trying /dev/kmsg and
+/// falling back on a specific error.
+///
+/// The checks are cheap and non-destructive:
+///   * /dev/kmsg exists, is the kernel's kmsg character device (containers often
+///     replace it) and can be opened and read from
+///   * klogctl is permitted for the requested action (a zero-length request performs
+///     the kernel's permission check without reading or clearing anything)
+///   * whether we're running inside a container or a non-initial user namespace
+///
+use crate::klogctl::{self, KLogType};
+use crate::kmsgfile;
+use crate::paths::SystemPaths;
+use crate::SelectedBackend;
+
+use std::env;
+use std::fs;
+use std::io::Read;
+
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, MetadataExt, OpenOptionsExt};
+
+/// Device numbers of /dev/kmsg (see Documentation/admin-guide/devices.txt)
+const KMSG_MAJOR: u64 = 1;
+const KMSG_MINOR: u64 = 11;
+
+/// The outcome of probing a single backend
+#[derive(Debug)]
+pub struct BackendProbe {
+    pub backend: SelectedBackend,
+    pub usable: bool,
+    /// Why the backend is (or isn't) usable, along with any noteworthy observations
+    pub reasons: Vec<String>,
+    /// The error that made the backend unusable, if there was one
+    pub error: Option<RMesgError>,
+    /// /dev/kmsg as opened by the probe (rewound to the first record), so it isn't opened twice
+    dev_kmsg: Option<fs::File>,
+}
+
+impl BackendProbe {
+    fn new(backend: SelectedBackend) -> Self {
+        Self {
+            backend,
+            usable: true,
+            reasons: Vec::new(),
+            error: None,
+            dev_kmsg: None,
+        }
+    }
+
+    fn unusable(&mut self, reason: String, error: Option<RMesgError>) {
+        self.usable = false;
+        self.reasons.push(reason);
+        if self.error.is_none() {
+            self.error = error;
+        }
+    }
+
+    /// The /dev/kmsg file the probe opened, ready to be read from the first record
+    pub(crate) fn take_dev_kmsg(&mut self) -> Option<fs::File> {
+        self.dev_kmsg.take()
+    }
+
+    /// The error explaining why this backend is unusable
+    pub fn into_error(self) -> RMesgError {
+        match self.error {
+            Some(e) => e,
+            None => RMesgError::BackendUnavailable(self.reasons.join("; ")),
+        }
+    }
+}
+
+/// The backend `Backend::Default` prefers, when it is usable. /dev/kmsg can't clear the
+/// buffer, so when it's usable a requested `clear` is ignored (use `Backend::KLogCtl` to clear).
+pub const PREFERRED_BACKEND: SelectedBackend = SelectedBackend::DevKMsg;
+
+/// Probes all built-in backends and returns them ranked: usable ones first, in order of
+/// preference (/dev/kmsg, then klogctl).
+pub fn probe_backends(clear: bool) -> Vec<BackendProbe> {
+    probe_backends_in(&SystemPaths::default(), clear)
+}
+
+/// Same as `probe_backends`, with /dev/kmsg (and the signs of a container) found through `paths`.
+pub fn probe_backends_in(paths: &SystemPaths, clear: bool) -> Vec<BackendProbe> {
+    let mut probes = vec![probe_dev_kmsg_in(paths), probe_klogctl(clear)];
+    add_container_note(paths, &mut probes);
+
+    // stable sort keeps preference order among usable (and unusable) ones
+    probes.sort_by_key(|probe| !probe.usable);
+    probes
+}
+
+/// Picks the best usable backend, and returns it along with the probes that were made:
+/// klogctl is only probed when /dev/kmsg is unusable.
+/// When none is usable, returns the error of the preferred one.
+pub fn select_backend(clear: bool) -> Result<(SelectedBackend, Vec<BackendProbe>), RMesgError> {
+    select_backend_in(&SystemPaths::default(), clear)
+}
+
+/// Same as `select_backend`, with /dev/kmsg (and the signs of a container) found through `paths`.
+pub fn select_backend_in(
+    paths: &SystemPaths,
+    clear: bool,
+) -> Result<(SelectedBackend, Vec<BackendProbe>), RMesgError> {
+    let preferred = probe_dev_kmsg_in(paths);
+    if preferred.usable {
+        return Ok((PREFERRED_BACKEND, vec![preferred]));
+    }
+
+    let mut probes = vec![preferred, probe_klogctl(clear)];
+    add_container_note(paths, &mut probes);
+
+    if let Some(selected) = probes.iter().find(|probe| probe.usable).map(|p| p.backend) {
+        return Ok((selected, probes));
+    }
+
+    Err(probes.swap_remove(0).into_error())
+}
+
+/// Probes the /dev/kmsg device file
+pub fn probe_dev_kmsg() -> BackendProbe {
+    probe_dev_kmsg_in(&SystemPaths::default())
+}
+
+/// Same as `probe_dev_kmsg`, with the device found through `paths`.
+pub fn probe_dev_kmsg_in(paths: &SystemPaths) -> BackendProbe {
+    let mut probe = BackendProbe::new(SelectedBackend::DevKMsg);
+    let dev_kmsg = paths.dev_kmsg.to_string_lossy();
+    let path = dev_kmsg.as_ref();
+
+    let metadata = match fs::metadata(path) {
+        Ok(m) => m,
+        Err(e) => {
+            probe.unusable(
+                format!("{} does not exist or cannot be inspected: {}", path, e),
+                Some(RMesgError::DevKMsgFileOpenError {
+                    path: path.to_owned(),
+                    source: e,
+                }),
+            );
+            return probe;
+        }
+    };
+
+    #[cfg(unix)]
+    {
+        let rdev = metadata.rdev();
+        let (major, minor) = device_numbers(rdev);
+        if !metadata.file_type().is_char_device() || (major, minor) != (KMSG_MAJOR, KMSG_MINOR) {
+            probe.unusable(
+                format!(
+                    "{} is not the kernel's kmsg device (expected character device {}:{}), as is common in containers",
+                    path, KMSG_MAJOR, KMSG_MINOR
+                ),
+                None,
+            );
+            return probe;
+        }
+    }
+
+    #[cfg(unix)]
+    let opened = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path);
+    #[cfg(not(unix))]
+    let opened = fs::File::open(path);
+
+    let mut file = match opened {
+        Ok(f) => f,
+        Err(e) => {
+            let error = kmsgfile::open_error_in(paths, path, e);
+            probe.unusable(format!("{} cannot be opened: {}", path, error), Some(error));
+            return probe;
+        }
+    };
+
+    let mut record = vec![0u8; kmsgfile::KMSG_RECORD_MAX];
+    match file.read(&mut record) {
+        Ok(_) => {}
+        // nothing to read yet, or the oldest record was just overwritten: both mean it's readable
+        Err(e)
+            if e.kind() == std::io::ErrorKind::WouldBlock
+                || e.raw_os_error() == Some(libc::EPIPE) => {}
+        Err(e) => {
+            let error = kmsgfile::open_error_in(paths, path, e);
+            probe.unusable(
+                format!("{} cannot be read from: {}", path, error),
+                Some(error),
+            );
+            return probe;
+        }
+    }
+
+    if let Err(e) = kmsgfile::rewind(&mut file) {
+        let error = kmsgfile::open_error_in(paths, path, e);
+        probe.unusable(
+            format!("{} cannot be rewound: {}", path, error),
+            Some(error),
+        );
+        return probe;
+    }
+
+    probe
+        .reasons
+        .push(format!("{} can be opened and read from", path));
+    probe.dev_kmsg = Some(file);
+    probe
+}
+
+/// Probes the klogctl syscall for the requested action
+pub fn probe_klogctl(clear: bool) -> BackendProbe {
+    let mut probe = BackendProbe::new(SelectedBackend::KLogCtl);
+
+    let action = match clear {
+        true => KLogType::SyslogActionReadClear,
+        false => KLogType::SyslogActionReadAll,
+    };
+
+    // A zero-length read is permission-checked by the kernel, but returns before reading (or clearing)
+    let mut empty: Vec<u8> = Vec::new();
+    if let Err(e) = klogctl::safely_wrapped_klogctl(action.clone(), &mut empty) {
+        probe.unusable(
+            format!("klogctl {} is not permitted: {}", action, e),
+            Some(e),
+        );
+        return probe;
+    }
+    probe
+        .reasons
+        .push(format!("klogctl {} is permitted", action));
+    probe
+}
+
+fn add_container_note(paths: &SystemPaths, probes: &mut [BackendProbe]) {
+    if let Some(note) = container_note(paths) {
+        for probe in probes.iter_mut() {
+            probe.reasons.push(note.clone());
+        }
+    }
+}
+
+/// A note for when we're running in a container or non-initial user namespace,
+/// where the kernel log is often restricted or not accessible at all
+fn container_note(paths: &SystemPaths) -> Option<String> {
+    let runtime = if paths.root_file("/.dockerenv").exists() {
+        Some("docker".to_owned())
+    } else if paths.root_file("/run/.containerenv").exists() {
+        Some("podman".to_owned())
+    } else if let Ok(container) = env::var("container") {
+        Some(container)
+    } else {
+        fs::read_to_string(paths.proc_file("1/cgroup"))
+            .ok()
+            .and_then(|cgroup| {
+                ["kubepods", "docker", "lxc", "containerd"]
+                    .iter()
+                    .find(|marker| cgroup.contains(*marker))
+                    .map(|marker| (*marker).to_owned())
+            })
+    };
+
+    let in_user_namespace = fs::read_to_string(paths.proc_file("self/uid_map"))
+        .ok()
+        .map(|map| !is_initial_uid_map(&map));
+
+    match (runtime, in_user_namespace) {
+        (_, Some(true)) => Some(
+            "Running in a non-initial user namespace: capabilities do not apply to the kernel log"
+                .to_owned(),
+        ),
+        (Some(runtime), _) => Some(format!(
+            "Running in a container ({}): the kernel log may be restricted by the runtime",
+            runtime
+        )),
+        _ => None,
+    }
+}
+
+/// Splits a dev_t into (major, minor) the way glibc's gnu_dev_major/gnu_dev_minor do
+fn device_numbers(rdev: u64) -> (u64, u64) {
+    let major = ((rdev >> 8) & 0xfff) | ((rdev >> 32) & !0xfff);
+    let minor = (rdev & 0xff) | ((rdev >> 12) & !0xff);
+    (major, minor)
+}
+
+fn is_initial_uid_map(map: &str) -> bool {
+    let fields: Vec<&str> = map.split_whitespace().collect();
+    fields == ["0", "0", "4294967295"]
+}
+
+/**********************************************************************************/
+// Tests! Tests! Tests!
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_probe_ranking() {
+        let probes = probe_backends(false);
+        assert_eq!(probes.len(), 2);
+
+        // usable backends are always ranked ahead of unusable ones
+        let first_unusable = probes
+            .iter()
+            .position(|p| !p.usable)
+            .unwrap_or(probes.len());
+        assert!(probes[first_unusable..].iter().all(|p| !p.usable));
+        assert!(probes.iter().all(|p| !p.reasons.is_empty()));
+    }
+
+    #[test]
+    fn test_probe_in_root() {
+        let paths = crate::paths::temp_root("probe");
+        fs::write(paths.proc_file("self/uid_map"), "0 0 4294967295\n").unwrap();
+
+        // nothing at /dev/kmsg
+        let probe = probe_dev_kmsg_in(&paths);
+        assert!(!probe.usable);
+        assert!(probe.reasons[0].starts_with(&paths.dev_kmsg.to_string_lossy().into_owned()));
+        assert!(container_note(&paths).is_none() || env::var("container").is_ok());
+
+        fs::write(paths.root_file("/.dockerenv"), "").unwrap();
+        assert!(container_note(&paths).unwrap().contains("docker"));
+
+        // a non-initial user namespace takes precedence
+        fs::write(paths.proc_file("self/uid_map"), "0 100000 65536\n").unwrap();
+        assert!(container_note(&paths)
+            .unwrap()
+            .contains("non-initial user namespace"));
+    }
+
+    #[test]
+    fn test_device_numbers() {
+        assert_eq!(device_numbers(0x10b), (KMSG_MAJOR, KMSG_MINOR));
+        assert_eq!(device_numbers(0x10301), (259, 1));
+    }
+
+    #[test]
+    fn test_uid_map() {
+        assert!(is_initial_uid_map("         0          0 4294967295\n"));
+        assert!(!is_initial_uid_map("         0     100000      65536\n"));
+    }
+}
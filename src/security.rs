@@ -0,0 +1,428 @@
+use crate::analysis::Analyzer;
+/// This module parses the audit records the kernel logs (when no audit daemon collects them)
+/// into `AuditRecord`s, and correlates the records of each audit event (by their serial
+/// number) into `SecurityEvent`s: SELinux AVC denials, AppArmor decisions and seccomp actions.
+///
+/// ```text
+/// audit: type=1400 audit(1610000000.123:456): avc:  denied  { read } for  pid=1234 comm="cat" name="shadow" dev="sda1" ino=1234 scontext=system_u:system_r:httpd_t:s0 tcontext=system_u:object_r:shadow_t:s0 tclass=file permissive=0
+/// audit: type=1300 audit(1610000000.123:456): arch=c000003e syscall=257 success=no exit=-13 ... pid=1234 comm="cat" exe="/usr/bin/cat"
+/// audit: type=1320 audit(1610000000.123:456):
+/// audit: type=1400 audit(1610000001.456:457): apparmor="DENIED" operation="open" profile="/usr/sbin/cupsd" name="/etc/shadow" pid=99 comm="cupsd" requested_mask="r" denied_mask="r" fsuid=0 ouid=0
+/// audit: type=1326 audit(1610000002.789:458): auid=1000 uid=1000 gid=1000 ses=2 subj=unconfined pid=4242 comm="a.out" exe="/tmp/a.out" sig=31 arch=c000003e syscall=59 compat=0 ip=0x7f0 code=0x0
+/// ```
+///
+use crate::entry::Entry;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::BTreeSet;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use strum_macros::Display;
+
+/// Audit record types (see include/uapi/linux/audit.h)
+pub const AUDIT_SYSCALL: u32 = 1300;
+pub const AUDIT_EOE: u32 = 1320;
+pub const AUDIT_SECCOMP: u32 = 1326;
+pub const AUDIT_USER_AVC: u32 = 1107;
+pub const AUDIT_AVC: u32 = 1400;
+
+/// Events made of more records are cut short (the records of an event are logged together, so
+/// this only happens when its end was lost)
+const MAX_EVENT_RECORDS: usize = 256;
+
+lazy_static! {
+    static ref RE_AUDIT: Regex = Regex::new(
+        r"^(?:audit: )?type=(?P<type>[[:digit:]]+) audit\((?P<seconds>[[:digit:]]+)\.(?P<millis>[[:digit:]]{3}):(?P<serial>[[:digit:]]+)\):[[:space:]]*(?P<body>.*)$"
+    )
+    .unwrap();
+    static ref RE_AVC: Regex = Regex::new(
+        r"^avc:[[:space:]]+(?P<decision>denied|granted)[[:space:]]+\{(?P<permissions>[^}]*)\}[[:space:]]+for[[:space:]]+(?P<fields>.*)$"
+    )
+    .unwrap();
+    static ref RE_FIELD: Regex = Regex::new(
+        r#"(?P<key>[[:alnum:]_-]+)=(?:"(?P<quoted>[^"]*)"|'(?P<single_quoted>[^']*)'|(?P<value>[^[:space:]]*))"#
+    )
+    .unwrap();
+}
+
+/// One audit record (a line)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// The record type (e.g. `AUDIT_AVC`)
+    pub record_type: u32,
+    /// When the event happened, from the audit(...) stamp
+    pub time: SystemTime,
+    /// The event's serial number, shared by all of its records (0 for records outside of any event)
+    pub serial: u64,
+    /// For SELinux AVC records, whether the access was "denied" or "granted"
+    pub decision: Option<String>,
+    /// For SELinux AVC records, the permissions between the braces
+    pub permissions: Vec<String>,
+    /// The key=value fields, in order (quotes removed)
+    pub fields: Vec<(String, String)>,
+    /// The time since boot of the entry
+    pub timestamp_from_system_start: Option<Duration>,
+}
+
+impl AuditRecord {
+    /// Parses a line (an entry's message), or `None` when it's no audit record.
+    pub fn parse(line: &str) -> Option<AuditRecord> {
+        let parts = RE_AUDIT.captures(line.trim())?;
+
+        let seconds: u64 = parts["seconds"].parse().ok()?;
+        let millis: u64 = parts["millis"].parse().ok()?;
+        let time = UNIX_EPOCH
+            .checked_add(Duration::from_secs(seconds))?
+            .checked_add(Duration::from_millis(millis))?;
+
+        let body = parts.name("body")?.as_str();
+        let (decision, permissions, fields) = match RE_AVC.captures(body) {
+            Some(avc) => (
+                Some(avc["decision"].to_owned()),
+                avc["permissions"]
+                    .split_whitespace()
+                    .map(|permission| permission.to_owned())
+                    .collect(),
+                avc.name("fields")?.as_str(),
+            ),
+            None => (None, Vec::new(), body),
+        };
+
+        let fields = RE_FIELD
+            .captures_iter(fields)
+            .filter_map(|field| {
+                let value = field
+                    .name("quoted")
+                    .or_else(|| field.name("single_quoted"))
+                    .or_else(|| field.name("value"))?;
+                Some((field["key"].to_owned(), value.as_str().to_owned()))
+            })
+            .collect();
+
+        Some(AuditRecord {
+            record_type: parts["type"].parse().ok()?,
+            time,
+            serial: parts["serial"].parse().ok()?,
+            decision,
+            permissions,
+            fields,
+            timestamp_from_system_start: None,
+        })
+    }
+
+    /// Parses an entry, keeping its timestamp.
+    pub fn from_entry(entry: &Entry) -> Option<AuditRecord> {
+        let mut record = Self::parse(&entry.message)?;
+        record.timestamp_from_system_start = entry.timestamp_from_system_start;
+        Some(record)
+    }
+
+    /// The value of a field (the first, should it repeat)
+    pub fn field(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// What kind of security event this record makes, if any
+    pub fn security_kind(&self) -> Option<SecurityEventKind> {
+        if self.field("apparmor").is_some() {
+            Some(SecurityEventKind::AppArmor)
+        } else if self.decision.is_some()
+            && (self.record_type == AUDIT_AVC || self.record_type == AUDIT_USER_AVC)
+        {
+            Some(SecurityEventKind::SelinuxAvc)
+        } else if self.record_type == AUDIT_SECCOMP {
+            Some(SecurityEventKind::Seccomp)
+        } else {
+            None
+        }
+    }
+}
+
+/// The kind of security event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum SecurityEventKind {
+    SelinuxAvc,
+    AppArmor,
+    Seccomp,
+}
+
+/// An audit event that is a security decision, with every record of the event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityEvent {
+    pub kind: SecurityEventKind,
+    /// The audit event's serial number
+    pub serial: u64,
+    /// When the event happened, from the audit(...) stamp
+    pub time: SystemTime,
+    /// "denied" or "granted" for SELinux, the apparmor= field (e.g. "DENIED", "ALLOWED", "AUDIT")
+    /// for AppArmor, and none for seccomp (whose action is in the code= field)
+    pub decision: Option<String>,
+    /// The permissions (SELinux) or denied mask (AppArmor) of all of the event's records
+    pub permissions: BTreeSet<String>,
+    /// The subject: scontext= (SELinux), profile= (AppArmor) or subj= (seccomp)
+    pub source_context: Option<String>,
+    /// The object: tcontext= (SELinux) or name= (AppArmor)
+    pub target_context: Option<String>,
+    /// The object's class (SELinux's tclass=, e.g. "file")
+    pub target_class: Option<String>,
+    /// All of the event's records (e.g. the SYSCALL and PROCTITLE records along with the AVC one)
+    pub records: Vec<AuditRecord>,
+}
+
+impl SecurityEvent {
+    /// Makes an event out of the records of an audit event, if it's a security decision
+    pub fn from_records(records: Vec<AuditRecord>) -> Option<SecurityEvent> {
+        let first = records
+            .iter()
+            .find(|record| record.security_kind().is_some())?;
+        let kind = first.security_kind()?;
+
+        let decision = match kind {
+            SecurityEventKind::SelinuxAvc => first.decision.clone(),
+            SecurityEventKind::AppArmor => first.field("apparmor").map(|d| d.to_owned()),
+            SecurityEventKind::Seccomp => None,
+        };
+        let (source, target) = match kind {
+            SecurityEventKind::SelinuxAvc => ("scontext", Some("tcontext")),
+            SecurityEventKind::AppArmor => ("profile", Some("name")),
+            SecurityEventKind::Seccomp => ("subj", None),
+        };
+        let source_context = first.field(source).map(|s| s.to_owned());
+        let target_context = target
+            .and_then(|target| first.field(target))
+            .map(|t| t.to_owned());
+        let target_class = first.field("tclass").map(|t| t.to_owned());
+
+        let mut permissions = BTreeSet::new();
+        for record in records.iter().filter(|r| r.security_kind() == Some(kind)) {
+            permissions.extend(record.permissions.iter().cloned());
+            if let Some(mask) = record.field("denied_mask") {
+                permissions.extend(mask.split_whitespace().map(|p| p.to_owned()));
+            }
+        }
+
+        Some(SecurityEvent {
+            kind,
+            serial: first.serial,
+            time: first.time,
+            decision,
+            permissions,
+            source_context,
+            target_context,
+            target_class,
+            records,
+        })
+    }
+
+    /// The value of a field in any of the event's records (the first, should it repeat)
+    pub fn field(&self, key: &str) -> Option<&str> {
+        self.records.iter().find_map(|record| record.field(key))
+    }
+
+    pub fn pid(&self) -> Option<u32> {
+        self.field("pid")?.parse().ok()
+    }
+
+    pub fn comm(&self) -> Option<&str> {
+        self.field("comm")
+    }
+
+    /// Whether access was denied (for seccomp, whether the syscall was kept from running).
+    /// SELinux in permissive mode (permissive=1) logs denials without enforcing them, so these
+    /// aren't, just like AppArmor's ALLOWED in complain mode.
+    pub fn is_denial(&self) -> bool {
+        match self.kind {
+            SecurityEventKind::SelinuxAvc => {
+                self.decision.as_deref() == Some("denied") && self.field("permissive") != Some("1")
+            }
+            SecurityEventKind::AppArmor => self.decision.as_deref() == Some("DENIED"),
+            // SECCOMP_RET_ALLOW and SECCOMP_RET_LOG let the syscall run
+            SecurityEventKind::Seccomp => {
+                !matches!(self.field("code"), Some("0x7fff0000") | Some("0x7ffc0000"))
+            }
+        }
+    }
+
+    /// The time since boot of the event's first record
+    pub fn timestamp_from_system_start(&self) -> Option<Duration> {
+        self.records.first()?.timestamp_from_system_start
+    }
+}
+
+/// Correlates audit records into `SecurityEvent`s.
+///
+/// An event is reported at its end-of-event record, at the next record of another event,
+/// or when the entries end. Records outside of any event (serial 0) are reported right away.
+#[derive(Debug, Default)]
+pub struct SecurityAnalyzer {
+    records: Vec<AuditRecord>,
+}
+
+impl SecurityAnalyzer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn close(&mut self) -> Vec<SecurityEvent> {
+        if self.records.is_empty() {
+            return Vec::new();
+        }
+        let records = std::mem::take(&mut self.records);
+        SecurityEvent::from_records(records).into_iter().collect()
+    }
+}
+
+impl Analyzer for SecurityAnalyzer {
+    type Report = SecurityEvent;
+
+    fn push(&mut self, entry: &Entry) -> Vec<SecurityEvent> {
+        let record = match AuditRecord::from_entry(entry) {
+            Some(record) => record,
+            None => return Vec::new(),
+        };
+
+        if record.serial == 0 {
+            return SecurityEvent::from_records(vec![record])
+                .into_iter()
+                .collect();
+        }
+
+        let mut events = match self.records.first() {
+            Some(open) if open.serial != record.serial => self.close(),
+            _ => Vec::new(),
+        };
+
+        let end_of_event = record.record_type == AUDIT_EOE;
+        self.records.push(record);
+        if end_of_event || self.records.len() >= MAX_EVENT_RECORDS {
+            events.extend(self.close());
+        }
+        events
+    }
+
+    fn finish(&mut self) -> Vec<SecurityEvent> {
+        self.close()
+    }
+}
+
+/**********************************************************************************/
+// Tests! Tests! Tests!
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis;
+    use crate::klogctl;
+
+    const AVC: &str = r#"audit: type=1400 audit(1610000000.123:456): avc:  denied  { read open } for  pid=1234 comm="cat" name="shadow" dev="sda1" ino=1234 scontext=system_u:system_r:httpd_t:s0 tcontext=system_u:object_r:shadow_t:s0 tclass=file permissive=0"#;
+    const APPARMOR: &str = r#"audit: type=1400 audit(1610000001.456:457): apparmor="DENIED" operation="open" profile="/usr/sbin/cupsd" name="/etc/shadow" pid=99 comm="cupsd" requested_mask="r" denied_mask="r" fsuid=0 ouid=0"#;
+    const SECCOMP: &str = r#"audit: type=1326 audit(1610000002.789:458): auid=1000 uid=1000 gid=1000 ses=2 subj=unconfined pid=4242 comm="a.out" exe="/tmp/a.out" sig=31 arch=c000003e syscall=59 compat=0 ip=0x7f0 code=0x0"#;
+
+    #[test]
+    fn test_parse_record() {
+        let record = AuditRecord::parse(AVC).unwrap();
+        assert_eq!(record.record_type, AUDIT_AVC);
+        assert_eq!(record.serial, 456);
+        assert_eq!(
+            record.time,
+            UNIX_EPOCH + Duration::from_millis(1_610_000_000_123)
+        );
+        assert_eq!(record.decision.as_deref(), Some("denied"));
+        assert_eq!(record.permissions, vec!["read", "open"]);
+        assert_eq!(record.field("comm"), Some("cat"));
+        assert_eq!(record.field("permissive"), Some("0"));
+        assert_eq!(record.security_kind(), Some(SecurityEventKind::SelinuxAvc));
+
+        let record = AuditRecord::parse(APPARMOR).unwrap();
+        assert_eq!(record.decision, None);
+        assert_eq!(record.field("profile"), Some("/usr/sbin/cupsd"));
+        assert_eq!(record.security_kind(), Some(SecurityEventKind::AppArmor));
+
+        let record = AuditRecord::parse("audit: type=1320 audit(1610000000.123:456): ").unwrap();
+        assert_eq!(record.record_type, AUDIT_EOE);
+        assert!(record.fields.is_empty());
+
+        assert_eq!(
+            AuditRecord::parse("audit: initializing netlink subsys (disabled)"),
+            None
+        );
+
+        // a time past what SystemTime can hold
+        assert_eq!(
+            AuditRecord::parse("audit: type=1400 audit(18446744073709551615.000:1): avc:  denied  { read } for  pid=1"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_security_events() {
+        let event = SecurityEvent::from_records(vec![AuditRecord::parse(AVC).unwrap()]).unwrap();
+        assert_eq!(event.kind, SecurityEventKind::SelinuxAvc);
+        assert!(event.is_denial());
+        assert_eq!(
+            event.source_context.as_deref(),
+            Some("system_u:system_r:httpd_t:s0")
+        );
+        assert_eq!(
+            event.target_context.as_deref(),
+            Some("system_u:object_r:shadow_t:s0")
+        );
+        assert_eq!(event.target_class.as_deref(), Some("file"));
+        assert!(event.permissions.contains("open"));
+
+        // logged, but not enforced
+        let permissive = AVC.replace("permissive=0", "permissive=1");
+        let event =
+            SecurityEvent::from_records(vec![AuditRecord::parse(&permissive).unwrap()]).unwrap();
+        assert_eq!(event.decision.as_deref(), Some("denied"));
+        assert!(!event.is_denial());
+
+        let event =
+            SecurityEvent::from_records(vec![AuditRecord::parse(APPARMOR).unwrap()]).unwrap();
+        assert_eq!(event.kind, SecurityEventKind::AppArmor);
+        assert_eq!(event.decision.as_deref(), Some("DENIED"));
+        assert_eq!(event.target_context.as_deref(), Some("/etc/shadow"));
+        assert_eq!(event.permissions.iter().collect::<Vec<_>>(), vec!["r"]);
+        assert_eq!(event.pid(), Some(99));
+
+        let event =
+            SecurityEvent::from_records(vec![AuditRecord::parse(SECCOMP).unwrap()]).unwrap();
+        assert_eq!(event.kind, SecurityEventKind::Seccomp);
+        assert_eq!(event.source_context.as_deref(), Some("unconfined"));
+        assert_eq!(event.field("syscall"), Some("59"));
+        assert!(event.is_denial());
+    }
+
+    #[test]
+    fn test_correlate() {
+        let lines = format!(
+            "<5>[  100.000000] {}\n\
+             <6>[  100.000100] eth0: unrelated\n\
+             <5>[  100.000200] audit: type=1300 audit(1610000000.123:456): arch=c000003e syscall=257 success=no exit=-13 pid=1234 comm=\"cat\" exe=\"/usr/bin/cat\"\n\
+             <5>[  100.000300] audit: type=1327 audit(1610000000.123:456): proctitle=636174002F6574632F736861646F77\n\
+             <5>[  100.000400] audit: type=1320 audit(1610000000.123:456): \n\
+             <5>[  101.000000] audit: type=1006 audit(1610000001.000:460): pid=1 uid=0 old-auid=4294967295 auid=0 res=1\n\
+             <5>[  102.000000] {}",
+            AVC, SECCOMP
+        );
+        let entries = klogctl::entries_from_lines(&lines).unwrap();
+
+        let events = analysis::analyze(SecurityAnalyzer::new(), &entries);
+        assert_eq!(events.len(), 2);
+
+        assert_eq!(events[0].serial, 456);
+        assert_eq!(events[0].records.len(), 4);
+        assert_eq!(events[0].field("exe"), Some("/usr/bin/cat"));
+        assert_eq!(
+            events[0].timestamp_from_system_start(),
+            Some(Duration::from_secs(100))
+        );
+
+        // reported when the entries end
+        assert_eq!(events[1].kind, SecurityEventKind::Seccomp);
+        assert_eq!(events[1].records.len(), 1);
+    }
+}
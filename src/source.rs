@@ -0,0 +1,62 @@
+#[cfg(any(feature = "sync", feature = "async"))]
+use crate::entry::Entry;
+/// Extension point for kernel log sources beyond the built-in backends.
+///
+/// Both built-in backends (`klogctl::KLogEntries` and the `kmsgfile` readers) implement
+/// these traits, and any other type that does can be handed to `logs_iter_from` or
+/// `logs_stream_from` to get the same `EntriesIterator`/`EntriesStream` the crate
+/// returns for its own backends. This allows plugging in readers for a serial console,
+/// pstore, a remote agent, etc. without forking the crate.
+///
+#[cfg(any(feature = "sync", feature = "async"))]
+use crate::error::RMesgError;
+#[cfg(any(feature = "sync", feature = "async"))]
+use crate::loss::LossStats;
+
+#[cfg(feature = "async")]
+use futures::stream::Stream;
+#[cfg(feature = "sync")]
+use std::iter::Iterator;
+
+/// A synchronous source of kernel log entries.
+///
+/// Implementors provide the blocking `Iterator` that yields entries as they become available.
+#[cfg(feature = "sync")]
+pub trait LogSource: Iterator<Item = Result<Entry, RMesgError>> {
+    /// The entries lost so far. Sources that can't lose entries (or can't tell) report none.
+    fn loss_stats(&self) -> LossStats {
+        LossStats::default()
+    }
+}
+
+/// An asynchronous source of kernel log entries.
+///
+/// Implementors provide the `Stream` that yields entries as they become available.
+#[cfg(feature = "async")]
+pub trait AsyncLogSource: Stream<Item = Result<Entry, RMesgError>> {
+    /// The entries lost so far. Sources that can't lose entries (or can't tell) report none.
+    fn loss_stats(&self) -> LossStats {
+        LossStats::default()
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<S: LogSource + ?Sized> LogSource for Box<S> {
+    fn loss_stats(&self) -> LossStats {
+        (**self).loss_stats()
+    }
+}
+
+#[cfg(feature = "async")]
+impl<S: AsyncLogSource + Unpin + ?Sized> AsyncLogSource for Box<S> {
+    fn loss_stats(&self) -> LossStats {
+        (**self).loss_stats()
+    }
+}
+
+#[cfg(feature = "async")]
+impl<S: AsyncLogSource + ?Sized> AsyncLogSource for core::pin::Pin<Box<S>> {
+    fn loss_stats(&self) -> LossStats {
+        self.as_ref().get_ref().loss_stats()
+    }
+}
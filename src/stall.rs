@@ -0,0 +1,327 @@
+use crate::analysis::{Analyzer, Frame, TaskInfo, TraceReport};
+/// This module recognizes the reports the kernel's watchdogs log when something is stuck,
+/// and groups each with the stack trace that follows it into a `StallEvent`:
+///
+/// ```text
+/// INFO: task jbd2/sda1-8:312 blocked for more than 120 seconds.
+/// watchdog: BUG: soft lockup - CPU#3 stuck for 23s! [kworker/3:1:123]
+/// NMI watchdog: Watchdog detected hard LOCKUP on cpu 5
+/// watchdog: CPU5: Watchdog detected hard LOCKUP on cpu 5
+/// rcu: INFO: rcu_sched detected stalls on CPUs/tasks:
+/// ```
+///
+/// These reports have no end marker, so one ends with the first line after its call trace
+/// that can't be part of it.
+///
+use crate::entry::Entry;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::time::Duration;
+use strum_macros::Display;
+
+lazy_static! {
+    static ref RE_HUNG_TASK: Regex = Regex::new(
+        r"^INFO: task (?P<comm>.+):(?P<pid>[[:digit:]]+) blocked for more than (?P<secs>[[:digit:]]+) seconds"
+    )
+    .unwrap();
+    static ref RE_SOFT_LOCKUP: Regex = Regex::new(
+        r"^watchdog: BUG: soft lockup - CPU#(?P<cpu>[[:digit:]]+) stuck for (?P<secs>[[:digit:]]+)s! \[(?P<comm>.+):(?P<pid>[[:digit:]]+)\]"
+    )
+    .unwrap();
+    static ref RE_HARD_LOCKUP: Regex = Regex::new(
+        r"^(?:NMI )?watchdog: (?:CPU[[:digit:]]+: )?(?:Watchdog detected )?hard LOCKUP on cpu (?P<cpu>[[:digit:]]+)"
+    )
+    .unwrap();
+    static ref RE_RCU_STALL: Regex =
+        Regex::new(r"^(?:rcu: )?INFO: [[:alnum:]_]+ (?:detected stalls|self-detected stall)").unwrap();
+    // "rcu:     3-...!: (1 GPs behind) idle=..."
+    static ref RE_RCU_STALLED_CPU: Regex =
+        Regex::new(r"^rcu:[[:space:]]+(?P<cpu>[[:digit:]]+)-").unwrap();
+    static ref RE_RCU_JIFFIES: Regex = Regex::new(r"\bt=(?P<jiffies>[[:digit:]]+) jiffies").unwrap();
+}
+
+/// What got stuck
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum StallKind {
+    /// A task stayed in uninterruptible sleep (state D), e.g. waiting on I/O
+    HungTask,
+    /// A CPU ran kernel code without scheduling
+    SoftLockup,
+    /// A CPU ran without taking interrupts
+    HardLockup,
+    /// RCU grace periods stopped completing
+    RcuStall,
+}
+
+/// A stall report, and the entries it was grouped from
+#[derive(Debug, Clone, PartialEq)]
+pub struct StallEvent {
+    pub kind: StallKind,
+    /// The stuck CPU (for RCU stalls, the first CPU reported as stalling)
+    pub cpu: Option<u32>,
+    /// The command (task name) of the stuck task
+    pub comm: Option<String>,
+    pub pid: Option<u32>,
+    /// How long it's been stuck for (for hung tasks, at least that long)
+    pub duration: Option<Duration>,
+    /// How long an RCU stall has lasted, in jiffies (their length depends on CONFIG_HZ)
+    pub jiffies: Option<u64>,
+    /// Every CPU an RCU stall was reported on
+    pub stalled_cpus: Vec<u32>,
+    /// The time since boot of the first entry
+    pub timestamp_from_system_start: Option<Duration>,
+    /// The task the stack trace was printed for (CPU, PID, command, taint flags and kernel release)
+    pub task: Option<TaskInfo>,
+    pub call_trace: Vec<Frame>,
+    /// Every entry of the report
+    pub entries: Vec<Entry>,
+}
+
+impl StallEvent {
+    fn new(kind: StallKind, entry: &Entry) -> Self {
+        Self {
+            kind,
+            cpu: None,
+            comm: None,
+            pid: None,
+            duration: None,
+            jiffies: None,
+            stalled_cpus: Vec::new(),
+            timestamp_from_system_start: entry.timestamp_from_system_start,
+            task: None,
+            call_trace: Vec::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Parses the line a stall report starts with
+    fn from_header(line: &str, entry: &Entry) -> Option<Self> {
+        if let Some(parts) = RE_HUNG_TASK.captures(line) {
+            let mut event = Self::new(StallKind::HungTask, entry);
+            event.comm = Some(parts["comm"].to_owned());
+            event.pid = parts["pid"].parse().ok();
+            event.duration = parts["secs"].parse().ok().map(Duration::from_secs);
+            Some(event)
+        } else if let Some(parts) = RE_SOFT_LOCKUP.captures(line) {
+            let mut event = Self::new(StallKind::SoftLockup, entry);
+            event.cpu = parts["cpu"].parse().ok();
+            event.comm = Some(parts["comm"].to_owned());
+            event.pid = parts["pid"].parse().ok();
+            event.duration = parts["secs"].parse().ok().map(Duration::from_secs);
+            Some(event)
+        } else if let Some(parts) = RE_HARD_LOCKUP.captures(line) {
+            let mut event = Self::new(StallKind::HardLockup, entry);
+            event.cpu = parts["cpu"].parse().ok();
+            Some(event)
+        } else if RE_RCU_STALL.is_match(line) {
+            Some(Self::new(StallKind::RcuStall, entry))
+        } else {
+            None
+        }
+    }
+
+    fn add(&mut self, line: &str) {
+        if self.kind != StallKind::RcuStall {
+            return;
+        }
+        if let Some(cpu) = RE_RCU_STALLED_CPU
+            .captures(line)
+            .and_then(|parts| parts["cpu"].parse().ok())
+        {
+            self.stalled_cpus.push(cpu);
+            self.cpu.get_or_insert(cpu);
+        } else if let Some(jiffies) = RE_RCU_JIFFIES
+            .captures(line)
+            .and_then(|parts| parts["jiffies"].parse().ok())
+        {
+            self.jiffies = Some(jiffies);
+        }
+    }
+
+    fn with_report(mut self, report: TraceReport) -> Self {
+        if let Some(task) = &report.task {
+            self.comm.get_or_insert_with(|| task.comm.clone());
+            self.pid.get_or_insert(task.pid);
+        }
+        self.task = report.task;
+        self.call_trace = report.call_trace;
+        self.entries = report.entries;
+        self
+    }
+}
+
+/// Finds `StallEvent`s among entries
+#[derive(Debug, Default)]
+pub struct StallAnalyzer {
+    current: Option<(StallEvent, TraceReport)>,
+}
+
+impl StallAnalyzer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn close(&mut self) -> Option<StallEvent> {
+        self.current
+            .take()
+            .map(|(event, report)| event.with_report(report))
+    }
+}
+
+impl Analyzer for StallAnalyzer {
+    type Report = StallEvent;
+
+    fn push(&mut self, entry: &Entry) -> Vec<StallEvent> {
+        let line = entry.message.trim();
+        let started = StallEvent::from_header(line, entry);
+        let mut reports = Vec::new();
+
+        if let Some((_, report)) = &self.current {
+            if started.is_some() || !report.continues_with(line) {
+                reports.extend(self.close());
+            }
+        }
+
+        let (event, report) = match (&mut self.current, started) {
+            (Some(current), _) => current,
+            (None, Some(event)) => self.current.insert((event, TraceReport::default())),
+            (None, None) => return reports,
+        };
+
+        event.add(line);
+        if report.add(line, entry) {
+            reports.extend(self.close());
+        }
+        reports
+    }
+
+    fn finish(&mut self) -> Vec<StallEvent> {
+        self.close().into_iter().collect()
+    }
+}
+
+/**********************************************************************************/
+// Tests! Tests! Tests!
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis;
+    use crate::klogctl;
+
+    #[test]
+    fn test_hung_task() {
+        let entries = klogctl::entries_from_lines(
+            "<3>[  246.000000] INFO: task jbd2/sda1-8:312 blocked for more than 120 seconds.\n\
+             <3>[  246.000001]       Not tainted 6.1.0 #1\n\
+             <3>[  246.000002] \"echo 0 > /proc/sys/kernel/hung_task_timeout_secs\" disables this message.\n\
+             <6>[  246.000003] task:jbd2/sda1-8     state:D stack:0     pid:312   ppid:2      flags:0x00004000\n\
+             <6>[  246.000004] Call Trace:\n\
+             <6>[  246.000005]  <TASK>\n\
+             <6>[  246.000006]  __schedule+0x2e5/0x8b0\n\
+             <6>[  246.000007]  io_schedule+0x42/0x70\n\
+             <6>[  246.000008]  </TASK>\n\
+             <6>[  250.000000] eth0: link up",
+        )
+        .unwrap();
+        let events = analysis::analyze(StallAnalyzer::new(), &entries);
+        assert_eq!(events.len(), 1);
+
+        let event = &events[0];
+        assert_eq!(event.kind, StallKind::HungTask);
+        assert_eq!(
+            (event.comm.as_deref(), event.pid),
+            (Some("jbd2/sda1-8"), Some(312))
+        );
+        assert_eq!(event.duration, Some(Duration::from_secs(120)));
+        assert_eq!(event.cpu, None);
+        assert_eq!(event.call_trace.len(), 2);
+        assert_eq!(event.entries.len(), 9);
+    }
+
+    #[test]
+    fn test_lockups() {
+        let entries = klogctl::entries_from_lines(
+            "<0>[   80.000000] watchdog: BUG: soft lockup - CPU#3 stuck for 23s! [kworker/3:1:123]\n\
+             <4>[   80.000001] Modules linked in: foo\n\
+             <4>[   80.000002] CPU: 3 PID: 123 Comm: kworker/3:1 Tainted: G             L     6.1.0 #1\n\
+             <4>[   80.000003] RIP: 0010:foo_spin+0x10/0x20 [foo]\n\
+             <4>[   80.000004] Call Trace:\n\
+             <4>[   80.000005]  process_one_work+0x1c7/0x380\n\
+             <0>[   90.000000] NMI watchdog: Watchdog detected hard LOCKUP on cpu 5\n\
+             <4>[   90.000001] CPU: 5 PID: 0 Comm: swapper/5 Tainted: G             L     6.1.0 #1\n\
+             <4>[   90.000002] Call Trace:\n\
+             <4>[   90.000003]  do_idle+0x1e1/0x250",
+        )
+        .unwrap();
+        let events = analysis::analyze(StallAnalyzer::new(), &entries);
+        assert_eq!(events.len(), 2);
+
+        assert_eq!(events[0].kind, StallKind::SoftLockup);
+        assert_eq!(events[0].cpu, Some(3));
+        assert_eq!(events[0].comm.as_deref(), Some("kworker/3:1"));
+        assert_eq!(events[0].duration, Some(Duration::from_secs(23)));
+        assert_eq!(events[0].call_trace[0].function, "process_one_work");
+
+        assert_eq!(events[1].kind, StallKind::HardLockup);
+        assert_eq!(events[1].cpu, Some(5));
+        // from the task the trace was printed for
+        assert_eq!(
+            (events[1].comm.as_deref(), events[1].pid),
+            (Some("swapper/5"), Some(0))
+        );
+        assert_eq!(events[1].duration, None);
+
+        // newer kernels also name the CPU that detected it
+        let entries = klogctl::entries_from_lines(
+            "<0>[   95.000000] watchdog: CPU5: Watchdog detected hard LOCKUP on cpu 5",
+        )
+        .unwrap();
+        let events = analysis::analyze(StallAnalyzer::new(), &entries);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, StallKind::HardLockup);
+        assert_eq!(events[0].cpu, Some(5));
+    }
+
+    #[test]
+    fn test_traceless_stall() {
+        let entries = klogctl::entries_from_lines(
+            "<3>[  246.000000] INFO: task jbd2/sda1-8:312 blocked for more than 120 seconds.\n\
+             <3>[  246.000001]       Not tainted 6.1.0 #1\n\
+             <6>[  247.000000] eth0: link up\n\
+             <6>[  248.000000] usb 1-1: USB disconnect, device number 2",
+        )
+        .unwrap();
+        let events = analysis::analyze(StallAnalyzer::new(), &entries);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].entries.len(), 2);
+    }
+
+    #[test]
+    fn test_rcu_stall() {
+        let entries = klogctl::entries_from_lines(
+            "<3>[  500.000000] rcu: INFO: rcu_sched detected stalls on CPUs/tasks:\n\
+             <3>[  500.000001] rcu: \t3-...!: (1 GPs behind) idle=a3c/1/0x4000000000000000 softirq=1/1 fqs=0\n\
+             <3>[  500.000002] rcu: \t6-...!: (0 ticks this GP) idle=7d2/1/0x4000000000000000 softirq=5/5 fqs=0\n\
+             <3>[  500.000003] \t(detected by 1, t=21002 jiffies, g=1234, q=56)\n\
+             <6>[  500.000004] Sending NMI from CPU 1 to CPUs 3:\n\
+             <4>[  500.000005] NMI backtrace for cpu 3\n\
+             <4>[  500.000006] CPU: 3 PID: 77 Comm: spinner Not tainted 6.1.0 #1\n\
+             <4>[  500.000007] Call Trace:\n\
+             <4>[  500.000008]  spin_forever+0x5/0x10\n\
+             <6>[  501.000000] eth0: link up",
+        )
+        .unwrap();
+        let events = analysis::analyze(StallAnalyzer::new(), &entries);
+        assert_eq!(events.len(), 1);
+
+        let event = &events[0];
+        assert_eq!(event.kind, StallKind::RcuStall);
+        assert_eq!(event.stalled_cpus, vec![3, 6]);
+        assert_eq!(event.cpu, Some(3));
+        assert_eq!(event.jiffies, Some(21002));
+        assert_eq!(event.comm.as_deref(), Some("spinner"));
+        assert_eq!(event.call_trace.len(), 1);
+    }
+}
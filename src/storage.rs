@@ -0,0 +1,460 @@
+use crate::analysis::Analyzer;
+/// This module classifies the errors block devices and filesystems log into
+/// `StorageError`s, and tallies them per disk (e.g. to decide which disks to replace):
+///
+/// ```text
+/// blk_update_request: I/O error, dev sda, sector 2048 op 0x0:(READ) flags 0x0 phys_seg 1 prio class 0
+/// Buffer I/O error on dev sda1, logical block 0, async page read
+/// sd 0:0:0:0: [sda] tag#0 Sense Key : Medium Error [current]
+/// EXT4-fs error (device sda1): ext4_find_entry:1455: inode #2: comm ls: reading directory lblock 0
+/// XFS (sdb1): metadata I/O error in "xfs_trans_read_buf_map" at daddr 0x2a0 len 8 error 5
+/// BTRFS error (device sdc): bdev /dev/sdc errs: wr 0, rd 1, flush 0, corrupt 0, gen 0
+/// nvme nvme0: I/O 123 QID 4 timeout, aborting
+/// ```
+///
+use crate::entry::Entry;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet};
+use std::iter::FromIterator;
+use std::time::Duration;
+use strum_macros::Display;
+
+lazy_static! {
+    static ref RE_BLOCK: Regex = Regex::new(
+        r"(?x)^
+        (?:[a-z_]+:[[:space:]]+)?
+        (?P<error>[[:alpha:]/[:space:]]+)[[:space:]]error,[[:space:]]dev[[:space:]]+(?P<device>[^,[:space:]]+),
+        [[:space:]]+sector[[:space:]]+(?P<sector>[[:digit:]]+)
+        (?:[[:space:]]+op[[:space:]]+0x[[:xdigit:]]+:\((?P<operation>[A-Z_]+)\))?"
+    )
+    .unwrap();
+    static ref RE_BUFFER: Regex =
+        Regex::new(r"^Buffer I/O error on dev(?:ice)? (?P<device>[^,[:space:]]+), (?P<detail>.*)$")
+            .unwrap();
+    static ref RE_SCSI_SENSE: Regex = Regex::new(
+        r"^sd [[:digit:]:]+: \[(?P<device>[^\]]+)\](?: tag#[[:digit:]]+)? Sense Key : (?P<key>.+?)(?: \[(?:current|deferred)\])?(?: \[descriptor\])?$"
+    )
+    .unwrap();
+    static ref RE_EXT: Regex = Regex::new(
+        r"^(?P<fs>EXT[234])-fs error \(device (?P<device>[^)]+)\): (?P<detail>.*)$"
+    )
+    .unwrap();
+    static ref RE_XFS: Regex = Regex::new(r"^XFS \((?P<device>[^)]+)\): (?P<detail>.*)$").unwrap();
+    static ref RE_XFS_DADDR: Regex = Regex::new(r"daddr 0x(?P<daddr>[[:xdigit:]]+)").unwrap();
+    static ref RE_BTRFS: Regex = Regex::new(
+        r"^BTRFS (?P<level>error|critical|warning) \(device (?P<device>[^)[:space:]]+)[^)]*\): (?P<detail>.*)$"
+    )
+    .unwrap();
+    static ref RE_NVME_TIMEOUT: Regex =
+        Regex::new(r"^nvme (?P<device>nvme[[:digit:]]+): I/O (?:tag )?[[:digit:]]+ .*QID [[:digit:]]+ timeout")
+            .unwrap();
+    static ref RE_NVME_DOWN: Regex =
+        Regex::new(r"^nvme (?P<device>nvme[[:digit:]]+): (?P<detail>controller is down.*)$").unwrap();
+    static ref RE_NVME_IO: Regex = Regex::new(
+        r"^(?P<device>nvme[[:digit:]]+n[[:digit:]]+): I/O Cmd\(0x[[:xdigit:]]+\) @ LBA (?P<lba>[[:digit:]]+), (?P<detail>.*)$"
+    )
+    .unwrap();
+    // partitions, and the disk they're on
+    static ref RE_NUMBERED_PARTITION: Regex =
+        Regex::new(r"^(?P<disk>(?:nvme[[:digit:]]+n|mmcblk|loop|nbd)[[:digit:]]+)p[[:digit:]]+$").unwrap();
+    static ref RE_LETTERED_PARTITION: Regex =
+        Regex::new(r"^(?P<disk>(?:sd|vd|hd|xvd)[a-z]+)[[:digit:]]+$").unwrap();
+}
+
+/// What went wrong
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Display)]
+pub enum StorageErrorKind {
+    /// A read or write failed
+    IoError,
+    /// The medium is damaged (bad sectors)
+    MediumError,
+    /// Other SCSI sense data (e.g. "Hardware Error", "Aborted Command")
+    ScsiSense,
+    /// A command didn't complete in time
+    Timeout,
+    /// A controller went down and is being reset
+    ControllerReset,
+    /// A filesystem found an inconsistency (or failed an operation)
+    FilesystemError,
+    /// A filesystem couldn't read or write its metadata
+    MetadataIoError,
+    /// A filesystem found corrupted data or metadata (e.g. a checksum mismatch)
+    Corruption,
+}
+
+/// An error reported by a block device or filesystem
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageError {
+    pub kind: StorageErrorKind,
+    /// The device as the kernel names it, e.g. "sda", "sda1", "nvme0" or "nvme0n1"
+    pub device: String,
+    /// The sector the error is at, when known (in 512-byte sectors, or for NVMe in the namespace's LBAs)
+    pub sector: Option<u64>,
+    /// The filesystem that reported it, e.g. "ext4", "xfs" or "btrfs"
+    pub fs: Option<String>,
+    /// The operation that failed, e.g. "READ" or "WRITE"
+    pub operation: Option<String>,
+    /// The rest of the message (or the sense key for SCSI sense data)
+    pub detail: String,
+    /// The time since boot of the entry
+    pub timestamp_from_system_start: Option<Duration>,
+}
+
+impl StorageError {
+    /// Classifies a line (an entry's message), or `None` when it's no storage error.
+    pub fn parse(line: &str) -> Option<StorageError> {
+        let line = line.trim();
+
+        if let Some(parts) = RE_BLOCK.captures(line) {
+            let error = parts["error"].to_lowercase();
+            let kind = if error.contains("medium") {
+                StorageErrorKind::MediumError
+            } else if error.contains("timeout") {
+                StorageErrorKind::Timeout
+            } else {
+                StorageErrorKind::IoError
+            };
+            let mut storage_error = Self::new(kind, &parts["device"], line);
+            storage_error.sector = parts["sector"].parse().ok();
+            storage_error.operation = parts.name("operation").map(|op| op.as_str().to_owned());
+            Some(storage_error)
+        } else if let Some(parts) = RE_BUFFER.captures(line) {
+            Some(Self::new(
+                StorageErrorKind::IoError,
+                &parts["device"],
+                &parts["detail"],
+            ))
+        } else if let Some(parts) = RE_SCSI_SENSE.captures(line) {
+            let kind = match &parts["key"] {
+                "Medium Error" => StorageErrorKind::MediumError,
+                _ => StorageErrorKind::ScsiSense,
+            };
+            Some(Self::new(kind, &parts["device"], &parts["key"]))
+        } else if let Some(parts) = RE_EXT.captures(line) {
+            let mut storage_error = Self::new(
+                StorageErrorKind::FilesystemError,
+                &parts["device"],
+                &parts["detail"],
+            );
+            storage_error.fs = Some(parts["fs"].to_lowercase());
+            Some(storage_error)
+        } else if let Some(parts) = RE_XFS.captures(line) {
+            let detail = &parts["detail"];
+            let kind = if detail.contains("metadata I/O error") {
+                StorageErrorKind::MetadataIoError
+            } else if detail.contains("Corruption") || detail.contains("corruption") {
+                StorageErrorKind::Corruption
+            } else if detail.contains("I/O error") || detail.contains("I/O Error") {
+                StorageErrorKind::IoError
+            } else {
+                // mounts, quotas, etc.
+                return None;
+            };
+            let mut storage_error = Self::new(kind, &parts["device"], detail);
+            storage_error.fs = Some("xfs".to_owned());
+            storage_error.sector = RE_XFS_DADDR
+                .captures(detail)
+                .and_then(|daddr| u64::from_str_radix(&daddr["daddr"], 16).ok());
+            Some(storage_error)
+        } else if let Some(parts) = RE_BTRFS.captures(line) {
+            let detail = &parts["detail"];
+            let kind = if detail.starts_with("bdev ") && detail.contains("errs:") {
+                StorageErrorKind::IoError
+            } else if detail.contains("csum") || detail.contains("checksum") {
+                StorageErrorKind::Corruption
+            } else if &parts["level"] != "warning" {
+                StorageErrorKind::FilesystemError
+            } else {
+                return None;
+            };
+            let mut storage_error = Self::new(kind, &parts["device"], detail);
+            storage_error.fs = Some("btrfs".to_owned());
+            Some(storage_error)
+        } else if let Some(parts) = RE_NVME_TIMEOUT.captures(line) {
+            Some(Self::new(StorageErrorKind::Timeout, &parts["device"], line))
+        } else if let Some(parts) = RE_NVME_DOWN.captures(line) {
+            Some(Self::new(
+                StorageErrorKind::ControllerReset,
+                &parts["device"],
+                &parts["detail"],
+            ))
+        } else if let Some(parts) = RE_NVME_IO.captures(line) {
+            let mut storage_error = Self::new(
+                StorageErrorKind::IoError,
+                &parts["device"],
+                &parts["detail"],
+            );
+            storage_error.sector = parts["lba"].parse().ok();
+            Some(storage_error)
+        } else {
+            None
+        }
+    }
+
+    /// Classifies an entry, keeping its timestamp.
+    pub fn from_entry(entry: &Entry) -> Option<StorageError> {
+        let mut storage_error = Self::parse(&entry.message)?;
+        storage_error.timestamp_from_system_start = entry.timestamp_from_system_start;
+        Some(storage_error)
+    }
+
+    /// The disk the device is (or is a partition of), e.g. "sda" for "sda1"
+    pub fn disk(&self) -> &str {
+        match RE_NUMBERED_PARTITION
+            .captures(&self.device)
+            .or_else(|| RE_LETTERED_PARTITION.captures(&self.device))
+        {
+            Some(parts) => parts
+                .name("disk")
+                .map_or(&self.device, |disk| disk.as_str()),
+            None => &self.device,
+        }
+    }
+
+    fn new(kind: StorageErrorKind, device: &str, detail: &str) -> Self {
+        Self {
+            kind,
+            device: device.to_owned(),
+            sector: None,
+            fs: None,
+            operation: None,
+            detail: detail.to_owned(),
+            timestamp_from_system_start: None,
+        }
+    }
+}
+
+/// Finds `StorageError`s among entries
+#[derive(Debug, Default)]
+pub struct StorageAnalyzer;
+
+impl StorageAnalyzer {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Analyzer for StorageAnalyzer {
+    type Report = StorageError;
+
+    fn push(&mut self, entry: &Entry) -> Vec<StorageError> {
+        StorageError::from_entry(entry).into_iter().collect()
+    }
+
+    fn finish(&mut self) -> Vec<StorageError> {
+        Vec::new()
+    }
+}
+
+/// The errors of one disk
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiskErrors {
+    pub errors: u64,
+    pub by_kind: BTreeMap<StorageErrorKind, u64>,
+    /// Every distinct sector an error was reported at
+    pub sectors: BTreeSet<u64>,
+    /// The filesystems that reported errors
+    pub filesystems: BTreeSet<String>,
+    pub first_seen: Option<Duration>,
+    pub last_seen: Option<Duration>,
+}
+
+impl DiskErrors {
+    /// The number of errors of a kind
+    pub fn count(&self, kind: StorageErrorKind) -> u64 {
+        self.by_kind.get(&kind).copied().unwrap_or_default()
+    }
+}
+
+/// Tallies storage errors per disk (partitions count towards their disk)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StorageErrorTally {
+    disks: BTreeMap<String, DiskErrors>,
+}
+
+impl StorageErrorTally {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, storage_error: &StorageError) {
+        let disk = self
+            .disks
+            .entry(storage_error.disk().to_owned())
+            .or_default();
+
+        disk.errors += 1;
+        *disk.by_kind.entry(storage_error.kind).or_default() += 1;
+        if let Some(sector) = storage_error.sector {
+            disk.sectors.insert(sector);
+        }
+        if let Some(fs) = &storage_error.fs {
+            disk.filesystems.insert(fs.clone());
+        }
+        if let Some(timestamp) = storage_error.timestamp_from_system_start {
+            disk.first_seen.get_or_insert(timestamp);
+            disk.last_seen = Some(timestamp);
+        }
+    }
+
+    /// The errors of a disk (e.g. "sda"), if it had any
+    pub fn disk(&self, disk: &str) -> Option<&DiskErrors> {
+        self.disks.get(disk)
+    }
+
+    /// Every disk that had errors, by name
+    pub fn disks(&self) -> impl Iterator<Item = (&str, &DiskErrors)> {
+        self.disks
+            .iter()
+            .map(|(name, errors)| (name.as_str(), errors))
+    }
+}
+
+impl<'a> FromIterator<&'a StorageError> for StorageErrorTally {
+    fn from_iter<T: IntoIterator<Item = &'a StorageError>>(errors: T) -> Self {
+        let mut tally = Self::new();
+        for storage_error in errors {
+            tally.add(storage_error);
+        }
+        tally
+    }
+}
+
+/**********************************************************************************/
+// Tests! Tests! Tests!
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis;
+    use crate::klogctl;
+
+    fn kind_and_device(line: &str) -> Option<(StorageErrorKind, String)> {
+        StorageError::parse(line).map(|e| (e.kind, e.device))
+    }
+
+    #[test]
+    fn test_classify() {
+        let error = StorageError::parse("blk_update_request: I/O error, dev sda, sector 2048 op 0x0:(READ) flags 0x0 phys_seg 1 prio class 0").unwrap();
+        assert_eq!(error.kind, StorageErrorKind::IoError);
+        assert_eq!(error.device, "sda");
+        assert_eq!(error.sector, Some(2048));
+        assert_eq!(error.operation.as_deref(), Some("READ"));
+
+        let error = StorageError::parse(
+            "critical medium error, dev sdb, sector 77 op 0x1:(WRITE) flags 0x800",
+        )
+        .unwrap();
+        assert_eq!(error.kind, StorageErrorKind::MediumError);
+        assert_eq!(error.operation.as_deref(), Some("WRITE"));
+
+        assert_eq!(
+            kind_and_device("Buffer I/O error on dev sda1, logical block 0, async page read"),
+            Some((StorageErrorKind::IoError, "sda1".to_owned()))
+        );
+        assert_eq!(
+            kind_and_device("sd 0:0:0:0: [sda] tag#0 Sense Key : Medium Error [current]"),
+            Some((StorageErrorKind::MediumError, "sda".to_owned()))
+        );
+        assert_eq!(
+            kind_and_device(
+                "sd 2:0:0:0: [sdc] tag#7 Sense Key : Hardware Error [current] [descriptor]"
+            ),
+            Some((StorageErrorKind::ScsiSense, "sdc".to_owned()))
+        );
+
+        let error = StorageError::parse("EXT4-fs error (device sda1): ext4_find_entry:1455: inode #2: comm ls: reading directory lblock 0").unwrap();
+        assert_eq!(error.kind, StorageErrorKind::FilesystemError);
+        assert_eq!(error.fs.as_deref(), Some("ext4"));
+        assert!(error.detail.starts_with("ext4_find_entry"));
+
+        let error = StorageError::parse("XFS (sdb1): metadata I/O error in \"xfs_trans_read_buf_map\" at daddr 0x2a0 len 8 error 5").unwrap();
+        assert_eq!(error.kind, StorageErrorKind::MetadataIoError);
+        assert_eq!(error.sector, Some(0x2a0));
+        assert_eq!(
+            StorageError::parse("XFS (sdb1): Mounting V5 Filesystem"),
+            None
+        );
+
+        assert_eq!(
+            kind_and_device("BTRFS error (device sdc): bdev /dev/sdc errs: wr 0, rd 1, flush 0, corrupt 0, gen 0"),
+            Some((StorageErrorKind::IoError, "sdc".to_owned()))
+        );
+        assert_eq!(
+            kind_and_device("BTRFS warning (device sdd state EA): csum failed root 5 ino 257 off 0 csum 0x8941f998 expected csum 0x00000000 mirror 1"),
+            Some((StorageErrorKind::Corruption, "sdd".to_owned()))
+        );
+
+        assert_eq!(
+            kind_and_device("nvme nvme0: I/O 123 QID 4 timeout, aborting"),
+            Some((StorageErrorKind::Timeout, "nvme0".to_owned()))
+        );
+        assert_eq!(
+            kind_and_device(
+                "nvme nvme0: controller is down; will reset: CSTS=0x3, PCI_STATUS=0x10"
+            ),
+            Some((StorageErrorKind::ControllerReset, "nvme0".to_owned()))
+        );
+        let error = StorageError::parse(
+            "nvme0n1: I/O Cmd(0x2) @ LBA 5120, 8 blocks, I/O Error (sct 0x2 / sc 0x81) MORE DNR",
+        )
+        .unwrap();
+        assert_eq!(error.sector, Some(5120));
+
+        assert_eq!(StorageError::parse("eth0: link up"), None);
+    }
+
+    #[test]
+    fn test_disks() {
+        let disk_of = |device: &str| {
+            StorageError::new(StorageErrorKind::IoError, device, "")
+                .disk()
+                .to_owned()
+        };
+        assert_eq!(disk_of("sda1"), "sda");
+        assert_eq!(disk_of("xvdb12"), "xvdb");
+        assert_eq!(disk_of("nvme0n1p2"), "nvme0n1");
+        assert_eq!(disk_of("mmcblk0p1"), "mmcblk0");
+        assert_eq!(disk_of("nvme0n1"), "nvme0n1");
+        assert_eq!(disk_of("dm-0"), "dm-0");
+    }
+
+    #[test]
+    fn test_tally() {
+        let entries = klogctl::entries_from_lines(
+            "<3>[   10.000000] blk_update_request: I/O error, dev sda, sector 2048 op 0x0:(READ) flags 0x0 phys_seg 1 prio class 0\n\
+             <3>[   10.000001] Buffer I/O error on dev sda1, logical block 0, async page read\n\
+             <6>[   11.000000] eth0: link up\n\
+             <3>[   12.000000] blk_update_request: I/O error, dev sda, sector 2048 op 0x0:(READ) flags 0x0 phys_seg 1 prio class 0\n\
+             <3>[   13.000000] EXT4-fs error (device sda1): ext4_find_entry:1455: inode #2: comm ls: reading directory lblock 0\n\
+             <3>[   14.000000] critical medium error, dev sdb, sector 77 op 0x0:(READ) flags 0x0",
+        )
+        .unwrap();
+        let errors = analysis::analyze(StorageAnalyzer::new(), &entries);
+        assert_eq!(errors.len(), 5);
+
+        let tally: StorageErrorTally = errors.iter().collect();
+        let disks: Vec<&str> = tally.disks().map(|(name, _)| name).collect();
+        assert_eq!(disks, vec!["sda", "sdb"]);
+
+        let sda = tally.disk("sda").unwrap();
+        assert_eq!(sda.errors, 4);
+        assert_eq!(sda.count(StorageErrorKind::IoError), 3);
+        assert_eq!(sda.count(StorageErrorKind::FilesystemError), 1);
+        assert_eq!(sda.sectors.len(), 1);
+        assert!(sda.filesystems.contains("ext4"));
+        assert_eq!(sda.first_seen, Some(Duration::from_secs(10)));
+        assert_eq!(sda.last_seen, Some(Duration::from_secs(13)));
+
+        assert_eq!(
+            tally
+                .disk("sdb")
+                .unwrap()
+                .count(StorageErrorKind::MediumError),
+            1
+        );
+        assert_eq!(tally.disk("sdc"), None);
+    }
+}
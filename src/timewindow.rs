@@ -0,0 +1,502 @@
+use crate::common;
+use crate::entry::Entry;
+/// This module keeps only the entries logged within a window of time (e.g. the five
+/// minutes around an alert), as the CLI's `--since` and `--until` do.
+///
+/// The bounds of a window can be given relative to boot (like entry timestamps), or as
+/// wall-clock times. Wall-clock bounds are mapped to the time since boot through the boot
+/// time, which is estimated the way `dmesg -T` does (and so drifts after suspend/resume).
+///
+/// Entries are compared by their `timestamp_from_system_start`, or their `wallclock_time`
+/// when that's all they carry. Entries with neither (e.g. continuation lines) go wherever
+/// the entry before them went.
+///
+use crate::error::RMesgError;
+#[cfg(feature = "async")]
+use crate::source::AsyncLogSource;
+#[cfg(feature = "sync")]
+use crate::source::LogSource;
+
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "async")]
+use core::pin::Pin;
+#[cfg(feature = "async")]
+use futures::stream::Stream;
+#[cfg(feature = "async")]
+use futures::task::{Context, Poll};
+#[cfg(feature = "async")]
+use pin_project::pin_project;
+
+lazy_static! {
+    static ref RE_RELATIVE: Regex = Regex::new(
+        r"(?x)^
+        (?:-[[:space:]]*)?
+        (?P<amount>[[:digit:]]+(?:\.[[:digit:]]+)?)[[:space:]]*
+        (?P<unit>s|sec|secs|seconds?|m|min|mins|minutes?|h|hours?|d|days?)
+        (?P<ago>[[:space:]]+ago)?
+        $"
+    )
+    .unwrap();
+    static ref RE_ABSOLUTE: Regex = Regex::new(
+        r"(?x)^
+        (?P<year>[[:digit:]]{4})-(?P<month>[[:digit:]]{2})-(?P<day>[[:digit:]]{2})
+        (?:[T[:space:]]
+            (?P<hour>[[:digit:]]{2}):(?P<minute>[[:digit:]]{2})
+            (?::(?P<second>[[:digit:]]{2})(?:[.,](?P<fraction>[[:digit:]]{1,9}))?)?
+        )?
+        [[:space:]]*
+        (?:(?P<utc>Z)|(?P<offset_sign>[+-])(?P<offset_hours>[[:digit:]]{2}):?(?P<offset_minutes>[[:digit:]]{2}))?
+        $"
+    )
+    .unwrap();
+}
+
+/// One end of a window of time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeBound {
+    /// The time since boot (as in `Entry::timestamp_from_system_start`)
+    SinceBoot(Duration),
+
+    /// A wall-clock time
+    WallClock(SystemTime),
+}
+
+/// Parses a time bound from:
+///   * seconds since boot: "1234.5"
+///   * a wall-clock time: "2026-10-18 10:00", "2026-10-18T10:00:00+02:00" (UTC unless an offset is
+///     given), "@1791973321" (seconds since the UNIX epoch) or "now"
+///   * a time relative to now: "10 min ago", "-10min", "2 hours ago" (units are s, min, h and d)
+impl FromStr for TimeBound {
+    type Err = RMesgError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let trimmed = value.trim();
+        let invalid = |reason: &str| RMesgError::InvalidTimeBound {
+            value: value.to_owned(),
+            reason: reason.to_owned(),
+        };
+
+        if trimmed == "now" {
+            return Ok(Self::WallClock(SystemTime::now()));
+        }
+
+        if let Some(epoch_secs) = trimmed.strip_prefix('@') {
+            return match epoch_secs.parse::<f64>() {
+                Ok(secs) if secs >= 0.0 => duration_from_secs(secs)
+                    .and_then(|since_epoch| UNIX_EPOCH.checked_add(since_epoch))
+                    .map(Self::WallClock)
+                    .ok_or_else(|| invalid("time out of range")),
+                _ => Err(invalid("expected seconds since the UNIX epoch after '@'")),
+            };
+        }
+
+        if let Ok(secs) = trimmed.parse::<f64>() {
+            return match secs >= 0.0 {
+                true => duration_from_secs(secs)
+                    .map(Self::SinceBoot)
+                    .ok_or_else(|| invalid("time out of range")),
+                false => Err(invalid("seconds since boot can't be negative")),
+            };
+        }
+
+        if let Some(parts) = RE_RELATIVE.captures(trimmed) {
+            // a bare "10min" would be ambiguous; it must be "10min ago" or "-10min"
+            if parts.name("ago").is_none() && !trimmed.starts_with('-') {
+                return Err(invalid(
+                    "relative times must be in the past, e.g. '10 min ago'",
+                ));
+            }
+            return match relative_duration(&parts)
+                .and_then(|ago| SystemTime::now().checked_sub(ago))
+            {
+                Some(time) => Ok(Self::WallClock(time)),
+                None => Err(invalid("time out of range")),
+            };
+        }
+
+        if let Some(parts) = RE_ABSOLUTE.captures(trimmed) {
+            return match absolute_time(&parts, trimmed) {
+                Some(time) => Ok(Self::WallClock(time)),
+                None => Err(invalid("not a valid date/time")),
+            };
+        }
+
+        Err(invalid(
+            "expected seconds since boot, a date/time, '@<epoch seconds>' or e.g. '10 min ago'",
+        ))
+    }
+}
+
+/// Keeps the entries logged within a window of time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeWindow {
+    since: Option<Duration>,
+    until: Option<Duration>,
+    boot_time: SystemTime,
+    last_included: Option<bool>,
+}
+
+impl TimeWindow {
+    /// A window between two optional bounds (inclusive), with wall-clock bounds mapped
+    /// through the current boot time.
+    pub fn new(since: Option<TimeBound>, until: Option<TimeBound>) -> Result<Self, RMesgError> {
+        Ok(Self::with_boot_time(since, until, boot_time()?))
+    }
+
+    /// Same as `new`, with wall-clock bounds mapped through a known boot time
+    /// (e.g. to filter entries read on another system).
+    pub fn with_boot_time(
+        since: Option<TimeBound>,
+        until: Option<TimeBound>,
+        boot_time: SystemTime,
+    ) -> Self {
+        let since_boot = |bound: TimeBound| match bound {
+            TimeBound::SinceBoot(d) => d,
+            // before boot is the same as at boot
+            TimeBound::WallClock(t) => t.duration_since(boot_time).unwrap_or_default(),
+        };
+
+        Self {
+            since: since.map(since_boot),
+            until: until.map(since_boot),
+            boot_time,
+            last_included: None,
+        }
+    }
+
+    /// Whether an entry falls within the window, or `None` when the entry carries no time.
+    pub fn contains(&self, entry: &Entry) -> Option<bool> {
+        let time = self.since_boot(entry)?;
+        Some(
+            !matches!(self.since, Some(since) if time < since)
+                && !matches!(self.until, Some(until) if time > until),
+        )
+    }
+
+    /// Whether an entry was logged after the window ended (so no later entry can be in it).
+    pub fn has_ended(&self, entry: &Entry) -> bool {
+        match (self.since_boot(entry), self.until) {
+            (Some(time), Some(until)) => time > until,
+            _ => false,
+        }
+    }
+
+    /// Whether to keep an entry, in a sequence of entries.
+    /// Entries without a time are kept when the entry before them was.
+    pub fn accepts(&mut self, entry: &Entry) -> bool {
+        let included = match self.contains(entry) {
+            Some(included) => included,
+            None => self
+                .last_included
+                .unwrap_or(self.since.is_none() && self.until.is_none()),
+        };
+        self.last_included = Some(included);
+        included
+    }
+
+    fn since_boot(&self, entry: &Entry) -> Option<Duration> {
+        match (entry.timestamp_from_system_start, entry.wallclock_time) {
+            (Some(timestamp), _) => Some(timestamp),
+            (None, Some(time)) => Some(time.duration_since(self.boot_time).unwrap_or_default()),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Keeps the entries of a vector that fall within the window.
+pub fn filter_entries(entries: Vec<Entry>, window: &mut TimeWindow) -> Vec<Entry> {
+    entries
+        .into_iter()
+        .filter(|entry| window.accepts(entry))
+        .collect()
+}
+
+/// Wraps an iterator of entries, keeping only those within the window, and ending
+/// once an entry past the window is seen. Errors are passed through.
+#[cfg(feature = "sync")]
+pub struct TimeWindowIter<I> {
+    inner: I,
+    window: TimeWindow,
+    ended: bool,
+}
+
+#[cfg(feature = "sync")]
+impl<I> TimeWindowIter<I> {
+    pub fn new(inner: I, window: TimeWindow) -> Self {
+        Self {
+            inner,
+            window,
+            ended: false,
+        }
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<I: Iterator<Item = Result<Entry, RMesgError>>> Iterator for TimeWindowIter<I> {
+    type Item = Result<Entry, RMesgError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.ended {
+            match self.inner.next()? {
+                Ok(entry) if self.window.has_ended(&entry) => self.ended = true,
+                Ok(entry) if !self.window.accepts(&entry) => {}
+                result => return Some(result),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<I: LogSource> LogSource for TimeWindowIter<I> {
+    fn loss_stats(&self) -> crate::loss::LossStats {
+        self.inner.loss_stats()
+    }
+}
+
+/// Wraps a stream of entries, keeping only those within the window, and ending
+/// once an entry past the window is seen. Errors are passed through.
+#[cfg(feature = "async")]
+#[pin_project]
+pub struct TimeWindowStream<S> {
+    #[pin]
+    inner: S,
+    window: TimeWindow,
+    ended: bool,
+}
+
+#[cfg(feature = "async")]
+impl<S> TimeWindowStream<S> {
+    pub fn new(inner: S, window: TimeWindow) -> Self {
+        Self {
+            inner,
+            window,
+            ended: false,
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<S: Stream<Item = Result<Entry, RMesgError>>> Stream for TimeWindowStream<S> {
+    type Item = Result<Entry, RMesgError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        while !*this.ended {
+            match this.inner.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(entry))) if this.window.has_ended(&entry) => *this.ended = true,
+                Poll::Ready(Some(Ok(entry))) if !this.window.accepts(&entry) => {}
+                polled => return polled,
+            }
+        }
+        Poll::Ready(None)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<S: AsyncLogSource> AsyncLogSource for TimeWindowStream<S> {
+    fn loss_stats(&self) -> crate::loss::LossStats {
+        self.inner.loss_stats()
+    }
+}
+
+/// Estimates when the system booted: now, less the time since boot (CLOCK_BOOTTIME),
+/// as `dmesg -T` does.
+#[cfg(target_os = "linux")]
+pub fn boot_time() -> Result<SystemTime, RMesgError> {
+    let mut since_boot = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_gettime(libc::CLOCK_BOOTTIME, &mut since_boot) } != 0 {
+        return Err(RMesgError::UnableToObtainSystemTime);
+    }
+
+    let since_boot = Duration::new(since_boot.tv_sec as u64, since_boot.tv_nsec as u32);
+    match SystemTime::now().checked_sub(since_boot) {
+        Some(time) => Ok(time),
+        None => Err(RMesgError::UnableToObtainSystemTime),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn boot_time() -> Result<SystemTime, RMesgError> {
+    Err(RMesgError::NotImplementedForThisPlatform)
+}
+
+/// A duration of (non-negative) seconds, or `None` when it's not finite or too long to hold.
+fn duration_from_secs(secs: f64) -> Option<Duration> {
+    if !secs.is_finite() {
+        return None;
+    }
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+fn relative_duration(parts: &Captures) -> Option<Duration> {
+    let amount: f64 = parts["amount"].parse().ok()?;
+    let unit_secs = match parts["unit"].chars().next() {
+        Some('m') => 60.0,
+        Some('h') => 3600.0,
+        Some('d') => 86400.0,
+        _ => 1.0,
+    };
+    duration_from_secs(amount * unit_secs)
+}
+
+fn absolute_time(parts: &Captures, value: &str) -> Option<SystemTime> {
+    let number = |name: &str| -> Option<u32> {
+        match parts.name(name) {
+            Some(m) => m.as_str().parse().ok(),
+            None => Some(0),
+        }
+    };
+
+    // the fraction is right-padded to nanoseconds
+    let nanos = match parts.name("fraction") {
+        Some(fraction) => format!("{:0<9}", fraction.as_str()).parse().ok()?,
+        None => 0,
+    };
+
+    let local = common::system_time_from_civil(
+        (i64::from(number("year")?), number("month")?, number("day")?),
+        (number("hour")?, number("minute")?, number("second")?, nanos),
+        value,
+    )
+    .ok()?;
+
+    let utc_offset_secs = match parts.name("offset_sign") {
+        None => 0,
+        Some(sign) => {
+            let offset = (number("offset_hours")? * 3600 + number("offset_minutes")? * 60) as i32;
+            match sign.as_str() {
+                "-" => -offset,
+                _ => offset,
+            }
+        }
+    };
+
+    common::shift_to_utc(local, utc_offset_secs)
+}
+
+/**********************************************************************************/
+// Tests! Tests! Tests!
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const OCT_14_2026_102201_UTC: u64 = 1_791_973_321;
+
+    fn entry_at(secs: Option<u64>, message: &str) -> Entry {
+        Entry {
+            facility: None,
+            level: None,
+            sequence_num: None,
+            timestamp_from_system_start: secs.map(Duration::from_secs),
+            wallclock_time: None,
+            message: message.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_parse_bounds() {
+        assert_eq!(
+            TimeBound::from_str("120.5").unwrap(),
+            TimeBound::SinceBoot(Duration::from_millis(120_500))
+        );
+        assert_eq!(
+            TimeBound::from_str("@1791973321").unwrap(),
+            TimeBound::WallClock(UNIX_EPOCH + Duration::from_secs(OCT_14_2026_102201_UTC))
+        );
+        assert_eq!(
+            TimeBound::from_str("2026-10-14 10:22:01").unwrap(),
+            TimeBound::WallClock(UNIX_EPOCH + Duration::from_secs(OCT_14_2026_102201_UTC))
+        );
+        assert_eq!(
+            TimeBound::from_str("2026-10-14T12:22:01.5+02:00").unwrap(),
+            TimeBound::WallClock(
+                UNIX_EPOCH + Duration::from_millis(OCT_14_2026_102201_UTC * 1000 + 500)
+            )
+        );
+
+        let before = SystemTime::now() - Duration::from_secs(600);
+        for relative in ["10 min ago", "10min ago", "-10min", "- 10 minutes"] {
+            match TimeBound::from_str(relative).unwrap() {
+                TimeBound::WallClock(t) => {
+                    assert!(t.duration_since(before).unwrap() < Duration::from_secs(5))
+                }
+                bound => panic!("{} parsed as {:?}", relative, bound),
+            }
+        }
+
+        assert!(TimeBound::from_str("10min").is_err());
+        assert!(TimeBound::from_str("2026-13-01").is_err());
+        assert!(TimeBound::from_str("yesterday-ish").is_err());
+
+        // out of range, rather than a panic
+        for out_of_range in [
+            "1e20",
+            "inf",
+            "NaN",
+            "@inf",
+            "@1e19",
+            "@1e300",
+            "99999999999999999999 days ago",
+        ] {
+            assert!(
+                TimeBound::from_str(out_of_range).is_err(),
+                "{} was accepted",
+                out_of_range
+            );
+        }
+    }
+
+    #[test]
+    fn test_window() {
+        let boot_time = UNIX_EPOCH + Duration::from_secs(OCT_14_2026_102201_UTC);
+        let mut window = TimeWindow::with_boot_time(
+            Some(TimeBound::SinceBoot(Duration::from_secs(10))),
+            Some(TimeBound::WallClock(boot_time + Duration::from_secs(20))),
+            boot_time,
+        );
+
+        let entries = vec![
+            entry_at(Some(5), "too early"),
+            entry_at(None, "continues too early"),
+            entry_at(Some(10), "first"),
+            entry_at(None, "continues first"),
+            entry_at(Some(20), "last"),
+            entry_at(Some(21), "too late"),
+        ];
+        assert!(window.has_ended(&entries[5]));
+
+        let messages: Vec<String> = filter_entries(entries, &mut window)
+            .into_iter()
+            .map(|entry| entry.message)
+            .collect();
+        assert_eq!(messages, ["first", "continues first", "last"]);
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn test_window_iter() {
+        let window = TimeWindow::with_boot_time(
+            None,
+            Some(TimeBound::SinceBoot(Duration::from_secs(1))),
+            UNIX_EPOCH,
+        );
+        let source = crate::mock::ScriptedSource::new()
+            .entry(entry_at(Some(0), "in"))
+            .entry(entry_at(Some(2), "out"))
+            .entry(entry_at(Some(0), "never read"));
+
+        let messages: Vec<String> = TimeWindowIter::new(source, window)
+            .map(|entry| entry.unwrap().message)
+            .collect();
+        assert_eq!(messages, ["in"]);
+    }
+}
@@ -0,0 +1,457 @@
+use crate::analysis::Analyzer;
+/// This module follows USB devices as they are plugged in and out, from the lines the USB core
+/// logs about them, into `UsbEvent`s; and keeps a table of the devices attached (with their
+/// vendor, product and serial number) and detached, and when:
+///
+/// ```text
+/// usb 1-1: new high-speed USB device number 2 using xhci_hcd
+/// usb 1-1: New USB device found, idVendor=0781, idProduct=5567, bcdDevice= 1.00
+/// usb 1-1: New USB device strings: Mfr=1, Product=2, SerialNumber=3
+/// usb 1-1: Product: Cruzer Blade
+/// usb 1-1: Manufacturer: SanDisk
+/// usb 1-1: SerialNumber: 4C530001230809115213
+/// usb 1-1: USB disconnect, device number 2
+/// ```
+///
+/// Records read from /dev/kmsg are followed by their dictionary, e.g.
+/// ` SUBSYSTEM=usb` and ` DEVICE=+usb:1-1` (each its own entry, see the `kmsgfile` module),
+/// which is used for the subsystem and device when present.
+///
+use crate::entry::Entry;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::iter::FromIterator;
+use std::time::Duration;
+
+lazy_static! {
+    static ref RE_USB: Regex =
+        Regex::new(r"^usb (?P<device>[^[:space:]:]+): (?P<event>.*)$").unwrap();
+    static ref RE_ATTACHED: Regex = Regex::new(
+        r"^new (?P<speed>[[:alnum:]-]+) USB device number (?P<number>[[:digit:]]+)(?: using (?P<controller>[^[:space:]]+))?"
+    )
+    .unwrap();
+    static ref RE_IDENTIFIED: Regex = Regex::new(
+        r"^New USB device found, idVendor=(?P<vendor>[[:xdigit:]]{4}), idProduct=(?P<product>[[:xdigit:]]{4})(?:, bcdDevice=[[:space:]]*(?P<bcd>[[:digit:].]+))?"
+    )
+    .unwrap();
+    static ref RE_STRING: Regex =
+        Regex::new(r"^(?P<name>Product|Manufacturer|SerialNumber): (?P<value>.*)$").unwrap();
+    static ref RE_DETACHED: Regex =
+        Regex::new(r"^USB disconnect, device number (?P<number>[[:digit:]]+)").unwrap();
+    static ref RE_ENUMERATION_ERROR: Regex = Regex::new(
+        r"^(?:device descriptor read|device not accepting address|unable to enumerate USB device|device not responding)"
+    )
+    .unwrap();
+    static ref RE_DICTIONARY: Regex =
+        Regex::new(r"^[[:space:]]+(?P<key>[A-Z_]+)=(?P<value>.*)$").unwrap();
+}
+
+/// What happened to a USB device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UsbEventKind {
+    /// A device was plugged in (the speed is e.g. "high-speed", the controller e.g. "xhci_hcd")
+    Attached {
+        speed: String,
+        number: u32,
+        controller: Option<String>,
+    },
+    /// The device's descriptor was read
+    Identified {
+        vendor_id: u16,
+        product_id: u16,
+        bcd_device: Option<String>,
+    },
+    Product(String),
+    Manufacturer(String),
+    SerialNumber(String),
+    /// A device was unplugged
+    Detached {
+        number: u32,
+    },
+    /// A device could not be enumerated (e.g. a bad cable, or a device misbehaving on purpose)
+    EnumerationError(String),
+}
+
+/// An event of a USB device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsbEvent {
+    /// The device's path on the bus (e.g. "1-1.2"), or the root hub's name (e.g. "usb1")
+    pub device: String,
+    /// The subsystem from the record's dictionary, when it had one
+    pub subsystem: Option<String>,
+    pub kind: UsbEventKind,
+    /// The time since boot of the entry
+    pub timestamp_from_system_start: Option<Duration>,
+}
+
+impl UsbEvent {
+    /// Parses a line (an entry's message), or `None` when it's no USB device event.
+    pub fn parse(line: &str) -> Option<UsbEvent> {
+        let parts = RE_USB.captures(line.trim())?;
+        let event = parts.name("event")?.as_str();
+
+        let kind = if let Some(attached) = RE_ATTACHED.captures(event) {
+            UsbEventKind::Attached {
+                speed: attached["speed"].to_owned(),
+                number: attached["number"].parse().ok()?,
+                controller: attached
+                    .name("controller")
+                    .map(|controller| controller.as_str().to_owned()),
+            }
+        } else if let Some(identified) = RE_IDENTIFIED.captures(event) {
+            UsbEventKind::Identified {
+                vendor_id: u16::from_str_radix(&identified["vendor"], 16).ok()?,
+                product_id: u16::from_str_radix(&identified["product"], 16).ok()?,
+                bcd_device: identified.name("bcd").map(|bcd| bcd.as_str().to_owned()),
+            }
+        } else if let Some(string) = RE_STRING.captures(event) {
+            let value = string["value"].to_owned();
+            match &string["name"] {
+                "Product" => UsbEventKind::Product(value),
+                "Manufacturer" => UsbEventKind::Manufacturer(value),
+                _ => UsbEventKind::SerialNumber(value),
+            }
+        } else if let Some(detached) = RE_DETACHED.captures(event) {
+            UsbEventKind::Detached {
+                number: detached["number"].parse().ok()?,
+            }
+        } else if RE_ENUMERATION_ERROR.is_match(event) {
+            UsbEventKind::EnumerationError(event.to_owned())
+        } else {
+            return None;
+        };
+
+        Some(UsbEvent {
+            device: parts["device"].to_owned(),
+            subsystem: None,
+            kind,
+            timestamp_from_system_start: None,
+        })
+    }
+
+    /// Parses an entry, keeping its timestamp.
+    pub fn from_entry(entry: &Entry) -> Option<UsbEvent> {
+        let mut event = Self::parse(&entry.message)?;
+        event.timestamp_from_system_start = entry.timestamp_from_system_start;
+        Some(event)
+    }
+
+    /// Applies a dictionary line of the event's record, returning whether it was the last one
+    /// (the kernel writes SUBSYSTEM before DEVICE).
+    fn add_dictionary(&mut self, key: &str, value: &str) -> bool {
+        match key {
+            "SUBSYSTEM" => {
+                self.subsystem = Some(value.to_owned());
+                false
+            }
+            "DEVICE" => {
+                // "+<subsystem>:<name>" for devices that are neither block, char nor net devices
+                if let Some((_, name)) = value.strip_prefix('+').and_then(|d| d.split_once(':')) {
+                    self.device = name.to_owned();
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Finds `UsbEvent`s among entries.
+///
+/// An event read from /dev/kmsg (whose entries have sequence numbers) is reported once its
+/// dictionary has been read, or the next record has; events from other sources (which carry
+/// no dictionary) are reported right away.
+#[derive(Debug, Default)]
+pub struct UsbAnalyzer {
+    pending: Option<UsbEvent>,
+}
+
+impl UsbAnalyzer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Analyzer for UsbAnalyzer {
+    type Report = UsbEvent;
+
+    fn push(&mut self, entry: &Entry) -> Vec<UsbEvent> {
+        if entry.level.is_none() {
+            if let Some(parts) = RE_DICTIONARY.captures(&entry.message) {
+                if let Some(event) = self.pending.as_mut() {
+                    if event.add_dictionary(&parts["key"], &parts["value"]) {
+                        return self.pending.take().into_iter().collect();
+                    }
+                }
+                return Vec::new();
+            }
+        }
+
+        let mut events: Vec<UsbEvent> = self.pending.take().into_iter().collect();
+        if let Some(event) = UsbEvent::from_entry(entry) {
+            if entry.sequence_num.is_some() {
+                self.pending = Some(event);
+            } else {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    fn finish(&mut self) -> Vec<UsbEvent> {
+        self.pending.take().into_iter().collect()
+    }
+}
+
+/// A USB device, as known from its events
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UsbDevice {
+    /// The device's path on the bus (e.g. "1-1.2")
+    pub device: String,
+    /// The device number it was given on its bus
+    pub number: Option<u32>,
+    pub speed: Option<String>,
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+    pub attached_at: Option<Duration>,
+    pub detached_at: Option<Duration>,
+}
+
+/// The USB devices attached (by their path on the bus), and those that were detached since,
+/// e.g. to audit which devices were plugged in and for how long
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UsbDeviceTable {
+    attached: BTreeMap<String, UsbDevice>,
+    detached: Vec<UsbDevice>,
+}
+
+impl UsbDeviceTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, event: &UsbEvent) {
+        let timestamp = event.timestamp_from_system_start;
+
+        match &event.kind {
+            UsbEventKind::Attached { speed, number, .. } => {
+                // a device still attached at the same path was unplugged without a trace
+                if let Some(previous) = self.attached.remove(&event.device) {
+                    self.detached.push(previous);
+                }
+                self.attached.insert(
+                    event.device.clone(),
+                    UsbDevice {
+                        device: event.device.clone(),
+                        number: Some(*number),
+                        speed: Some(speed.clone()),
+                        attached_at: timestamp,
+                        ..UsbDevice::default()
+                    },
+                );
+            }
+            UsbEventKind::Detached { .. } => {
+                if let Some(mut device) = self.attached.remove(&event.device) {
+                    device.detached_at = timestamp;
+                    self.detached.push(device);
+                }
+            }
+            UsbEventKind::EnumerationError(_) => {}
+            kind => {
+                let device = self
+                    .attached
+                    .entry(event.device.clone())
+                    .or_insert_with(|| UsbDevice {
+                        device: event.device.clone(),
+                        ..UsbDevice::default()
+                    });
+                match kind {
+                    UsbEventKind::Identified {
+                        vendor_id,
+                        product_id,
+                        ..
+                    } => {
+                        device.vendor_id = Some(*vendor_id);
+                        device.product_id = Some(*product_id);
+                    }
+                    UsbEventKind::Product(product) => device.product = Some(product.clone()),
+                    UsbEventKind::Manufacturer(manufacturer) => {
+                        device.manufacturer = Some(manufacturer.clone())
+                    }
+                    UsbEventKind::SerialNumber(serial) => {
+                        device.serial_number = Some(serial.clone())
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// The device attached at a path (e.g. "1-1.2"), if any
+    pub fn device(&self, device: &str) -> Option<&UsbDevice> {
+        self.attached.get(device)
+    }
+
+    /// Every device attached, by path
+    pub fn attached(&self) -> impl Iterator<Item = &UsbDevice> {
+        self.attached.values()
+    }
+
+    /// Every device detached, in the order they were
+    pub fn detached(&self) -> &[UsbDevice] {
+        &self.detached
+    }
+}
+
+impl<'a> FromIterator<&'a UsbEvent> for UsbDeviceTable {
+    fn from_iter<T: IntoIterator<Item = &'a UsbEvent>>(events: T) -> Self {
+        let mut table = Self::new();
+        for event in events {
+            table.add(event);
+        }
+        table
+    }
+}
+
+/**********************************************************************************/
+// Tests! Tests! Tests!
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis;
+    use crate::common;
+    use crate::klogctl;
+    use crate::kmsgfile;
+
+    const PLUG_AND_UNPLUG: &str =
+        "<6>[   10.000000] usb 1-1: new high-speed USB device number 2 using xhci_hcd
+<6>[   10.200000] usb 1-1: New USB device found, idVendor=0781, idProduct=5567, bcdDevice= 1.00
+<6>[   10.200010] usb 1-1: New USB device strings: Mfr=1, Product=2, SerialNumber=3
+<6>[   10.200020] usb 1-1: Product: Cruzer Blade
+<6>[   10.200030] usb 1-1: Manufacturer: SanDisk
+<6>[   10.200040] usb 1-1: SerialNumber: 4C530001230809115213
+<6>[   10.300000] usb-storage 1-1:1.0: USB Mass Storage device detected
+<6>[   12.000000] usb 1-2: new full-speed USB device number 3 using xhci_hcd
+<6>[   12.200000] usb 1-2: New USB device found, idVendor=046d, idProduct=c52b, bcdDevice=12.11
+<6>[   42.000000] usb 1-1: USB disconnect, device number 2";
+
+    #[test]
+    fn test_parse() {
+        let event = UsbEvent::parse("usb 1-1.4: new high-speed USB device number 5 using ehci-pci")
+            .unwrap();
+        assert_eq!(event.device, "1-1.4");
+        assert_eq!(
+            event.kind,
+            UsbEventKind::Attached {
+                speed: "high-speed".to_owned(),
+                number: 5,
+                controller: Some("ehci-pci".to_owned())
+            }
+        );
+
+        assert_eq!(
+            UsbEvent::parse(
+                "usb 1-1: New USB device found, idVendor=0781, idProduct=5567, bcdDevice= 1.00"
+            )
+            .unwrap()
+            .kind,
+            UsbEventKind::Identified {
+                vendor_id: 0x0781,
+                product_id: 0x5567,
+                bcd_device: Some("1.00".to_owned())
+            }
+        );
+        assert_eq!(
+            UsbEvent::parse("usb 1-1: SerialNumber: 4C530001230809115213")
+                .unwrap()
+                .kind,
+            UsbEventKind::SerialNumber("4C530001230809115213".to_owned())
+        );
+        assert_eq!(
+            UsbEvent::parse("usb 1-1: USB disconnect, device number 2")
+                .unwrap()
+                .kind,
+            UsbEventKind::Detached { number: 2 }
+        );
+        assert_eq!(
+            UsbEvent::parse("usb 3-2: device descriptor read/64, error -71")
+                .unwrap()
+                .kind,
+            UsbEventKind::EnumerationError("device descriptor read/64, error -71".to_owned())
+        );
+
+        assert_eq!(
+            UsbEvent::parse("usb 1-1: New USB device strings: Mfr=1, Product=2, SerialNumber=3"),
+            None
+        );
+        assert_eq!(
+            UsbEvent::parse("usb-storage 1-1:1.0: USB Mass Storage device detected"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_device_table() {
+        let entries = klogctl::entries_from_lines(PLUG_AND_UNPLUG).unwrap();
+        let events = analysis::analyze(UsbAnalyzer::new(), &entries);
+        assert_eq!(events.len(), 8);
+
+        let table: UsbDeviceTable = events.iter().collect();
+        let attached: Vec<&str> = table.attached().map(|d| d.device.as_str()).collect();
+        assert_eq!(attached, vec!["1-2"]);
+        assert_eq!(table.device("1-2").unwrap().vendor_id, Some(0x046d));
+
+        let detached = &table.detached()[0];
+        assert_eq!(detached.device, "1-1");
+        assert_eq!(detached.number, Some(2));
+        assert_eq!(detached.product.as_deref(), Some("Cruzer Blade"));
+        assert_eq!(detached.manufacturer.as_deref(), Some("SanDisk"));
+        assert_eq!(
+            detached.serial_number.as_deref(),
+            Some("4C530001230809115213")
+        );
+        assert_eq!(detached.attached_at, Some(Duration::from_secs(10)));
+        assert_eq!(detached.detached_at, Some(Duration::from_secs(42)));
+    }
+
+    #[test]
+    fn test_kmsg_dictionary() {
+        let entries = common::entries_from_numbered_lines(
+            "6,501,10000000,-;usb 1-1: new high-speed USB device number 2 using xhci_hcd
+ SUBSYSTEM=usb
+ DEVICE=+usb:1-1
+6,502,10200000,-;usb 1-1: Product: Cruzer Blade
+ SUBSYSTEM=usb
+ DEVICE=+usb:1-1
+6,503,10300000,-;usb 1-1: Manufacturer: SanDisk",
+            kmsgfile::entry_from_line,
+        )
+        .unwrap();
+
+        let mut analyzer = UsbAnalyzer::new();
+        let mut events = Vec::new();
+        for entry in &entries {
+            events.extend(analyzer.push(entry));
+        }
+
+        // the last event waits for its dictionary, or the next record
+        assert_eq!(events.len(), 2);
+        assert!(events
+            .iter()
+            .all(|event| event.subsystem.as_deref() == Some("usb") && event.device == "1-1"));
+
+        let last = analyzer.finish();
+        assert_eq!(last.len(), 1);
+        assert_eq!(last[0].subsystem, None);
+        assert_eq!(
+            last[0].timestamp_from_system_start,
+            Some(Duration::from_micros(10_300_000))
+        );
+    }
+}
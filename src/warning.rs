@@ -0,0 +1,318 @@
+use crate::analysis::{Analyzer, Frame, TaskInfo, TraceReport};
+/// This module recognizes kernel warnings (`WARN()`/`WARN_ON()` splats) and groups their
+/// entries into a `KernelWarning`:
+///
+/// ```text
+/// ------------[ cut here ]------------
+/// WARNING: CPU: 3 PID: 12 at drivers/foo.c:123 foo_func+0x12/0x40 [foo]
+/// Modules linked in: foo ...
+/// CPU: 3 PID: 12 Comm: kworker/3:1 Tainted: G        W 6.1.0 #1
+/// RIP: 0010:foo_func+0x12/0x40 [foo]
+/// Call Trace:
+///  ...
+/// ---[ end trace 0000000000000000 ]---
+/// ```
+///
+/// The same warning hits repeatedly (and across hosts), so every warning has a
+/// `WarningSignature` which stays the same across boots and kernel builds, to count them by.
+///
+use crate::entry::{Entry, LogFacility, LogLevel};
+use crate::oops;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::time::Duration;
+
+/// The number of call trace frames (besides the warning's own location) in a signature
+const SIGNATURE_FRAMES: usize = 3;
+
+/// A `WARN()` with a message prints it between "cut here" and "WARNING:"; anything
+/// longer isn't a warning.
+const MAX_MESSAGE_LINES: usize = 4;
+
+lazy_static! {
+    static ref RE_WARNING: Regex = Regex::new(
+        r"(?x)^
+        WARNING:
+        (?:[[:space:]]+CPU:[[:space:]]+[[:digit:]]+[[:space:]]+PID:[[:space:]]+[[:digit:]]+)?
+        [[:space:]]+at
+        (?:[[:space:]]+(?P<file>[^[:space:]:]+):(?P<line>[[:digit:]]+))?
+        [[:space:]]+(?P<location>.+?)
+        (?:\(\))?
+        $"
+    )
+    .unwrap();
+}
+
+/// A kernel warning, and the entries it was parsed from
+#[derive(Debug, Clone, PartialEq)]
+pub struct KernelWarning {
+    /// The source file the warning is in, e.g. "drivers/foo.c"
+    pub file: Option<String>,
+    /// The line within `file`
+    pub line: Option<u32>,
+    /// The function the warning is in
+    pub location: Option<Frame>,
+    /// The message given to `WARN()`, if any
+    pub message: Option<String>,
+    /// The facility of the "WARNING:" entry
+    pub facility: Option<LogFacility>,
+    /// The level of the "WARNING:" entry
+    pub level: Option<LogLevel>,
+    /// The time since boot of the first entry
+    pub timestamp_from_system_start: Option<Duration>,
+    /// The task that was running (CPU, PID, command, taint flags and kernel release)
+    pub task: Option<TaskInfo>,
+    /// The modules loaded at the time (without their taint flags)
+    pub modules: Vec<String>,
+    pub call_trace: Vec<Frame>,
+    /// Whether the warning's end marker was seen
+    pub complete: bool,
+    /// Every entry of the warning
+    pub entries: Vec<Entry>,
+}
+
+impl KernelWarning {
+    /// The warning's signature: its file:line (or function, when the kernel doesn't
+    /// print the file), and the functions of the top reliable frames of its call trace.
+    pub fn signature(&self) -> WarningSignature {
+        let mut signature = match (&self.file, self.line, &self.location) {
+            (Some(file), Some(line), _) => format!("{}:{}", file, line),
+            (_, _, Some(location)) => location.function.clone(),
+            _ => "unknown".to_owned(),
+        };
+
+        for frame in self
+            .call_trace
+            .iter()
+            .filter(|frame| frame.reliable)
+            .take(SIGNATURE_FRAMES)
+        {
+            signature.push(' ');
+            signature.push_str(&frame.function);
+        }
+
+        WarningSignature(signature)
+    }
+}
+
+/// Identifies a warning independently of when and where it happened (no timestamps,
+/// PIDs or addresses, and no offsets within functions, which differ across builds),
+/// e.g. "drivers/foo.c:123 foo_work process_one_work worker_thread"
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct WarningSignature(pub String);
+
+impl Display for WarningSignature {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Finds `KernelWarning`s among entries
+#[derive(Debug, Default)]
+pub struct WarningAnalyzer {
+    // the entries since "cut here", while waiting for the "WARNING:" line
+    cut_here: Option<Vec<Entry>>,
+    current: Option<(KernelWarning, TraceReport)>,
+}
+
+impl WarningAnalyzer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn start(&mut self, entry: &Entry, line: &str) {
+        let parts = match RE_WARNING.captures(line) {
+            Some(parts) => parts,
+            None => return,
+        };
+
+        let preceding = self.cut_here.take().unwrap_or_default();
+        let message: Vec<&str> = preceding
+            .iter()
+            .skip(1)
+            .map(|entry| entry.message.trim())
+            .collect();
+
+        let warning = KernelWarning {
+            file: parts.name("file").map(|file| file.as_str().to_owned()),
+            line: parts
+                .name("line")
+                .and_then(|line| line.as_str().parse().ok()),
+            location: Frame::parse(&parts["location"]),
+            message: match message.is_empty() {
+                true => None,
+                false => Some(message.join("\n")),
+            },
+            facility: entry.facility,
+            level: entry.level,
+            timestamp_from_system_start: preceding
+                .first()
+                .unwrap_or(entry)
+                .timestamp_from_system_start,
+            task: None,
+            modules: Vec::new(),
+            call_trace: Vec::new(),
+            complete: false,
+            entries: Vec::new(),
+        };
+        self.current = Some((warning, TraceReport::with_entries(preceding)));
+    }
+
+    fn close(&mut self) -> Option<KernelWarning> {
+        self.current.take().map(|(mut warning, report)| {
+            warning.task = report.task;
+            warning.modules = report.modules;
+            warning.call_trace = report.call_trace;
+            warning.complete = report.complete;
+            warning.entries = report.entries;
+            warning
+        })
+    }
+}
+
+impl Analyzer for WarningAnalyzer {
+    type Report = KernelWarning;
+
+    fn push(&mut self, entry: &Entry) -> Vec<KernelWarning> {
+        let line = entry.message.trim();
+        let is_cut_here = line.starts_with("------------[ cut here ]");
+        let is_warning = RE_WARNING.is_match(line);
+        let mut reports = Vec::new();
+
+        if let Some((_, report)) = &self.current {
+            if is_cut_here || is_warning || !report.continues_with(line) {
+                reports.extend(self.close());
+            }
+        }
+
+        if let Some((_, report)) = &mut self.current {
+            if report.add(line, entry) {
+                reports.extend(self.close());
+            }
+        } else if is_cut_here {
+            self.cut_here = Some(vec![entry.clone()]);
+        } else if is_warning {
+            self.start(entry, line);
+            if let Some((_, report)) = &mut self.current {
+                report.add(line, entry);
+            }
+        } else if let Some(preceding) = &mut self.cut_here {
+            // "kernel BUG at" follows "cut here" too, but that's a crash
+            if oops::header_kind(line).is_some() || preceding.len() > MAX_MESSAGE_LINES {
+                self.cut_here = None;
+            } else {
+                preceding.push(entry.clone());
+            }
+        }
+
+        reports
+    }
+
+    fn finish(&mut self) -> Vec<KernelWarning> {
+        self.cut_here = None;
+        self.close().into_iter().collect()
+    }
+}
+
+/**********************************************************************************/
+// Tests! Tests! Tests!
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis;
+    use crate::klogctl;
+    use std::collections::HashMap;
+
+    const WARNING: &str = r"<4>[   50.000000] ------------[ cut here ]------------
+<4>[   50.000001] foo: unexpected state 3
+<4>[   50.000002] WARNING: CPU: 3 PID: 12 at drivers/foo/foo.c:123 foo_work+0x12/0x40 [foo]
+<4>[   50.000003] Modules linked in: foo(O) bar
+<4>[   50.000004] CPU: 3 PID: 12 Comm: kworker/3:1 Tainted: G           O      6.1.0 #1
+<4>[   50.000005] Hardware name: QEMU Standard PC (i440FX + PIIX, 1996), BIOS 1.15.0-1 04/01/2014
+<4>[   50.000006] Workqueue: events foo_work [foo]
+<4>[   50.000007] RIP: 0010:foo_work+0x12/0x40 [foo]
+<4>[   50.000008] Call Trace:
+<4>[   50.000009]  <TASK>
+<4>[   50.000010]  ? __warn+0x7d/0xc0
+<4>[   50.000011]  process_one_work+0x1c7/0x380
+<4>[   50.000012]  worker_thread+0x4d/0x380
+<4>[   50.000013]  kthread+0xe9/0x110
+<4>[   50.000014]  ret_from_fork+0x22/0x30
+<4>[   50.000015]  </TASK>
+<4>[   50.000016] ---[ end trace 0000000000000000 ]---
+<6>[   51.000000] eth0: link up";
+
+    #[test]
+    fn test_warning() {
+        let entries = klogctl::entries_from_lines(WARNING).unwrap();
+        let warnings = analysis::analyze(WarningAnalyzer::new(), &entries);
+        assert_eq!(warnings.len(), 1);
+
+        let warning = &warnings[0];
+        assert_eq!(warning.file.as_deref(), Some("drivers/foo/foo.c"));
+        assert_eq!(warning.line, Some(123));
+        assert_eq!(
+            warning.location.as_ref().map(|l| l.to_string()),
+            Some("foo_work+0x12/0x40 [foo]".to_owned())
+        );
+        assert_eq!(warning.message.as_deref(), Some("foo: unexpected state 3"));
+        assert_eq!(warning.level, Some(LogLevel::Warning));
+        assert_eq!(warning.facility, Some(LogFacility::Kern));
+        assert_eq!(
+            warning.timestamp_from_system_start,
+            Some(Duration::from_secs(50))
+        );
+        assert_eq!(warning.task.as_ref().unwrap().comm, "kworker/3:1");
+        assert_eq!(warning.modules, vec!["foo", "bar"]);
+        assert_eq!(warning.call_trace.len(), 5);
+        assert!(warning.complete);
+        assert_eq!(warning.entries.len(), 17);
+        assert_eq!(
+            warning.signature().to_string(),
+            "drivers/foo/foo.c:123 process_one_work worker_thread kthread"
+        );
+    }
+
+    #[test]
+    fn test_signatures() {
+        // the same warning on another host, kernel build and boot
+        let elsewhere = WARNING
+            .replace("PID: 12", "PID: 4021")
+            .replace("+0x1c7/0x380", "+0x1d3/0x390")
+            .replace("[   50.", "[ 9050.");
+        let lines = format!(
+            "{}\n{}\n<4>[ 9100.000000] WARNING: at kernel/other.c:7 other_func+0x1/0x2()",
+            WARNING, elsewhere
+        );
+
+        let entries = klogctl::entries_from_lines(&lines).unwrap();
+        let mut counts: HashMap<WarningSignature, usize> = HashMap::new();
+        for warning in analysis::analyze(WarningAnalyzer::new(), &entries) {
+            *counts.entry(warning.signature()).or_default() += 1;
+        }
+
+        assert_eq!(counts.len(), 2);
+        assert_eq!(
+            counts[&WarningSignature(
+                "drivers/foo/foo.c:123 process_one_work worker_thread kthread".to_owned()
+            )],
+            2
+        );
+        assert_eq!(counts[&WarningSignature("kernel/other.c:7".to_owned())], 1);
+    }
+
+    #[test]
+    fn test_not_warnings() {
+        // BUG() prints "cut here" too
+        let entries = klogctl::entries_from_lines(
+            "<4>[    5.000000] ------------[ cut here ]------------\n\
+             <4>[    5.000001] kernel BUG at mm/slub.c:321!\n\
+             <4>[    5.000002] WARNING: possible circular locking dependency detected",
+        )
+        .unwrap();
+        assert!(analysis::analyze(WarningAnalyzer::new(), &entries).is_empty());
+    }
+}